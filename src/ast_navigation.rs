@@ -5,6 +5,7 @@
 
 use std::collections::HashMap;
 use std::fs;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
@@ -59,7 +60,7 @@ pub enum NavigationError {
 // ---------------------------------------------------------------------------
 
 /// A position in a source file (zero-indexed line and column).
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 #[cfg_attr(feature = "mcp-server", derive(schemars::JsonSchema))]
 pub struct Position {
     pub line: usize,
@@ -76,7 +77,7 @@ impl From<Point> for Position {
 }
 
 /// A contiguous range in a source file.
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 #[cfg_attr(feature = "mcp-server", derive(schemars::JsonSchema))]
 pub struct Span {
     pub start: Position,
@@ -370,8 +371,7 @@ pub fn run_query(
     language: &Language,
     query_str: &str,
 ) -> Result<Vec<QueryMatchResult>, NavigationError> {
-    let query = Query::new(language, query_str)
-        .map_err(|e| NavigationError::InvalidQuery(format!("{e}")))?;
+    let query = compile_query(language, query_str)?;
 
     let capture_names = query.capture_names();
     let mut cursor = QueryCursor::new();
@@ -382,6 +382,44 @@ pub fn run_query(
     Ok(results)
 }
 
+/// Compile a tree-sitter query for a language.
+///
+/// Invalid queries produce [`NavigationError::InvalidQuery`].
+pub fn compile_query(language: &Language, query_str: &str) -> Result<Query, NavigationError> {
+    Query::new(language, query_str).map_err(|e| NavigationError::InvalidQuery(format!("{e}")))
+}
+
+/// Find the byte ranges of the nodes in the subtree rooted at `node` that a query captures with
+/// the name `capture_name`.
+///
+/// Only complete matches are used, so predicates like `#eq?` are applied. A query that doesn't
+/// have the capture yields no ranges.
+pub fn query_capture_ranges(
+    node: Node,
+    text: &str,
+    language: &Language,
+    query_str: &str,
+    capture_name: &str,
+) -> Result<Vec<Range<usize>>, NavigationError> {
+    let query = compile_query(language, query_str)?;
+    let Some(capture_index) = query.capture_index_for_name(capture_name) else {
+        return Ok(Vec::new());
+    };
+    let mut cursor = QueryCursor::new();
+    let mut matches = cursor.matches(&query, node, text.as_bytes());
+    let mut ranges = Vec::new();
+
+    while let Some(m) = matches.next() {
+        ranges.extend(
+            m.captures
+                .iter()
+                .filter(|cap| cap.index == capture_index)
+                .map(|cap| cap.node.byte_range()),
+        );
+    }
+    Ok(ranges)
+}
+
 /// Collect query matches from the tree into `results`.
 ///
 /// Iterates all matches produced by `QueryCursor::matches` using the
@@ -582,6 +620,38 @@ pub fn get_definition(
     Ok((sym, full_text))
 }
 
+/// Find the tree-sitter node that defines a symbol by name.
+///
+/// This is the node whose text [`get_definition`] returns, which lets callers work with the
+/// definition's subtree rather than a copy of its text.
+///
+/// Returns [`NavigationError::SymbolNotFound`] if no symbol with the given name exists.
+pub fn get_definition_node<'tree>(
+    tree: &'tree Tree,
+    text: &str,
+    language: &Language,
+    language_name: &str,
+    symbol_name: &str,
+) -> Result<Node<'tree>, NavigationError> {
+    let symbols = list_symbols(tree, text, language, language_name);
+    let sym = symbols
+        .iter()
+        .find(|s| s.name == symbol_name)
+        .ok_or_else(|| NavigationError::SymbolNotFound(symbol_name.to_string()))?;
+
+    let point_start = Point {
+        row: sym.span.start.line,
+        column: sym.span.start.column,
+    };
+    let point_end = Point {
+        row: sym.span.end.line,
+        column: sym.span.end.column,
+    };
+    tree.root_node()
+        .descendant_for_point_range(point_start, point_end)
+        .ok_or_else(|| NavigationError::SymbolNotFound(symbol_name.to_string()))
+}
+
 /// List the child symbols of a parent symbol identified by name.
 ///
 /// This finds the parent symbol via [`list_symbols`], locates the corresponding tree-sitter
@@ -893,8 +963,8 @@ impl ParseCache {
 mod tests {
     use crate::ast_navigation::{
         MAX_INLINE_TEXT_LEN, NavigationDirection, NavigationError, ParseCache, get_children_of,
-        get_definition, get_node_at_position, get_scope, list_symbols, navigate, node_to_info,
-        run_query, scope_kinds_for_language, symbol_query_for_language,
+        get_definition, get_definition_node, get_node_at_position, get_scope, list_symbols,
+        navigate, node_to_info, run_query, scope_kinds_for_language, symbol_query_for_language,
     };
     use crate::parse::{self, GrammarConfig};
     use pretty_assertions::assert_eq as p_assert_eq;
@@ -1402,6 +1472,18 @@ mod tests {
         }
     }
 
+    #[test]
+    fn get_definition_node_matches_definition_text() {
+        let src = "fn other() {}\n\nfn target_func(x: i32) -> i32 {\n    x + 1\n}\n";
+        let (tree, lang) = parse_rust(src);
+        let node = get_definition_node(&tree, src, &lang, "rust", "target_func").unwrap();
+        let (_, full_text) = get_definition(&tree, src, &lang, "rust", "target_func").unwrap();
+
+        p_assert_eq!(node.kind(), "function_item");
+        p_assert_eq!(node.start_position().row, 2);
+        p_assert_eq!(node.utf8_text(src.as_bytes()).unwrap(), full_text);
+    }
+
     // -----------------------------------------------------------------------
     // get_children_of
     // -----------------------------------------------------------------------
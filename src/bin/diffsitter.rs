@@ -1,28 +1,41 @@
 use ::console::Term;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::CommandFactory;
 use clap::FromArgMatches;
 #[cfg(panic = "unwind")]
 use human_panic::setup_panic;
+use libdiffsitter::ast_navigation::{SymbolInfo, get_definition_node, list_symbols};
 use libdiffsitter::cli;
 use libdiffsitter::cli::Args;
 use libdiffsitter::config::APP_NAME;
 use libdiffsitter::config::Config;
 use libdiffsitter::console_utils;
 use libdiffsitter::diff;
+use libdiffsitter::diff::{DiffConfig, DiffEngine, DiffMode};
+use libdiffsitter::encoding::{self, TextFormat};
 use libdiffsitter::generate_ast_vector_data;
+use libdiffsitter::input_processing::{
+    DocumentRange, Entry, Granularity, TreeSitterProcessor, VectorData,
+};
+use libdiffsitter::merge::{self, MergeDocument};
 #[cfg(feature = "static-grammar-libs")]
 use libdiffsitter::parse::SUPPORTED_LANGUAGES;
 use libdiffsitter::parse::generate_language;
 use libdiffsitter::parse::lang_name_from_file_ext;
-use libdiffsitter::render::{DisplayData, DocumentDiffData, Renderer};
+use libdiffsitter::render::{DisplayData, DocumentDiffData, Renderer, Renderers};
+use libdiffsitter::stats::ChangeClass;
+use libdiffsitter::symbol_summary::summarize_symbols;
+use libdiffsitter::tree_diff;
 use log::{LevelFilter, debug, info, warn};
 use serde_json as json;
 use std::{
-    io,
-    path::Path,
-    process::{Child, Command},
+    borrow::Cow,
+    fs, io,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{self, Child, Command},
 };
+use tree_sitter::{Point, Tree};
 
 #[cfg(feature = "better-build-info")]
 use shadow_rs::shadow;
@@ -91,13 +104,28 @@ fn are_input_files_supported(args: &Args, config: &Config) -> bool {
     })
 }
 
+/// A document to diff, along with the entries that were generated from it.
+struct DiffDocument<'a> {
+    path: &'a Path,
+    text: &'a str,
+    entries: &'a [Entry<'a>],
+
+    /// How the text was stored in the file before it was decoded
+    format: TextFormat,
+
+    /// The syntax tree of the document, which documents that are diffed as plain text don't have
+    tree: Option<&'a Tree>,
+
+    /// The symbols defined in the diffed part of the document, if it has a syntax tree
+    symbols: Option<Vec<SymbolInfo>>,
+}
+
 /// Take the diff of two files
 fn run_diff(args: Args, config: Config) -> Result<()> {
     // Check whether we can get the renderer up front. This is more ergonomic than running the diff
     // and then informing the user their renderer choice is incorrect/that the config is invalid.
-    let render_config = config.formatting;
-    let render_param = args.renderer;
-    let renderer = render_config.get_renderer(render_param)?;
+    let render_param = args.renderer.clone();
+    let renderer = config.formatting.clone().get_renderer(render_param)?;
 
     let file_type = args.file_type.as_deref();
     let path_a = args.old.as_ref().unwrap();
@@ -107,22 +135,270 @@ fn run_diff(args: Args, config: Config) -> Result<()> {
     // AstVectorData structs. Because of that, we can't make a function that generates the ast
     // vectors in one shot.
 
-    let ast_data_a = generate_ast_vector_data(path_a.clone(), file_type, &config.grammar)?;
-    let ast_data_b = generate_ast_vector_data(path_b.clone(), file_type, &config.grammar)?;
-    let diff_vec_a = config.input_processing.process_vec_data(&ast_data_a);
-    let diff_vec_b = config.input_processing.process_vec_data(&ast_data_b);
+    let mut ast_data_a = generate_ast_vector_data(path_a.clone(), file_type, &config.grammar)?;
+    let mut ast_data_b = generate_ast_vector_data(path_b.clone(), file_type, &config.grammar)?;
+
+    // The tree diff only matches the nodes of each document's own syntax tree, so embedded code is
+    // diffed as part of the leaves that contain it
+    if args.mode.unwrap_or(config.diff.mode) == DiffMode::Tree {
+        ast_data_a.injections.clear();
+        ast_data_b.injections.clear();
+    }
+    let processor_a = config.input_processing_for(&ast_data_a.resolved_language);
+    let processor_b = config.input_processing_for(&ast_data_b.resolved_language);
+    check_verify(&args, &processor_a)?;
+    check_verify(&args, &processor_b)?;
+    for injection in ast_data_a.injections.iter().chain(&ast_data_b.injections) {
+        check_verify(&args, &config.input_processing_for(&injection.language))?;
+    }
+    let (diff_vec_a, symbols_a) = process_document(
+        &config,
+        &processor_a,
+        &ast_data_a,
+        args.old_symbol.as_deref().or(args.symbol.as_deref()),
+        args.old_range,
+    )?;
+    let (diff_vec_b, symbols_b) = process_document(
+        &config,
+        &processor_b,
+        &ast_data_b,
+        args.new_symbol.as_deref().or(args.symbol.as_deref()),
+        args.new_range,
+    )?;
+
+    diff_documents(
+        &args,
+        config.diff,
+        &renderer,
+        DiffDocument {
+            path: &ast_data_a.path,
+            text: &ast_data_a.text,
+            entries: &diff_vec_a,
+            format: ast_data_a.format,
+            tree: Some(&ast_data_a.tree),
+            symbols: Some(symbols_a),
+        },
+        DiffDocument {
+            path: &ast_data_b.path,
+            text: &ast_data_b.text,
+            entries: &diff_vec_b,
+            format: ast_data_b.format,
+            tree: Some(&ast_data_b.tree),
+            symbols: Some(symbols_b),
+        },
+    )
+}
+
+/// Generate the entries and list the symbols for a document
+///
+/// Both are limited to the definition of `symbol` if one is given, and to the lines in `range` if
+/// one is given. Embedded code is processed with the input processing options for its language.
+fn process_document<'a>(
+    config: &Config,
+    processor: &TreeSitterProcessor,
+    data: &'a VectorData,
+    symbol: Option<&str>,
+    range: Option<DocumentRange>,
+) -> Result<(Vec<Entry<'a>>, Vec<SymbolInfo>)> {
+    let language = data.tree.language();
+    processor.check_ignore_queries(&language, &data.resolved_language)?;
+    let mut symbols = list_symbols(&data.tree, &data.text, &language, &data.resolved_language);
+    let range = range.map(|range| range.to_points(&data.text));
+    let node = match (symbol, range) {
+        (Some(symbol), _) => get_definition_node(
+            &data.tree,
+            &data.text,
+            &language,
+            &data.resolved_language,
+            symbol,
+        )
+        .with_context(|| format!("Failed to find `{symbol}` in {}", data.path.display()))?,
+        // Only process the smallest node that covers the range, rather than the whole tree
+        (None, Some(range)) => data
+            .tree
+            .root_node()
+            .descendant_for_point_range(range.start, range.end)
+            .unwrap_or_else(|| data.tree.root_node()),
+        (None, None) => {
+            let entries = processor.process_vec_data_with(data, |language| {
+                Cow::Owned(config.input_processing_for(language))
+            });
+            return Ok((entries, symbols));
+        }
+    };
+    let (start, end) = (node.start_position(), node.end_position());
+    if symbol.is_some() {
+        symbols.retain(|info| {
+            let (info_start, info_end) = (&info.span.start, &info.span.end);
+            (start.row, start.column) <= (info_start.line, info_start.column)
+                && (info_end.line, info_end.column) <= (end.row, end.column)
+        });
+    }
+    let mut entries = processor.process_node_with(
+        node,
+        &data.text,
+        &data.resolved_language,
+        &data.injections,
+        |language| Cow::Owned(config.input_processing_for(language)),
+    );
+    if let Some(range) = range {
+        entries.retain(|entry| range.contains(entry));
+        symbols.retain(|info| {
+            let (start, end) = (&info.span.start, &info.span.end);
+            range.overlaps(
+                Point::new(start.line, start.column),
+                Point::new(end.line, end.column),
+            )
+        });
+    }
+    Ok((entries, symbols))
+}
+
+/// Check that the diff can be verified with the given input processing options.
+fn check_verify(args: &Args, processor: &TreeSitterProcessor) -> Result<()> {
+    // Normalized and masked entries don't match the text of the document, so the hunks can't
+    // reproduce it
+    if args.verify && !processor.normalizers.is_empty() {
+        anyhow::bail!("--verify can't be used with normalizers");
+    }
+    if args.verify && !processor.ignore_patterns.is_empty() {
+        anyhow::bail!("--verify can't be used with ignore patterns");
+    }
+    Ok(())
+}
+
+/// Take the diff of two files that can't be parsed by splitting them into plain text tokens
+fn run_text_diff(args: Args, config: Config) -> Result<()> {
+    let renderer = config.formatting.get_renderer(args.renderer.clone())?;
+    let (Some(path_a), Some(path_b)) = (&args.old, &args.new) else {
+        anyhow::bail!("You need two files to make a diff.");
+    };
+    if args.symbol.is_some() || args.old_symbol.is_some() || args.new_symbol.is_some() {
+        anyhow::bail!("Symbols can only be diffed in files with a supported language.");
+    }
+    check_verify(&args, &config.input_processing)?;
+    let fallback = config.grammar.fallback_encoding;
+    let (text_a, format_a) = encoding::read_file(path_a, fallback)
+        .with_context(|| format!("Failed to read {}", path_a.display()))?;
+    let (text_b, format_b) = encoding::read_file(path_b, fallback)
+        .with_context(|| format!("Failed to read {}", path_b.display()))?;
+    let mut diff_vec_a = config.input_processing.process_text(&text_a);
+    let mut diff_vec_b = config.input_processing.process_text(&text_b);
+    if let Some(range) = args.old_range.map(|range| range.to_points(&text_a)) {
+        diff_vec_a.retain(|entry| range.contains(entry));
+    }
+    if let Some(range) = args.new_range.map(|range| range.to_points(&text_b)) {
+        diff_vec_b.retain(|entry| range.contains(entry));
+    }
+
+    diff_documents(
+        &args,
+        config.diff,
+        &renderer,
+        DiffDocument {
+            path: path_a,
+            text: &text_a,
+            entries: &diff_vec_a,
+            format: format_a,
+            tree: None,
+            symbols: None,
+        },
+        DiffDocument {
+            path: path_b,
+            text: &text_b,
+            entries: &diff_vec_b,
+            format: format_b,
+            tree: None,
+            symbols: None,
+        },
+    )
+}
+
+/// Diff two documents and render the diff to stdout
+fn diff_documents(
+    args: &Args,
+    mut diff_config: DiffConfig,
+    renderer: &Renderers,
+    old: DiffDocument,
+    new: DiffDocument,
+) -> Result<()> {
+    if let Some(engine) = args.engine {
+        diff_config.engine = engine;
+    }
+
+    if let Some(mode) = args.mode {
+        diff_config.mode = mode;
+    }
+
+    if args.detect_moves {
+        diff_config.detect_moves = true;
+    }
+
+    if args.detect_renames {
+        diff_config.detect_renames = true;
+    }
 
-    let hunks = diff::compute_edit_script(&diff_vec_a, &diff_vec_b)?;
+    if let Some(max_cost) = args.max_cost {
+        diff_config.max_cost = Some(max_cost);
+    }
+
+    if let Some(max_time) = args.max_time {
+        diff_config.max_time = Some(max_time);
+    }
+
+    if args.pair_hunks {
+        diff_config.pair_hunks = true;
+    }
+
+    if args.similarity {
+        let similarity = diff::similarity(old.entries, new.entries, &diff_config);
+        println!("{similarity:.4}");
+        return Ok(());
+    }
+    let (hunks, strategy) = match (diff_config.mode, old.tree.zip(new.tree)) {
+        (DiffMode::Tree, Some((old_tree, new_tree))) => {
+            let hunks =
+                tree_diff::compute_tree_edit_script(old_tree, old.entries, new_tree, new.entries)?;
+            (hunks, None)
+        }
+        (mode, _) => {
+            if mode == DiffMode::Tree {
+                warn!("Plain text documents don't have a syntax tree, using a flat diff instead");
+            }
+            let output = diff::compute_diff(old.entries, new.entries, &diff_config)?;
+            (output.hunks, Some(output.strategy))
+        }
+    };
+
+    if args.verify {
+        diff::verify_hunks(old.text, old.entries, new.text, new.entries, &hunks)?;
+        info!("Verified that the hunks reproduce the new document");
+    }
+
+    if args.classify {
+        let class = ChangeClass::classify(old.text, new.text, &hunks);
+        println!("{class}");
+        process::exit(class.exit_code());
+    }
+    let symbols = old
+        .symbols
+        .as_deref()
+        .zip(new.symbols.as_deref())
+        .map(|(old_symbols, new_symbols)| summarize_symbols(old_symbols, new_symbols, &hunks));
     let params = DisplayData {
         hunks,
         old: DocumentDiffData {
-            filename: &ast_data_a.path.to_string_lossy(),
-            text: &ast_data_a.text,
+            filename: &old.path.to_string_lossy(),
+            text: old.text,
+            format: old.format,
         },
         new: DocumentDiffData {
-            filename: &ast_data_b.path.to_string_lossy(),
-            text: &ast_data_b.text,
+            filename: &new.path.to_string_lossy(),
+            text: new.text,
+            format: new.format,
         },
+        strategy,
+        symbols,
     };
     // Use a buffered terminal instead of a normal unbuffered terminal so we can amortize the cost
     // of printing. It doesn't really matter how frequently the terminal prints to stdout because
@@ -136,6 +412,82 @@ fn run_diff(args: Args, config: Config) -> Result<()> {
     Ok(())
 }
 
+/// Merge the changes that two files made to their common ancestor
+///
+/// The merged file is written to `output`, or to stdout if it isn't set. This returns whether the
+/// merge has conflicts.
+fn run_merge(
+    file_type: Option<&str>,
+    engine: Option<DiffEngine>,
+    config: &Config,
+    [base, ours, theirs]: [PathBuf; 3],
+    output: Option<&Path>,
+    path: Option<&Path>,
+) -> Result<bool> {
+    // The path of the merged file is only used to deduce the file type if it isn't set explicitly
+    let file_type = match (file_type, path.and_then(Path::extension)) {
+        (Some(file_type), _) => Some(file_type),
+        (None, Some(ext)) => Some(lang_name_from_file_ext(
+            &ext.to_string_lossy(),
+            &config.grammar,
+        )?),
+        (None, None) => None,
+    };
+    let ast_data_base = generate_ast_vector_data(base, file_type, &config.grammar)?;
+    let ast_data_ours = generate_ast_vector_data(ours, file_type, &config.grammar)?;
+    let ast_data_theirs = generate_ast_vector_data(theirs, file_type, &config.grammar)?;
+    let processor = merge_processor(config, &ast_data_base.resolved_language);
+    processor.check_ignore_queries(
+        &ast_data_base.tree.language(),
+        &ast_data_base.resolved_language,
+    )?;
+    let processor_for = |language: &str| Cow::Owned(merge_processor(config, language));
+    let vec_base = processor.process_vec_data_with(&ast_data_base, processor_for);
+    let vec_ours = processor.process_vec_data_with(&ast_data_ours, processor_for);
+    let vec_theirs = processor.process_vec_data_with(&ast_data_theirs, processor_for);
+    let merged = merge::merge(
+        MergeDocument {
+            text: &ast_data_base.text,
+            entries: &vec_base,
+        },
+        MergeDocument {
+            text: &ast_data_ours.text,
+            entries: &vec_ours,
+        },
+        MergeDocument {
+            text: &ast_data_theirs.text,
+            entries: &vec_theirs,
+        },
+        engine.unwrap_or(config.diff.engine),
+    );
+    // Git replaces our version of the file with the merged file, so it keeps our encoding, byte
+    // order mark and line endings
+    let merged_bytes = encoding::encode(&merged.text, &ast_data_ours.format)
+        .context("Failed to encode the merged file")?;
+
+    if let Some(output) = output {
+        fs::write(output, &merged_bytes)?;
+    } else {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&merged_bytes)?;
+        stdout.flush()?;
+    }
+
+    if merged.has_conflicts() {
+        warn!("The merge has {} conflicts", merged.conflicts);
+    }
+    Ok(merged.has_conflicts())
+}
+
+/// The input processing options to merge a document in a language with.
+fn merge_processor(config: &Config, language: &str) -> TreeSitterProcessor {
+    let mut processor = config.input_processing_for(language);
+    // Changes are merged leaf by leaf, so leaves are never split into smaller entries
+    processor.granularity = Granularity::Node;
+    processor.kind_granularity.clear();
+    processor
+}
+
 /// Serialize the default options struct to a json file and print that to stdout
 fn dump_default_config() -> Result<()> {
     let config = Config::default();
@@ -190,6 +542,14 @@ fn main() -> Result<()> {
     let command = Args::command();
     let matches = command.get_matches();
     let args = Args::from_arg_matches(&matches)?;
+    let log_level = if args.debug {
+        LevelFilter::Trace
+    } else {
+        LevelFilter::Off
+    };
+    pretty_env_logger::formatted_timed_builder()
+        .filter_level(log_level)
+        .init();
 
     // We parse the config as early as possible so users can get quick feedback if anything is off
     // with their config.
@@ -203,29 +563,44 @@ fn main() -> Result<()> {
             Command::GenCompletion { shell } => {
                 print_shell_completion(shell.into());
             }
+            Command::Merge {
+                base,
+                ours,
+                theirs,
+                output,
+                path,
+            } => {
+                let has_conflicts = run_merge(
+                    args.file_type.as_deref(),
+                    args.engine,
+                    &config,
+                    [base, ours, theirs],
+                    output.as_deref(),
+                    path.as_deref(),
+                )?;
+
+                // Git merge drivers signal conflicts with a non-zero exit status
+                if has_conflicts {
+                    process::exit(1);
+                }
+            }
         }
     } else {
-        let log_level = if args.debug {
-            LevelFilter::Trace
-        } else {
-            LevelFilter::Off
-        };
-        pretty_env_logger::formatted_timed_builder()
-            .filter_level(log_level)
-            .init();
         console_utils::set_term_colors(args.color_output);
+
         // First check if the input files can be parsed with tree-sitter.
         let files_supported = are_input_files_supported(&args, &config);
 
         // If the files are supported by our grammars, awesome. Otherwise fall back to a diff
-        // utility if one is specified.
+        // utility if one is specified, or diff the files as plain text.
         if files_supported {
             run_diff(args, config)?;
         } else if let Some(cmd) = config.fallback_cmd {
             info!("Input files are not supported but user has configured diff fallback");
             diff_fallback(&cmd, &args.old.unwrap(), &args.new.unwrap())?;
         } else {
-            anyhow::bail!("Unsupported file type with no fallback command specified.");
+            info!("Input files are not supported, diffing them as plain text");
+            run_text_diff(args, config)?;
         }
     }
     Ok(())
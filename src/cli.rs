@@ -1,4 +1,6 @@
 use crate::console_utils::ColorOutputPolicy;
+use crate::diff::{DiffEngine, DiffMode};
+use crate::input_processing::DocumentRange;
 use clap::Parser;
 use std::path::PathBuf;
 use strum_macros::EnumString;
@@ -67,6 +69,103 @@ pub struct Args {
     /// If no option is supplied then this will fall back to the default renderer.
     #[clap(short, long)]
     pub renderer: Option<String>,
+
+    /// Specify which diff engine to use. Valid values are: "myers", "patience", "histogram".
+    ///
+    /// This overrides the engine set in the config. "patience" and "histogram" tend to produce
+    /// more readable hunks for refactor-heavy changes because they avoid anchoring on common
+    /// tokens like braces.
+    #[clap(long)]
+    pub engine: Option<DiffEngine>,
+
+    /// Specify how the documents are compared. Valid values are: "flat", "tree".
+    ///
+    /// "flat" diffs the leaves of each syntax tree as a sequence. "tree" matches the nodes of both
+    /// syntax trees, which lets it report blocks of code that were moved. This overrides the mode
+    /// set in the config.
+    #[clap(long)]
+    pub mode: Option<DiffMode>,
+
+    /// Display runs of deleted text that show up unchanged as additions as moves.
+    ///
+    /// This only applies to the "flat" diff mode, the "tree" diff mode always detects moves.
+    #[clap(long)]
+    pub detect_moves: bool,
+
+    /// Display leaves that were renamed consistently throughout the file as a single rename.
+    ///
+    /// This only applies to the "flat" diff mode.
+    #[clap(long)]
+    pub detect_renames: bool,
+
+    /// The maximum cost of the edit script, in number of edits.
+    ///
+    /// If the diff would cost more than this, diffsitter falls back to diffing whole nodes, and
+    /// then to an anchored diff. This only applies to the "flat" diff mode.
+    #[clap(long)]
+    pub max_cost: Option<usize>,
+
+    /// The maximum time to spend searching for the edit script, in milliseconds.
+    ///
+    /// If the diff takes longer than this, diffsitter falls back to cheaper diffs in the same way
+    /// as with `--max-cost`. This only applies to the "flat" diff mode.
+    #[clap(long)]
+    pub max_time: Option<u64>,
+
+    /// Display each deleted hunk together with the added hunk that replaced it.
+    ///
+    /// This only applies to the "flat" diff mode.
+    #[clap(long)]
+    pub pair_hunks: bool,
+
+    /// Check that applying the computed hunks to the old file reproduces the new file, ignoring
+    /// whitespace.
+    ///
+    /// Whitespace isn't part of the diff, so changes that only touch whitespace (such as
+    /// indentation) aren't checked. This is a debugging aid for diffsitter itself. If the check
+    /// fails, diffsitter exits with an error instead of displaying the diff.
+    #[clap(long)]
+    pub verify: bool,
+
+    /// Print how similar the two files are, from 0 to 1, instead of the diff.
+    ///
+    /// The similarity is the fraction of entries that are unchanged between the two files,
+    /// weighted by node kind according to `similarity-weights` in the config.
+    #[clap(long)]
+    pub similarity: bool,
+
+    /// Print what the diff changed instead of the diff, and exit with a matching status code.
+    ///
+    /// The classes are `no_change` (0), `formatting` (1) if only whitespace or formatting
+    /// changed, `comments` (2) if only comments changed, and `code` (3) for anything else.
+    #[clap(long)]
+    pub classify: bool,
+
+    /// Only diff the definition of the symbol with this name in both files.
+    ///
+    /// The symbol can be a function, type, or anything else that the language's symbol query
+    /// picks up. Line numbers in the output are still relative to the start of each file.
+    #[clap(long)]
+    pub symbol: Option<String>,
+
+    /// The name of the symbol to diff in the old file, if it differs from `--symbol`.
+    #[clap(long)]
+    pub old_symbol: Option<String>,
+
+    /// The name of the symbol to diff in the new file, if it differs from `--symbol`.
+    #[clap(long)]
+    pub new_symbol: Option<String>,
+
+    /// Only diff the entries in this range of the old file, like `120:240` or `bytes:1200-2400`.
+    ///
+    /// Lines are numbered from 1 and both ends are inclusive. Byte offsets start at 0 and the end
+    /// is exclusive. Line numbers in the output are still relative to the start of the file.
+    #[clap(long)]
+    pub old_range: Option<DocumentRange>,
+
+    /// Only diff the entries in this range of the new file, like `118:260` or `bytes:1100-2600`.
+    #[clap(long)]
+    pub new_range: Option<DocumentRange>,
 }
 
 /// A wrapper struct for `clap_complete::Shell`.
@@ -102,7 +201,7 @@ impl From<ShellWrapper> for clap_complete::Shell {
 }
 
 /// Commands related to the configuration
-#[derive(Debug, Eq, PartialEq, Clone, Copy, Parser, EnumString)]
+#[derive(Debug, Eq, PartialEq, Clone, Parser, EnumString)]
 #[strum(serialize_all = "snake_case")]
 pub enum Command {
     /// List the languages that this program was compiled for
@@ -118,4 +217,31 @@ pub enum Command {
         /// This will print the shell completion script to stdout. bash, zsh, and fish are supported.
         shell: ShellWrapper,
     },
+
+    /// Merge the changes two files made to their common ancestor
+    ///
+    /// Changes are merged at the granularity of leaf nodes, and changes that touch the same leaves
+    /// are written out with conflict markers. This exits with a non-zero status if there are
+    /// conflicts, so it can be used as a git merge driver.
+    Merge {
+        /// The common ancestor of the two files
+        base: PathBuf,
+
+        /// Our version of the file
+        ours: PathBuf,
+
+        /// Their version of the file
+        theirs: PathBuf,
+
+        /// Write the merged file to this path instead of stdout
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+
+        /// The path of the merged file, which is used to deduce the file type.
+        ///
+        /// Git merge drivers receive temporary files without an extension, so this should be set
+        /// to `%P` when diffsitter is used as a merge driver.
+        #[clap(long)]
+        path: Option<PathBuf>,
+    },
 }
@@ -1,14 +1,19 @@
 //! Utilities and definitions for config handling
 
 use crate::{
-    cli::Args, figment_utils::JsonProvider, input_processing::TreeSitterProcessor,
-    parse::GrammarConfig, render::RenderConfig,
+    cli::Args,
+    diff::DiffConfig,
+    figment_utils::JsonProvider,
+    input_processing::{Granularity, ProcessorOverrides, TreeSitterProcessor},
+    parse::GrammarConfig,
+    render::RenderConfig,
 };
 use anyhow::Result;
 use figment::{
     self, Figment,
     providers::{Format, Serialized},
 };
+use log::warn;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
@@ -27,6 +32,12 @@ const CFG_FILE_NAME: &str = "config.json5";
 /// The app name used for configuration purposes.
 pub const APP_NAME: &str = "diffsitter";
 
+/// The deprecated key that `input-processing.granularity` replaced
+const SPLIT_GRAPHEMES_KEY: &str = "input-processing.split-graphemes";
+
+/// The key for the granularity of the input processing options
+const GRANULARITY_KEY: &str = "input-processing.granularity";
+
 /// The config struct for the application
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
 #[serde(rename_all = "kebab-case", default)]
@@ -47,6 +58,9 @@ pub struct Config {
     /// Options for processing tree-sitter input.
     pub input_processing: TreeSitterProcessor,
 
+    /// Options for computing the diff.
+    pub diff: DiffConfig,
+
     /// The program to invoke if the given files can not be parsed by the available tree-sitter
     /// parsers.
     ///
@@ -55,7 +69,21 @@ pub struct Config {
     /// ```sh
     /// ${FALLBACK_PROGRAM} ${OLD} ${NEW}
     /// ```
+    ///
+    /// If this isn't set, the files are split into words, punctuation and whitespace and diffed as
+    /// plain text.
     pub fallback_cmd: Option<String>,
+
+    /// Options for specific languages, keyed by the name of the language.
+    pub languages: HashMap<String, LanguageConfig>,
+}
+
+/// Options that only apply to one language.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct LanguageConfig {
+    /// Input processing options that override the global `input-processing` options.
+    pub input_processing: ProcessorOverrides,
 }
 
 /// The possible errors that can arise when attempting to read a config
@@ -91,7 +119,8 @@ impl Config {
         let fig: Figment = {
             let mut fig = figment::Figment::from(Serialized::defaults(Config::default()));
             if let Some(cfg_path) = get_config_path_from_args(path, no_config) {
-                fig = merge_fig_provider_from_ext(fig, &cfg_path)?;
+                let file_fig = merge_fig_provider_from_ext(Figment::new(), &cfg_path)?;
+                fig = fig.merge(deprecated_options(&file_fig)?).merge(file_fig);
             }
             fig
         };
@@ -111,6 +140,20 @@ impl Config {
     pub fn new_from_args(cli_args: &Args) -> Result<Self> {
         Self::try_from_file(cli_args.config.as_ref(), cli_args.no_config)
     }
+
+    /// The input processing options for a language.
+    ///
+    /// These are the global input processing options, merged with the overrides for the language
+    /// if it has any.
+    #[must_use]
+    pub fn input_processing_for(&self, language: &str) -> TreeSitterProcessor {
+        match self.languages.get(language) {
+            Some(language_config) => self
+                .input_processing
+                .with_overrides(&language_config.input_processing),
+            None => self.input_processing.clone(),
+        }
+    }
 }
 
 /// Select the file path for the diffsitter config.
@@ -136,6 +179,32 @@ fn get_config_path_from_args<P: AsRef<Path>>(
     default_config_file_path().ok()
 }
 
+/// Translate the options from a config file that were replaced by newer options.
+///
+/// `input-processing.split-graphemes` was replaced by `input-processing.granularity`, where `false`
+/// corresponds to `node` and `true` to `grapheme`. The translated options are merged before the
+/// config file, so the newer options take precedence if they're also set.
+fn deprecated_options(file_fig: &Figment) -> Result<Figment> {
+    let mut fig = Figment::new();
+
+    if let Ok(value) = file_fig.find_value(SPLIT_GRAPHEMES_KEY) {
+        let Some(split_graphemes) = value.to_bool() else {
+            anyhow::bail!("`{SPLIT_GRAPHEMES_KEY}` must be a boolean");
+        };
+        let granularity = if split_graphemes {
+            Granularity::Grapheme
+        } else {
+            Granularity::Node
+        };
+        warn!(
+            "`{SPLIT_GRAPHEMES_KEY}` is deprecated, set `{GRANULARITY_KEY}` to \"{}\" instead",
+            if split_graphemes { "grapheme" } else { "node" }
+        );
+        fig = fig.merge(Serialized::default(GRANULARITY_KEY, granularity));
+    }
+    Ok(fig)
+}
+
 /// Merge the given path's figment data.
 ///
 /// This is a helper function that constructs the correct figment provider for a given config path
@@ -195,8 +264,10 @@ fn default_config_file_path() -> Result<PathBuf> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::input_processing::Granularity;
     use anyhow::Context;
     use rstest::*;
+    use std::collections::HashSet;
     use std::env;
 
     // Tests the sample config that's in the docs
@@ -240,4 +311,57 @@ mod tests {
             })
             .unwrap();
     }
+
+    #[test]
+    fn test_language_overrides() {
+        let path = PathBuf::from("resources/test_configs/language_overrides.json");
+        let config = Config::try_from_file(Some(&path), false).unwrap();
+        let rust = config.input_processing_for("rust");
+        let python = config.input_processing_for("python");
+
+        assert_eq!(rust.granularity, Granularity::Node);
+        assert!(rust.strip_whitespace);
+        assert_eq!(
+            rust.exclude_kinds,
+            Some(HashSet::from(["line_comment".to_string()]))
+        );
+        assert_eq!(python, config.input_processing);
+        assert_eq!(python.granularity, Granularity::Word);
+        assert_eq!(python.exclude_kinds, None);
+    }
+
+    #[test]
+    fn test_deprecated_split_graphemes() {
+        let path = PathBuf::from("resources/test_configs/split_graphemes.json");
+        let config = Config::try_from_file(Some(&path), false).unwrap();
+        assert_eq!(config.input_processing.granularity, Granularity::Node);
+    }
+
+    #[rstest]
+    #[case(
+        r#"{"input-processing": {"split-graphemes": true}}"#,
+        Granularity::Grapheme
+    )]
+    #[case(
+        r#"{"input-processing": {"split-graphemes": false, "granularity": "word"}}"#,
+        Granularity::Word
+    )]
+    fn test_split_graphemes_granularity(#[case] contents: &str, #[case] expected: Granularity) {
+        use std::io::Write;
+
+        let mut file = tempfile::Builder::new().suffix(".json").tempfile().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        let config = Config::try_from_file(Some(&file.path()), false).unwrap();
+        assert_eq!(config.input_processing.granularity, expected);
+    }
+
+    #[test]
+    fn test_invalid_split_graphemes() {
+        use std::io::Write;
+
+        let mut file = tempfile::Builder::new().suffix(".json").tempfile().unwrap();
+        file.write_all(br#"{"input-processing": {"split-graphemes": "yes"}}"#)
+            .unwrap();
+        assert!(Config::try_from_file(Some(&file.path()), false).is_err());
+    }
 }
@@ -3,13 +3,20 @@
 
 use crate::input_processing::{EditType, Entry};
 use crate::neg_idx_vec::NegIdxVec;
+use crate::rename::{Rename, extract_renames};
 use anyhow::Result;
+use log::info;
 use logging_timer::time;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fmt::Debug;
+use std::hash::Hash;
 use std::iter::FromIterator;
 use std::ops::Range;
+use std::time::{Duration, Instant};
+use strum::{Display, EnumString};
 use thiserror::Error;
+use tree_sitter::Point;
 
 /// Find the length of the common prefix between the ranges specified for `a` and `b`.
 fn common_prefix_len<T: PartialEq>(
@@ -98,6 +105,26 @@ impl Line<'_> {
             entries: Vec::new(),
         }
     }
+
+    /// Get the range of columns an entry covers on this line, where `len` is the length of the
+    /// line.
+    ///
+    /// An entry that spans multiple lines covers the rest of the line it starts on, every line in
+    /// between and the start of the line it ends on.
+    #[must_use]
+    pub fn columns(&self, entry: &Entry, len: usize) -> Range<usize> {
+        let start = if entry.start_position.row == self.line_index {
+            entry.start_position.column.min(len)
+        } else {
+            0
+        };
+        let end = if entry.end_position.row == self.line_index {
+            entry.end_position.column.min(len)
+        } else {
+            len
+        };
+        start..end.max(start)
+    }
 }
 
 /// A grouping of consecutive edit lines for a document
@@ -209,6 +236,8 @@ impl<'a> Hunk<'a> {
     /// Entries can only be appended in ascending order (first to last). It is an error to append
     /// entries out of order. For example, you can't insert an entry on line 1 after inserting an
     /// entry on line 5.
+    ///
+    /// An entry that spans multiple lines is added to every line it covers.
     pub fn push_back(&mut self, entry: &'a Entry<'a>) -> Result<(), HunkInsertionError> {
         self.can_push_back(entry)?;
         let incoming_line_idx = entry.start_position().row;
@@ -222,8 +251,27 @@ impl<'a> Hunk<'a> {
         // vector so the unwrap won't fail
         let last_line = self.0.last_mut().unwrap();
         last_line.entries.push(entry);
+
+        for line_index in incoming_line_idx + 1..=entry.last_row() {
+            self.0.push(Line {
+                line_index,
+                entries: vec![entry],
+            });
+        }
         Ok(())
     }
+
+    /// Iterate over the entries in the hunk in document order.
+    ///
+    /// Entries that span multiple lines are only returned once.
+    pub fn entries(&self) -> impl Iterator<Item = &'a Entry<'a>> {
+        self.0.iter().flat_map(|line| {
+            line.entries
+                .iter()
+                .copied()
+                .filter(|entry| entry.start_position.row == line.line_index)
+        })
+    }
 }
 
 impl Default for Hunk<'_> {
@@ -274,12 +322,54 @@ impl<T: Debug + Clone + PartialEq + Serialize> DocumentType<T> {
 }
 
 /// A hunk with metadata about which document it came from.
-pub type RichHunk<'a> = DocumentType<Hunk<'a>>;
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum RichHunk<'a> {
+    /// A hunk with text that was deleted from the old document
+    Old(Hunk<'a>),
+
+    /// A hunk with text that was added to the new document
+    New(Hunk<'a>),
+
+    /// Text that was moved to a different location in the new document
+    Moved(MovedHunk<'a>),
+
+    /// A leaf that was consistently renamed throughout the document
+    Renamed(Rename<'a>),
+
+    /// A hunk from the old document along with the hunk from the new document that replaced it
+    Changed(ChangedHunk<'a>),
+}
+
+/// A region of text that was replaced.
+///
+/// This pairs up the deletions and the additions that took their place, so renderers can display
+/// them as a single change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChangedHunk<'a> {
+    /// The text that was deleted from the old document
+    pub old: Hunk<'a>,
+
+    /// The text that was added to the new document
+    pub new: Hunk<'a>,
+}
+
+/// A block of text that was moved between the old and new documents.
+///
+/// The text in a moved block can span lines that aren't adjacent (for example, a function with
+/// blank lines in its body), so each side is stored as a group of [hunks](Hunks).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MovedHunk<'a> {
+    /// Where the text was in the old document
+    pub old: Hunks<'a>,
+
+    /// Where the text is in the new document
+    pub new: Hunks<'a>,
+}
 
 /// The hunks that correspond to a document
 ///
 /// This type implements a helper builder function that can take
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Hunks<'a>(pub Vec<Hunk<'a>>);
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
@@ -317,6 +407,39 @@ impl<'a> RichHunksBuilder<'a> {
         self.hunks
     }
 
+    /// Add a moved block to the hunks.
+    ///
+    /// Any entries that are added after the moved block will start new hunks, so the hunks are
+    /// displayed in the same order they were added.
+    pub fn push_moved(&mut self, hunk: MovedHunk<'a>) {
+        self.hunks.0.push(RichHunk::Moved(hunk));
+        self.last_old = None;
+        self.last_new = None;
+    }
+
+    /// Add a rename to the hunks.
+    ///
+    /// Like [moved blocks](Self::push_moved), any entries added after the rename will start new
+    /// hunks.
+    pub fn push_rename(&mut self, rename: Rename<'a>) {
+        self.hunks.0.push(RichHunk::Renamed(rename));
+        self.last_old = None;
+        self.last_new = None;
+    }
+
+    /// Get the hunk at the given index.
+    ///
+    /// The builder only keeps track of the indices of hunks from the old or new document, so this
+    /// will panic if the index refers to anything other than an old or new hunk.
+    fn document_hunk_mut(&mut self, idx: usize) -> &mut Hunk<'a> {
+        match &mut self.hunks.0[idx] {
+            RichHunk::Old(hunk) | RichHunk::New(hunk) => hunk,
+            RichHunk::Moved(_) | RichHunk::Renamed(_) | RichHunk::Changed(_) => {
+                unreachable!("only old and new hunks are used for insertion")
+            }
+        }
+    }
+
     /// Initialize a new hunk struct for the incoming entry if necessary.
     ///
     /// This returns the hunk that the entry should be added to.
@@ -325,8 +448,8 @@ impl<'a> RichHunksBuilder<'a> {
         incoming_entry: &DocumentType<&'a Entry<'a>>,
     ) -> Result<usize, HunkInsertionError> {
         let (mut last_idx, new_hunk) = match incoming_entry {
-            DocumentType::Old(_) => (self.last_old, DocumentType::Old(Hunk::new())),
-            DocumentType::New(_) => (self.last_new, DocumentType::New(Hunk::new())),
+            DocumentType::Old(_) => (self.last_old, RichHunk::Old(Hunk::new())),
+            DocumentType::New(_) => (self.last_new, RichHunk::New(Hunk::new())),
         };
 
         match last_idx {
@@ -339,14 +462,13 @@ impl<'a> RichHunksBuilder<'a> {
             // If there is a reference to the last hunk that corresponds to the incoming entry's
             // document type, we check the line numbers and create a new hunk if necessary.
             Some(idx) => {
-                let last_hunk = self.hunks.0[idx].as_ref();
-                let last_line = last_hunk.last_line();
+                let last_line = self.document_hunk_mut(idx).last_line();
 
                 // If the hunk is populated, we only add to it if the incoming entry is on the same
                 // line as the last hunk for the same type. Otherwise we break and create a new
                 // one. If the hunk is empty, we can obviously add to it, so we do nothing.
                 if let Some(last_line) = last_line {
-                    let incoming_line = incoming_entry.as_ref().end_position.row;
+                    let incoming_line = incoming_entry.as_ref().start_position.row;
 
                     if incoming_line < last_line {
                         return Err(HunkInsertionError::PriorLine {
@@ -378,8 +500,7 @@ impl<'a> RichHunksBuilder<'a> {
     /// Add an entry to the hunks.
     pub fn push_back(&mut self, entry_wrapper: DocumentType<&'a Entry<'a>>) -> Result<()> {
         let insertion_idx = self.get_hunk_for_insertion(&entry_wrapper)?;
-        self.hunks.0[insertion_idx]
-            .as_mut()
+        self.document_hunk_mut(insertion_idx)
             .push_back(entry_wrapper.consume())?;
         Ok(())
     }
@@ -397,6 +518,22 @@ impl<'a> Hunks<'a> {
         Hunks(Vec::new())
     }
 
+    /// Returns the first line number of the first hunk
+    ///
+    /// This will return [None] if there are no hunks
+    #[must_use]
+    pub fn first_line(&self) -> Option<usize> {
+        self.0.first().and_then(Hunk::first_line)
+    }
+
+    /// Returns the last line number of the last hunk
+    ///
+    /// This will return [None] if there are no hunks
+    #[must_use]
+    pub fn last_line(&self) -> Option<usize> {
+        self.0.last().and_then(Hunk::last_line)
+    }
+
     pub fn push_back(&mut self, entry: &'a Entry<'a>) -> Result<()> {
         if let Some(hunk) = self.0.last_mut() {
             match hunk.can_push_back(entry) {
@@ -463,6 +600,36 @@ where
     fn diff(&self, a: &'elem [T], b: &'elem [T]) -> Self::Container;
 }
 
+/// A limit on how much work Myers' algorithm can do while computing a diff.
+///
+/// The cost of an edit script is the number of edits in it. The budget is shared between every
+/// region that a diff engine hands off to Myers' algorithm, so it bounds the cost of the whole
+/// diff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CostBudget {
+    /// There is no limit
+    Unlimited,
+
+    /// The number of edits that can still be made
+    Limited(usize),
+
+    /// The number of edits that can still be made, and the time by which the diff has to be done
+    Timed(usize, Instant),
+
+    /// Don't search for a shortest edit script at all, every region is deleted and added wholesale
+    Replace,
+}
+
+/// The error returned when a diff would exceed its [budget](CostBudget).
+#[derive(Debug, Error)]
+#[error("The diff exceeded its maximum cost or time")]
+struct BudgetExceeded;
+
+/// Whether a deadline was given and has passed.
+fn is_past(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() >= deadline)
+}
+
 #[derive(Eq, PartialEq, Copy, Clone, Debug, Default)]
 pub struct Myers {}
 
@@ -580,6 +747,83 @@ impl Myers {
         Myers::diff_impl(res, old, old_second_half, new, new_second_half, frontiers);
     }
 
+    /// Diff a region of the inputs without exceeding the given budget.
+    ///
+    /// Only the search for the first middle snake has to be bounded. The edit scripts for the
+    /// halves on either side of it can't cost more than the edit script for the whole region, so
+    /// they are computed as usual. The middle snake only gives us a bound that is accurate to
+    /// within one edit, so the actual cost is checked at the end.
+    ///
+    /// If the budget is exceeded, nothing is added to `res`.
+    fn diff_region<'elem, T: Eq + Debug + 'elem>(
+        res: &mut Vec<EditType<&'elem T>>,
+        old: &'elem [T],
+        mut old_range: Range<usize>,
+        new: &'elem [T],
+        mut new_range: Range<usize>,
+        frontiers: &mut MyersFrontiers,
+        budget: &mut CostBudget,
+    ) -> Result<(), BudgetExceeded> {
+        let (max_cost, deadline) = match *budget {
+            CostBudget::Unlimited => {
+                Myers::diff_impl(res, old, old_range, new, new_range, frontiers);
+                return Ok(());
+            }
+            CostBudget::Limited(max_cost) => (max_cost, None),
+            CostBudget::Timed(max_cost, deadline) => (max_cost, Some(deadline)),
+            CostBudget::Replace => {
+                res.extend(old_range.map(|i| EditType::Deletion(&old[i])));
+                res.extend(new_range.map(|i| EditType::Addition(&new[i])));
+                return Ok(());
+            }
+        };
+        let common_pref_len = common_prefix_len(old, old_range.clone(), new, new_range.clone());
+        old_range.start += common_pref_len;
+        new_range.start += common_pref_len;
+
+        let common_suf_len = common_suffix_len(old, old_range.clone(), new, new_range.clone());
+        old_range.end = old_range.start.max(old_range.end - common_suf_len);
+        new_range.end = new_range.start.max(new_range.end - common_suf_len);
+        let start_len = res.len();
+
+        if old_range.is_empty() || new_range.is_empty() {
+            Myers::diff_impl(res, old, old_range, new, new_range, frontiers);
+        } else {
+            // A middle snake found at step `d` means the edit script costs at least `2d - 1`
+            let max_d = i32::try_from(max_cost.div_ceil(2)).unwrap_or(i32::MAX);
+            let Some(Coordinates {
+                old: x_start,
+                new: y_start,
+            }) = Myers::middle_snake_bounded(
+                old,
+                old_range.clone(),
+                new,
+                new_range.clone(),
+                frontiers,
+                Some(max_d),
+                deadline,
+            )
+            else {
+                return Err(BudgetExceeded);
+            };
+            let (old_first_half, old_second_half) = split_range(&old_range, x_start);
+            let (new_first_half, new_second_half) = split_range(&new_range, y_start);
+            Myers::diff_impl(res, old, old_first_half, new, new_first_half, frontiers);
+            Myers::diff_impl(res, old, old_second_half, new, new_second_half, frontiers);
+        }
+        let cost = res.len() - start_len;
+
+        if cost > max_cost || is_past(deadline) {
+            res.truncate(start_len);
+            return Err(BudgetExceeded);
+        }
+        *budget = match deadline {
+            Some(deadline) => CostBudget::Timed(max_cost - cost, deadline),
+            None => CostBudget::Limited(max_cost - cost),
+        };
+        Ok(())
+    }
+
     /// Calculate the (x, y) coordinates of the midpoint of the optimal path.
     ///
     /// This implementation directly derives from "An O(ND) Difference Algorithm and Its Variations"
@@ -592,6 +836,24 @@ impl Myers {
         new_range: Range<usize>,
         frontiers: &mut MyersFrontiers,
     ) -> Coordinates<usize> {
+        Myers::middle_snake_bounded(old, old_range, new, new_range, frontiers, None, None)
+            .expect("the middle snake always exists if the search is unbounded")
+    }
+
+    /// Calculate the (x, y) coordinates of the midpoint of the optimal path, giving up after
+    /// `max_d` steps or once `deadline` has passed.
+    ///
+    /// This returns `None` if the middle snake wasn't found within `max_d` steps, which means the
+    /// optimal path has more than `2 * max_d - 1` edits, or if it wasn't found in time.
+    fn middle_snake_bounded<T: Eq>(
+        old: &[T],
+        old_range: Range<usize>,
+        new: &[T],
+        new_range: Range<usize>,
+        frontiers: &mut MyersFrontiers,
+        max_d: Option<i32>,
+        deadline: Option<Instant>,
+    ) -> Option<Coordinates<usize>> {
         let n = old_range.len() as i32;
         let m = new_range.len() as i32;
         let delta = n - m;
@@ -604,7 +866,13 @@ impl Myers {
         fwd_front[1] = 0;
         rev_front[1] = 0;
 
-        for d in 0..=midpoint {
+        let max_d = max_d.map_or(midpoint, |max_d| max_d.min(midpoint));
+
+        for d in 0..=max_d {
+            if is_past(deadline) {
+                return None;
+            }
+
             // Find the end of the furthest reaching forward d-path
             for k in (-d..=d).rev().step_by(2) {
                 // k == -d and k != d are just bounds checks to make sure we don't try to compare
@@ -671,10 +939,10 @@ impl Myers {
                     // the range of the length of the inputs, which are valid usize values. This property
                     // is also checked with assertions in debug releases.
                     if x + reverse_x >= n {
-                        return Coordinates {
+                        return Some(Coordinates {
                             old: old as usize,
                             new: new as usize,
-                        };
+                        });
                     }
                 }
             }
@@ -743,152 +1011,1511 @@ impl Myers {
                             new_range.end,
                         );
 
-                        return Coordinates {
+                        return Some(Coordinates {
                             old: old as usize,
                             new: new as usize,
-                        };
+                        });
                     }
                 }
             }
         }
-        unreachable!();
+        None
     }
 }
 
-impl<'a> TryFrom<Vec<EditType<&'a Entry<'a>>>> for RichHunks<'a> {
-    type Error = anyhow::Error;
+/// An implementation of the patience diff algorithm.
+///
+/// Patience diff only anchors on elements that occur exactly once in both inputs, which keeps it
+/// from lining up common filler tokens like braces and semicolons. Regions that don't have any
+/// unique elements are handed off to Myers' algorithm.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Default)]
+pub struct Patience {}
 
-    fn try_from(edits: Vec<EditType<&'a Entry<'a>>>) -> Result<Self, Self::Error> {
-        let mut builder = RichHunksBuilder::new();
-        for edit_wrapper in edits {
-            // TODO: deal with the clone here
-            let edit = match edit_wrapper {
-                EditType::Addition(edit) => DocumentType::New(edit),
-                EditType::Deletion(edit) => DocumentType::Old(edit),
-            };
-            builder.push_back(edit)?;
-        }
-        Ok(builder.build())
+impl<'elem, T> Engine<'elem, T> for Patience
+where
+    T: Eq + Hash + Debug + 'elem,
+{
+    type Container = Vec<EditType<&'elem T>>;
+
+    fn diff(&self, a: &'elem [T], b: &'elem [T]) -> Self::Container {
+        let mut res = Vec::with_capacity(a.len() + b.len());
+        let mut frontiers = MyersFrontiers::new(a.len(), b.len());
+        Patience::diff_impl(
+            &mut res,
+            a,
+            0..a.len(),
+            b,
+            0..b.len(),
+            &mut frontiers,
+            &mut CostBudget::Unlimited,
+        )
+        .expect("an unlimited budget can't be exceeded");
+        res
     }
 }
 
-/// Compute the hunks corresponding to the minimum edit path between two documents.
-///
-/// This will process the the AST vectors with the user-provided settings.
-///
-/// This will return two groups of [hunks](diff::Hunks) in a tuple of the form
-/// `(old_hunks, new_hunks)`.
-#[time("info", "diff::{}")]
-pub fn compute_edit_script<'a>(
-    old: &'a [Entry<'a>],
-    new: &'a [Entry<'a>],
-) -> Result<RichHunks<'a>> {
-    let myers = Myers::default();
-    let edit_script = myers.diff(old, new);
-    RichHunks::try_from(edit_script)
+/// Occurrence information for an element in the patience diff algorithm.
+#[derive(Debug, Default, Clone, Copy)]
+struct Occurrences {
+    /// The number of times the element shows up in the old input
+    old_count: usize,
+
+    /// The number of times the element shows up in the new input
+    new_count: usize,
+
+    /// The index of the last occurrence in the old input
+    old_idx: usize,
+
+    /// The index of the last occurrence in the new input
+    new_idx: usize,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use pretty_assertions::assert_eq as p_assert_eq;
-    use test_case::test_case;
+impl Patience {
+    /// The recursive helper for the patience diff algorithm.
+    ///
+    /// `frontiers` and `budget` are only used when falling back to Myers' algorithm, `frontiers` is
+    /// passed through so it only has to be allocated once.
+    fn diff_impl<'elem, T: Eq + Hash + Debug + 'elem>(
+        res: &mut Vec<EditType<&'elem T>>,
+        old: &'elem [T],
+        mut old_range: Range<usize>,
+        new: &'elem [T],
+        mut new_range: Range<usize>,
+        frontiers: &mut MyersFrontiers,
+        budget: &mut CostBudget,
+    ) -> Result<(), BudgetExceeded> {
+        let common_pref_len = common_prefix_len(old, old_range.clone(), new, new_range.clone());
+        old_range.start += common_pref_len;
+        new_range.start += common_pref_len;
 
-    /// A convenience function to invoke the a Myers diff
-    fn myers_diff<'a, T>(a: &'a [T], b: &'a [T]) -> Vec<EditType<&'a T>>
-    where
-        T: 'a + Eq + Debug,
-    {
-        let myers = Myers::default();
-        myers.diff(a, b)
-    }
+        let common_suf_len = common_suffix_len(old, old_range.clone(), new, new_range.clone());
+        old_range.end = old_range.start.max(old_range.end - common_suf_len);
+        new_range.end = new_range.start.max(new_range.end - common_suf_len);
 
-    #[test]
-    fn mid_snake_empty_input() {
-        let input_a = b"";
-        let input_b = b"";
+        let anchors = Patience::unique_anchors(old, old_range.clone(), new, new_range.clone());
 
-        let mut frontiers = MyersFrontiers::new(input_a.len(), input_b.len());
-        let mid_snake = Myers::middle_snake(
-            &input_a[..],
-            0..input_a.len(),
-            &input_b[..],
-            0..input_b.len(),
-            &mut frontiers,
-        );
-        let expected = Coordinates { old: 0, new: 0 };
-        p_assert_eq!(expected, mid_snake);
-    }
+        // Myers handles the trivial cases where either range is empty, as well as regions where
+        // there's nothing unique to anchor on.
+        if anchors.is_empty() {
+            return Myers::diff_region(res, old, old_range, new, new_range, frontiers, budget);
+        }
 
-    #[test]
-    fn mid_snake() {
-        let input_a = &b"ABCABBA"[..];
-        let input_b = &b"CBABAC"[..];
-        let mut frontiers = MyersFrontiers::new(input_a.len(), input_b.len());
-        let mid_snake = Myers::middle_snake(
-            input_a,
-            0..input_a.len(),
-            input_b,
-            0..input_b.len(),
-            &mut frontiers,
-        );
-        let expected = Coordinates { old: 4, new: 1 };
-        p_assert_eq!(expected, mid_snake);
+        // Every anchor is a match, so we only need to diff the gaps between them
+        let (mut old_start, mut new_start) = (old_range.start, new_range.start);
+
+        for anchor in anchors {
+            Patience::diff_impl(
+                res,
+                old,
+                old_start..anchor.old,
+                new,
+                new_start..anchor.new,
+                frontiers,
+                budget,
+            )?;
+            old_start = anchor.old + 1;
+            new_start = anchor.new + 1;
+        }
+        Patience::diff_impl(
+            res,
+            old,
+            old_start..old_range.end,
+            new,
+            new_start..new_range.end,
+            frontiers,
+            budget,
+        )
     }
 
-    #[test]
-    fn myers_diff_empty_inputs() {
-        let input_a: Vec<i32> = vec![];
-        let input_b: Vec<i32> = vec![];
-        let edit_script = myers_diff(&input_a, &input_b);
-        assert!(edit_script.is_empty());
-    }
+    /// Find the longest sequence of elements that are unique in both ranges and appear in the same
+    /// order in both ranges.
+    ///
+    /// The returned coordinates are sorted in ascending order.
+    fn unique_anchors<T: Eq + Hash>(
+        old: &[T],
+        old_range: Range<usize>,
+        new: &[T],
+        new_range: Range<usize>,
+    ) -> Vec<Coordinates<usize>> {
+        let mut occurrences: HashMap<&T, Occurrences> = HashMap::new();
 
-    #[test]
-    fn myers_diff_no_diff() {
-        let input_a: Vec<i32> = vec![0; 4];
-        let input_b: Vec<i32> = vec![0; 4];
-        let edit_script = myers_diff(&input_a, &input_b);
-        assert!(edit_script.is_empty());
-    }
+        for i in old_range {
+            let entry = occurrences.entry(&old[i]).or_default();
+            entry.old_count += 1;
+            entry.old_idx = i;
+        }
 
-    #[test]
-    fn myers_diff_one_addition() {
-        let input_a: Vec<i32> = Vec::new();
-        let input_b: Vec<i32> = vec![0];
-        let expected = vec![EditType::Addition(&input_b[0])];
-        let edit_script = myers_diff(&input_a, &input_b);
-        p_assert_eq!(expected, edit_script);
+        for i in new_range {
+            // Elements that aren't in the old range can never be anchors
+            if let Some(entry) = occurrences.get_mut(&new[i]) {
+                entry.new_count += 1;
+                entry.new_idx = i;
+            }
+        }
+
+        let mut candidates: Vec<Coordinates<usize>> = occurrences
+            .into_values()
+            .filter(|x| x.old_count == 1 && x.new_count == 1)
+            .map(|x| Coordinates {
+                old: x.old_idx,
+                new: x.new_idx,
+            })
+            .collect();
+        candidates.sort_unstable_by_key(|x| x.old);
+
+        let new_indices: Vec<usize> = candidates.iter().map(|x| x.new).collect();
+        longest_increasing_subsequence(&new_indices)
+            .into_iter()
+            .map(|i| candidates[i])
+            .collect()
     }
+}
 
-    #[test]
-    fn myers_diff_one_deletion() {
-        let input_a: Vec<i32> = vec![0];
-        let input_b: Vec<i32> = Vec::new();
-        let expected = vec![EditType::Deletion(&input_a[0])];
-        let edit_script = myers_diff(&input_a, &input_b);
-        p_assert_eq!(expected, edit_script);
+/// Find the longest strictly increasing subsequence of `values`.
+///
+/// This returns the indices of the elements in the subsequence, in ascending order.
+pub(crate) fn longest_increasing_subsequence(values: &[usize]) -> Vec<usize> {
+    // Patience sorting: `piles` holds the index of the element at the top of each pile and
+    // `back_refs` points each element at the top of the previous pile at the time it was placed.
+    let mut piles: Vec<usize> = Vec::new();
+    let mut back_refs: Vec<Option<usize>> = Vec::with_capacity(values.len());
+
+    for (i, value) in values.iter().enumerate() {
+        let pile = piles.partition_point(|&top| values[top] < *value);
+        back_refs.push(pile.checked_sub(1).map(|prev| piles[prev]));
+
+        if pile == piles.len() {
+            piles.push(i);
+        } else {
+            piles[pile] = i;
+        }
     }
 
-    #[test]
-    fn myers_diff_single_substitution() {
-        let myers = Myers::default();
-        let input_a = [1];
-        let input_b = [2];
-        let edit_script = myers.diff(&input_a[..], &input_b[..]);
-        let expected = vec![
-            EditType::Addition(&input_b[0]),
-            EditType::Deletion(&input_a[0]),
-        ];
-        p_assert_eq!(expected, edit_script);
+    let mut subsequence = Vec::with_capacity(piles.len());
+    let mut current = piles.last().copied();
+
+    while let Some(i) = current {
+        subsequence.push(i);
+        current = back_refs[i];
     }
+    subsequence.reverse();
+    subsequence
+}
 
-    #[test]
-    fn myers_diff_single_substitution_with_common_elements() {
-        let myers = Myers::default();
-        let input_a = [0, 0, 0];
-        let input_b = [0, 1, 0];
-        let edit_script = myers.diff(&input_a[..], &input_b[..]);
+/// The maximum number of times an element can occur in the old input and still be considered as
+/// an anchor by the histogram diff algorithm.
+///
+/// This mirrors the limit that git uses, it keeps the histogram engine from degrading on inputs
+/// with a lot of repeated elements.
+const HISTOGRAM_MAX_CHAIN_LEN: usize = 64;
+
+/// An implementation of the histogram diff algorithm.
+///
+/// This is an extension of patience diff that anchors on the longest common region built around
+/// the element with the fewest occurrences, so it still finds good anchors when nothing is unique.
+/// Regions without any suitable anchors are handed off to Myers' algorithm.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Default)]
+pub struct Histogram {}
+
+impl<'elem, T> Engine<'elem, T> for Histogram
+where
+    T: Eq + Hash + Debug + 'elem,
+{
+    type Container = Vec<EditType<&'elem T>>;
+
+    fn diff(&self, a: &'elem [T], b: &'elem [T]) -> Self::Container {
+        let mut res = Vec::with_capacity(a.len() + b.len());
+        let mut frontiers = MyersFrontiers::new(a.len(), b.len());
+        Histogram::diff_impl(
+            &mut res,
+            a,
+            0..a.len(),
+            b,
+            0..b.len(),
+            &mut frontiers,
+            &mut CostBudget::Unlimited,
+        )
+        .expect("an unlimited budget can't be exceeded");
+        res
+    }
+}
+
+/// A region that is common to both inputs, used as an anchor by the histogram diff algorithm.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct CommonRegion {
+    /// The start of the region in the old input
+    old: usize,
+
+    /// The start of the region in the new input
+    new: usize,
+
+    /// The number of elements in the region
+    len: usize,
+
+    /// The number of times the anchor element occurs in the old input
+    occurrences: usize,
+}
+
+impl Histogram {
+    /// The recursive helper for the histogram diff algorithm.
+    fn diff_impl<'elem, T: Eq + Hash + Debug + 'elem>(
+        res: &mut Vec<EditType<&'elem T>>,
+        old: &'elem [T],
+        mut old_range: Range<usize>,
+        new: &'elem [T],
+        mut new_range: Range<usize>,
+        frontiers: &mut MyersFrontiers,
+        budget: &mut CostBudget,
+    ) -> Result<(), BudgetExceeded> {
+        let common_pref_len = common_prefix_len(old, old_range.clone(), new, new_range.clone());
+        old_range.start += common_pref_len;
+        new_range.start += common_pref_len;
+
+        let common_suf_len = common_suffix_len(old, old_range.clone(), new, new_range.clone());
+        old_range.end = old_range.start.max(old_range.end - common_suf_len);
+        new_range.end = new_range.start.max(new_range.end - common_suf_len);
+
+        let Some(region) =
+            Histogram::find_anchor_region(old, old_range.clone(), new, new_range.clone())
+        else {
+            return Myers::diff_region(res, old, old_range, new, new_range, frontiers, budget);
+        };
+
+        Histogram::diff_impl(
+            res,
+            old,
+            old_range.start..region.old,
+            new,
+            new_range.start..region.new,
+            frontiers,
+            budget,
+        )?;
+        Histogram::diff_impl(
+            res,
+            old,
+            region.old + region.len..old_range.end,
+            new,
+            region.new + region.len..new_range.end,
+            frontiers,
+            budget,
+        )
+    }
+
+    /// Find the common region to split the inputs on.
+    ///
+    /// We prefer regions anchored on elements that occur less frequently in the old input, and
+    /// break ties with the length of the region.
+    fn find_anchor_region<T: Eq + Hash>(
+        old: &[T],
+        old_range: Range<usize>,
+        new: &[T],
+        new_range: Range<usize>,
+    ) -> Option<CommonRegion> {
+        let mut positions: HashMap<&T, Vec<usize>> = HashMap::new();
+
+        for i in old_range.clone() {
+            positions.entry(&old[i]).or_default().push(i);
+        }
+        let mut best: Option<CommonRegion> = None;
+
+        for new_idx in new_range.clone() {
+            let Some(old_positions) = positions.get(&new[new_idx]) else {
+                continue;
+            };
+            let occurrences = old_positions.len();
+
+            if occurrences > HISTOGRAM_MAX_CHAIN_LEN
+                || best.is_some_and(|x| occurrences > x.occurrences)
+            {
+                continue;
+            }
+
+            for &old_idx in old_positions {
+                // Extend the match backwards, then forwards
+                let mut offset = 0;
+
+                while old_idx - offset > old_range.start
+                    && new_idx - offset > new_range.start
+                    && old[old_idx - offset - 1] == new[new_idx - offset - 1]
+                {
+                    offset += 1;
+                }
+                let len = offset
+                    + common_prefix_len(old, old_idx..old_range.end, new, new_idx..new_range.end);
+                let region = CommonRegion {
+                    old: old_idx - offset,
+                    new: new_idx - offset,
+                    len,
+                    occurrences,
+                };
+                let is_better = best.is_none_or(|x| {
+                    region.occurrences < x.occurrences
+                        || (region.occurrences == x.occurrences && region.len > x.len)
+                });
+
+                if is_better {
+                    best = Some(region);
+                }
+            }
+        }
+        best
+    }
+}
+
+/// The diff engines that can be used to compute an edit script.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, Display, EnumString,
+)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum DiffEngine {
+    /// Myers' algorithm, which finds a minimal edit script
+    #[default]
+    Myers,
+
+    /// Patience diff, which only anchors on elements that are unique in both documents
+    Patience,
+
+    /// Histogram diff, which anchors on the least frequent elements in the old document
+    Histogram,
+}
+
+impl<'elem, T> Engine<'elem, T> for DiffEngine
+where
+    T: Eq + Hash + Debug + 'elem,
+{
+    type Container = Vec<EditType<&'elem T>>;
+
+    fn diff(&self, a: &'elem [T], b: &'elem [T]) -> Self::Container {
+        match self {
+            DiffEngine::Myers => Myers::default().diff(a, b),
+            DiffEngine::Patience => Patience::default().diff(a, b),
+            DiffEngine::Histogram => Histogram::default().diff(a, b),
+        }
+    }
+}
+
+impl DiffEngine {
+    /// Compute the edit script with this engine without exceeding the given budget.
+    fn diff_with_budget<'elem, T: Eq + Hash + Debug + 'elem>(
+        self,
+        a: &'elem [T],
+        b: &'elem [T],
+        mut budget: CostBudget,
+    ) -> Result<Vec<EditType<&'elem T>>, BudgetExceeded> {
+        let mut res = Vec::with_capacity(a.len() + b.len());
+        let mut frontiers = MyersFrontiers::new(a.len(), b.len());
+        let (old_range, new_range) = (0..a.len(), 0..b.len());
+        let budget = &mut budget;
+
+        match self {
+            DiffEngine::Myers => {
+                Myers::diff_region(&mut res, a, old_range, b, new_range, &mut frontiers, budget)?;
+            }
+            DiffEngine::Patience => {
+                Patience::diff_impl(&mut res, a, old_range, b, new_range, &mut frontiers, budget)?;
+            }
+            DiffEngine::Histogram => {
+                Histogram::diff_impl(&mut res, a, old_range, b, new_range, &mut frontiers, budget)?;
+            }
+        }
+        Ok(res)
+    }
+}
+
+/// How the edit script for a flat diff was computed.
+///
+/// If the diff is allowed to cost as much and take as long as it needs to, this is always
+/// [`DiffStrategy::Exact`]. Otherwise we fall back to cheaper strategies until one of them fits in
+/// the maximum cost and time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Display)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum DiffStrategy {
+    /// The configured engine diffed every entry
+    Exact,
+
+    /// The configured engine diffed whole nodes instead of individual entries
+    ///
+    /// This is much cheaper when entries are split into graphemes, but changes are only as precise
+    /// as the nodes.
+    Coarse,
+
+    /// The nodes were diffed by anchoring on common nodes, and the regions between anchors were
+    /// replaced wholesale
+    Anchored,
+}
+
+/// The entries that came from the same tree-sitter node.
+///
+/// Two groups are equal if their nodes have the same kind and text, which makes them a coarser
+/// unit to diff than the entries.
+#[derive(Debug)]
+pub(crate) struct NodeEntries<'a> {
+    pub entries: &'a [Entry<'a>],
+    pub kind_id: u16,
+    pub text: String,
+}
+
+impl PartialEq for NodeEntries<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind_id == other.kind_id && self.text == other.text
+    }
+}
+
+impl Eq for NodeEntries<'_> {}
+
+impl Hash for NodeEntries<'_> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.kind_id.hash(state);
+        self.text.hash(state);
+    }
+}
+
+/// Group the entries of a document by the node they came from.
+///
+/// The entries that came from a node are always contiguous.
+pub(crate) fn group_by_node<'a>(entries: &'a [Entry<'a>]) -> Vec<NodeEntries<'a>> {
+    let mut groups = Vec::new();
+    let mut start = 0;
+
+    while start < entries.len() {
+        let id = entries[start].leaf_id();
+        let len = entries[start..]
+            .iter()
+            .take_while(|entry| entry.leaf_id() == id)
+            .count();
+        let group = &entries[start..start + len];
+        groups.push(NodeEntries {
+            entries: group,
+            kind_id: group[0].kind_id,
+            text: group.iter().map(|entry| entry.text.as_ref()).collect(),
+        });
+        start += len;
+    }
+    groups
+}
+
+/// Convert an edit script over groups of entries to an edit script over the entries.
+fn expand_node_edits<'a>(edits: Vec<EditType<&NodeEntries<'a>>>) -> Vec<EditType<&'a Entry<'a>>> {
+    edits
+        .into_iter()
+        .flat_map(|edit| match edit {
+            EditType::Addition(group) => group
+                .entries
+                .iter()
+                .map(EditType::Addition)
+                .collect::<Vec<_>>(),
+            EditType::Deletion(group) => group.entries.iter().map(EditType::Deletion).collect(),
+        })
+        .collect()
+}
+
+/// Compute an edit script that costs at most `max_cost` and is done within `max_time`, falling back
+/// to cheaper strategies if the configured engine can't produce one.
+///
+/// The time is shared between the entry and node diffs, the anchored diff isn't timed.
+fn bounded_edit_script<'a>(
+    old: &'a [Entry<'a>],
+    new: &'a [Entry<'a>],
+    engine: DiffEngine,
+    max_cost: Option<usize>,
+    max_time: Option<Duration>,
+) -> (Vec<EditType<&'a Entry<'a>>>, DiffStrategy) {
+    let max_cost = max_cost.unwrap_or(usize::MAX);
+    let budget = match max_time {
+        Some(max_time) => CostBudget::Timed(max_cost, Instant::now() + max_time),
+        None => CostBudget::Limited(max_cost),
+    };
+
+    if let Ok(edits) = engine.diff_with_budget(old, new, budget) {
+        return (edits, DiffStrategy::Exact);
+    }
+    info!("Diffing entries exceeded the maximum cost or time, diffing nodes instead");
+    let old_nodes = group_by_node(old);
+    let new_nodes = group_by_node(new);
+
+    if let Ok(edits) = engine.diff_with_budget(&old_nodes, &new_nodes, budget) {
+        return (expand_node_edits(edits), DiffStrategy::Coarse);
+    }
+    info!("Diffing nodes exceeded the maximum cost or time, using an anchored diff");
+    let edits = DiffEngine::Histogram
+        .diff_with_budget(&old_nodes, &new_nodes, CostBudget::Replace)
+        .expect("replacing regions wholesale doesn't have a cost limit");
+    (expand_node_edits(edits), DiffStrategy::Anchored)
+}
+
+/// The ways two documents can be compared.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, Display, EnumString,
+)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum DiffMode {
+    /// Diff the flattened leaves of each document
+    #[default]
+    Flat,
+
+    /// Match the subtrees of each document, which can detect nodes that were moved
+    ///
+    /// See [`crate::tree_diff`] for details.
+    Tree,
+}
+
+/// Options for computing the diff between two documents.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct DiffConfig {
+    /// The diff engine to use when computing the edit script.
+    ///
+    /// This only applies to the flat diff mode.
+    pub engine: DiffEngine,
+
+    /// Whether to diff the flattened leaves or the trees of each document.
+    pub mode: DiffMode,
+
+    /// Whether to look for runs of deleted text that show up unchanged as additions, and display
+    /// them as moves.
+    ///
+    /// This only applies to the flat diff mode, the tree diff mode always detects moves.
+    pub detect_moves: bool,
+
+    /// The minimum number of characters in a run of text for it to be considered a move.
+    ///
+    /// Short runs like a lone brace or keyword often match by coincidence, so they're left as
+    /// regular additions and deletions.
+    pub min_move_len: usize,
+
+    /// Whether to report leaves that were consistently renamed throughout the document as a single
+    /// rename instead of separate substitutions.
+    ///
+    /// This only applies to the flat diff mode.
+    pub detect_renames: bool,
+
+    /// The maximum cost of the edit script, in number of edits.
+    ///
+    /// Diffing two large documents that are very different can take a long time. If the edit
+    /// script would cost more than this, the diff falls back to diffing whole nodes, and then to an
+    /// anchored diff that doesn't search for a minimal edit script. There is no limit by default.
+    ///
+    /// This only applies to the flat diff mode.
+    pub max_cost: Option<usize>,
+
+    /// The maximum time to spend searching for an edit script, in milliseconds.
+    ///
+    /// This falls back to cheaper diffs in the same way as `max_cost`. The time is shared between
+    /// diffing entries and diffing whole nodes, the anchored diff isn't limited. There is no limit
+    /// by default.
+    ///
+    /// This only applies to the flat diff mode.
+    pub max_time: Option<u64>,
+
+    /// Whether to pair each deleted hunk with the added hunk that replaced it, so they can be
+    /// displayed as a single change.
+    ///
+    /// This only applies to the flat diff mode.
+    pub pair_hunks: bool,
+
+    /// The weight of each node kind when computing the similarity of two documents.
+    ///
+    /// Node kinds that aren't in the map have a weight of 1. A weight of 0 ignores a node kind,
+    /// for example to leave comments out of the similarity.
+    pub similarity_weights: HashMap<String, u32>,
+}
+
+impl Default for DiffConfig {
+    fn default() -> Self {
+        Self {
+            engine: DiffEngine::default(),
+            mode: DiffMode::default(),
+            detect_moves: false,
+            min_move_len: 20,
+            detect_renames: false,
+            max_cost: None,
+            max_time: None,
+            pair_hunks: false,
+            similarity_weights: HashMap::new(),
+        }
+    }
+}
+
+impl<'a> TryFrom<Vec<EditType<&'a Entry<'a>>>> for RichHunks<'a> {
+    type Error = anyhow::Error;
+
+    fn try_from(edits: Vec<EditType<&'a Entry<'a>>>) -> Result<Self, Self::Error> {
+        let mut builder = RichHunksBuilder::new();
+        for edit_wrapper in edits {
+            // TODO: deal with the clone here
+            let edit = match edit_wrapper {
+                EditType::Addition(edit) => DocumentType::New(edit),
+                EditType::Deletion(edit) => DocumentType::Old(edit),
+            };
+            builder.push_back(edit)?;
+        }
+        Ok(builder.build())
+    }
+}
+
+/// The result of a flat diff between two documents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiffOutput<'a> {
+    /// The hunks that make up the diff
+    pub hunks: RichHunks<'a>,
+
+    /// How the edit script was computed
+    pub strategy: DiffStrategy,
+}
+
+/// Compute the hunks corresponding to the minimum edit path between two documents.
+///
+/// This will process the the AST vectors with the user-provided settings.
+///
+/// This will return two groups of [hunks](diff::Hunks) in a tuple of the form
+/// `(old_hunks, new_hunks)`.
+pub fn compute_edit_script<'a>(
+    old: &'a [Entry<'a>],
+    new: &'a [Entry<'a>],
+    config: &DiffConfig,
+) -> Result<RichHunks<'a>> {
+    compute_diff(old, new, config).map(|output| output.hunks)
+}
+
+/// Compute the diff between two documents, along with the strategy that was used to compute it.
+///
+/// This is the same as [`compute_edit_script`], the strategy is only interesting if the config
+/// sets a maximum cost or time.
+#[time("info", "diff::{}")]
+pub fn compute_diff<'a>(
+    old: &'a [Entry<'a>],
+    new: &'a [Entry<'a>],
+    config: &DiffConfig,
+) -> Result<DiffOutput<'a>> {
+    let (edit_script, strategy) = edit_script(old, new, config);
+    let hunks = hunks_from_edit_script(old, new, edit_script, config)?;
+    Ok(DiffOutput { hunks, strategy })
+}
+
+/// Turn an edit script into hunks, applying the post-processing that's enabled in the config.
+pub(crate) fn hunks_from_edit_script<'a>(
+    old: &'a [Entry<'a>],
+    new: &'a [Entry<'a>],
+    mut edit_script: Vec<EditType<&'a Entry<'a>>>,
+    config: &DiffConfig,
+) -> Result<RichHunks<'a>> {
+    // Renames and moves remove edits, so this has to be computed from the original edit script
+    let alignment = config
+        .pair_hunks
+        .then(|| Alignment::new(old, new, &edit_script));
+    let renames = if config.detect_renames {
+        extract_renames(old, new, &mut edit_script)
+    } else {
+        Vec::new()
+    };
+    let mut hunks = RichHunks::try_from(edit_script)?;
+
+    if config.detect_moves {
+        hunks = detect_moves(hunks, old, new, config.min_move_len)?;
+    }
+
+    if let Some(alignment) = alignment {
+        hunks = pair_hunks(hunks, old, new, &alignment);
+    }
+    // Renames apply to the whole document, so they're displayed before any of the hunks
+    hunks
+        .0
+        .splice(0..0, renames.into_iter().map(RichHunk::Renamed));
+    Ok(hunks)
+}
+
+/// Compute the edit script between two documents, respecting the maximum cost and time in the
+/// config.
+pub(crate) fn edit_script<'a>(
+    old: &'a [Entry<'a>],
+    new: &'a [Entry<'a>],
+    config: &DiffConfig,
+) -> (Vec<EditType<&'a Entry<'a>>>, DiffStrategy) {
+    info!("Computing edit script with the {} engine", config.engine);
+    let max_time = config.max_time.map(Duration::from_millis);
+    let (edit_script, strategy) = if config.max_cost.is_some() || max_time.is_some() {
+        bounded_edit_script(old, new, config.engine, config.max_cost, max_time)
+    } else {
+        (config.engine.diff(old, new), DiffStrategy::Exact)
+    };
+    info!("Computed edit script with the {strategy} strategy");
+    (edit_script, strategy)
+}
+
+/// Compute how similar two documents are, from 0 for documents with nothing in common to 1 for
+/// identical documents.
+///
+/// The similarity is the fraction of entries that the edit script leaves unchanged, so two
+/// documents where half of the entries were replaced have a similarity of 0.5. Each entry is
+/// weighted by its node kind according to [`DiffConfig::similarity_weights`]. Two documents
+/// without any weighted entries are considered identical.
+#[time("info", "diff::{}")]
+pub fn similarity<'a>(old: &'a [Entry<'a>], new: &'a [Entry<'a>], config: &DiffConfig) -> f64 {
+    let weight = |entry: &Entry| {
+        config
+            .similarity_weights
+            .get(entry.kind())
+            .map_or(1, |&weight| u64::from(weight))
+    };
+    let total: u64 = old.iter().chain(new).map(weight).sum();
+
+    if total == 0 {
+        return 1.0;
+    }
+    let (edit_script, _) = edit_script(old, new, config);
+    let changed: u64 = edit_script.iter().map(|edit| weight(edit)).sum();
+    (total - changed) as f64 / total as f64
+}
+
+/// Find the index of an entry that was borrowed from a slice of entries.
+pub(crate) fn entry_index<T>(entries: &[T], entry: &T) -> usize {
+    let offset = std::ptr::from_ref(entry).addr() - entries.as_ptr().addr();
+    offset / std::mem::size_of::<T>()
+}
+
+/// For each element, count how many unchanged elements come before it.
+fn unchanged_counts(changed: &[bool]) -> Vec<usize> {
+    let mut count = 0;
+    changed
+        .iter()
+        .map(|&is_changed| {
+            let current = count;
+            if !is_changed {
+                count += 1;
+            }
+            current
+        })
+        .collect()
+}
+
+/// The position of each entry relative to the unchanged entries in an edit script.
+///
+/// Each entry is assigned the number of unchanged entries that come before it. An unchanged entry
+/// in the old document and the unchanged entry it was matched with have the same value, so changes
+/// that sit between the same pair of unchanged entries have the same value as well.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Alignment {
+    /// The alignment of each entry in the old document
+    pub old: Vec<usize>,
+
+    /// The alignment of each entry in the new document
+    pub new: Vec<usize>,
+}
+
+impl Alignment {
+    /// Compute the alignment from an edit script between `old` and `new`.
+    ///
+    /// The entries in the edit script must be borrowed from `old` and `new`.
+    pub fn new<'a>(
+        old: &'a [Entry<'a>],
+        new: &'a [Entry<'a>],
+        edits: &[EditType<&'a Entry<'a>>],
+    ) -> Self {
+        let mut old_changed = vec![false; old.len()];
+        let mut new_changed = vec![false; new.len()];
+
+        for edit in edits {
+            match edit {
+                EditType::Deletion(entry) => old_changed[entry_index(old, entry)] = true,
+                EditType::Addition(entry) => new_changed[entry_index(new, entry)] = true,
+            }
+        }
+        Self {
+            old: unchanged_counts(&old_changed),
+            new: unchanged_counts(&new_changed),
+        }
+    }
+}
+
+/// Pair up old and new hunks that replace each other.
+///
+/// An old hunk and a new hunk replace each other if they're next to each other and their changes
+/// sit between the same unchanged entries, according to the [alignment](Alignment) of the original
+/// edit script. Everything else is left as is.
+pub(crate) fn pair_hunks<'a>(
+    hunks: RichHunks<'a>,
+    old: &'a [Entry<'a>],
+    new: &'a [Entry<'a>],
+    alignment: &Alignment,
+) -> RichHunks<'a> {
+    // The range of alignment values that a hunk covers
+    let span = |hunk: &Hunk<'a>, entries: &'a [Entry<'a>], alignment: &[usize]| {
+        let mut values = hunk
+            .entries()
+            .map(|entry| alignment[entry_index(entries, entry)]);
+        let first = values.next()?;
+        Some(first..=values.last().unwrap_or(first))
+    };
+    let overlaps = |old_hunk: &Hunk<'a>, new_hunk: &Hunk<'a>| match (
+        span(old_hunk, old, &alignment.old),
+        span(new_hunk, new, &alignment.new),
+    ) {
+        (Some(x), Some(y)) => x.start() <= y.end() && y.start() <= x.end(),
+        _ => false,
+    };
+    let mut paired = Vec::with_capacity(hunks.0.len());
+    let mut iter = hunks.0.into_iter().peekable();
+
+    while let Some(hunk) = iter.next() {
+        let changed = match (&hunk, iter.peek()) {
+            (RichHunk::Old(old_hunk), Some(RichHunk::New(new_hunk)))
+            | (RichHunk::New(new_hunk), Some(RichHunk::Old(old_hunk)))
+                if overlaps(old_hunk, new_hunk) =>
+            {
+                Some(ChangedHunk {
+                    old: old_hunk.clone(),
+                    new: new_hunk.clone(),
+                })
+            }
+            _ => None,
+        };
+
+        match changed {
+            Some(changed) => {
+                iter.next();
+                paired.push(RichHunk::Changed(changed));
+            }
+            None => paired.push(hunk),
+        }
+    }
+    RichHunks(paired)
+}
+
+/// A changed entry along with the index of the stretch of changes it came from.
+///
+/// A stretch is a hunk, or several hunks that are only separated by unchanged whitespace.
+type ChangedEntry<'a> = (&'a Entry<'a>, usize);
+
+/// A run of deleted entries that shows up unchanged in the added entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct MoveCandidate {
+    /// The number of characters in the run
+    text_len: usize,
+
+    /// The index of the first entry in the deleted entries
+    old: usize,
+
+    /// The index of the first entry in the added entries
+    new: usize,
+
+    /// The number of entries in the run
+    len: usize,
+}
+
+/// Pair runs of deleted entries with identical runs of added entries and turn them into
+/// [moved hunks](MovedHunk).
+///
+/// This is a post-processing pass over the output of a flat diff. The flat diff can only express
+/// a block of code that was moved as a deletion and an addition, which gets very noisy when
+/// methods are reordered. Runs only span several hunks on either side if the hunks are separated by
+/// unchanged whitespace, like the blank lines inside of a function, and runs with fewer than
+/// `min_len` characters are ignored.
+///
+/// Runs are chosen greedily from longest to shortest. The moved hunks are placed where their text
+/// shows up in the new document.
+#[time("info", "diff::{}")]
+pub fn detect_moves<'a>(
+    hunks: RichHunks<'a>,
+    old: &[Entry],
+    new: &[Entry],
+    min_len: usize,
+) -> Result<RichHunks<'a>> {
+    let mut deleted: Vec<ChangedEntry> = Vec::new();
+    let mut added: Vec<ChangedEntry> = Vec::new();
+
+    for (hunk_idx, hunk) in hunks.0.iter().enumerate() {
+        match hunk {
+            RichHunk::Old(hunk) => deleted.extend(hunk.entries().map(|e| (e, hunk_idx))),
+            RichHunk::New(hunk) => added.extend(hunk.entries().map(|e| (e, hunk_idx))),
+            RichHunk::Changed(ChangedHunk { old, new }) => {
+                deleted.extend(old.entries().map(|e| (e, hunk_idx)));
+                added.extend(new.entries().map(|e| (e, hunk_idx)));
+            }
+            RichHunk::Moved(_) | RichHunk::Renamed(_) => (),
+        }
+    }
+    join_whitespace_gaps(&mut deleted, old);
+    join_whitespace_gaps(&mut added, new);
+    let candidates = find_move_candidates(&deleted, &added, min_len);
+
+    // The move that each deleted and added entry belongs to, if any
+    let mut old_moves: Vec<Option<usize>> = vec![None; deleted.len()];
+    let mut new_moves: Vec<Option<usize>> = vec![None; added.len()];
+    let mut moves: Vec<Option<MovedHunk>> = Vec::new();
+
+    for candidate in candidates {
+        let old_range = candidate.old..candidate.old + candidate.len;
+        let new_range = candidate.new..candidate.new + candidate.len;
+
+        if old_moves[old_range.clone()].iter().any(Option::is_some)
+            || new_moves[new_range.clone()].iter().any(Option::is_some)
+        {
+            continue;
+        }
+        let mut moved_hunk = MovedHunk {
+            old: Hunks::new(),
+            new: Hunks::new(),
+        };
+
+        for &(entry, _) in &deleted[old_range.clone()] {
+            moved_hunk.old.push_back(entry)?;
+        }
+
+        for &(entry, _) in &added[new_range.clone()] {
+            moved_hunk.new.push_back(entry)?;
+        }
+        old_moves[old_range].fill(Some(moves.len()));
+        new_moves[new_range].fill(Some(moves.len()));
+        moves.push(Some(moved_hunk));
+    }
+    info!("Detected {} moved blocks", moves.len());
+
+    if moves.is_empty() {
+        return Ok(hunks);
+    }
+
+    // Rebuild the hunks in their original order without the moved entries
+    let mut builder = RichHunksBuilder::new();
+    let mut deleted_moves = old_moves.into_iter();
+    let mut added_moves = new_moves.into_iter();
+
+    for hunk in hunks.0 {
+        let entries: Vec<DocumentType<&Entry>> = match hunk {
+            RichHunk::Old(hunk) => hunk.entries().map(DocumentType::Old).collect(),
+            RichHunk::New(hunk) => hunk.entries().map(DocumentType::New).collect(),
+            RichHunk::Changed(ChangedHunk { old, new }) => old
+                .entries()
+                .map(DocumentType::Old)
+                .chain(new.entries().map(DocumentType::New))
+                .collect(),
+            RichHunk::Moved(moved_hunk) => {
+                builder.push_moved(moved_hunk);
+                continue;
+            }
+            RichHunk::Renamed(rename) => {
+                builder.push_rename(rename);
+                continue;
+            }
+        };
+
+        for entry in entries {
+            match entry {
+                DocumentType::Old(entry) => {
+                    if deleted_moves.next().flatten().is_none() {
+                        builder.push_back(DocumentType::Old(entry))?;
+                    }
+                }
+                DocumentType::New(entry) => match added_moves.next().flatten() {
+                    Some(move_idx) => {
+                        if let Some(moved_hunk) = moves[move_idx].take() {
+                            builder.push_moved(moved_hunk);
+                        }
+                    }
+                    None => builder.push_back(DocumentType::New(entry))?,
+                },
+            }
+        }
+    }
+    Ok(builder.build())
+}
+
+/// Number the stretches of changed entries, replacing the hunk indices of the entries.
+///
+/// Consecutive hunks belong to the same stretch if the only entries between them in the document
+/// are whitespace, otherwise a moved block with a blank line in it would be split at the blank line.
+fn join_whitespace_gaps(changed: &mut [ChangedEntry], document: &[Entry]) {
+    let mut stretch = 0;
+    let mut prev_hunk = changed.first().map(|&(_, hunk_idx)| hunk_idx);
+
+    for i in 1..changed.len() {
+        let (prev, _) = changed[i - 1];
+        let (entry, hunk_idx) = changed[i];
+
+        if prev_hunk != Some(hunk_idx) {
+            let gap_start = document.partition_point(|e| e.start_position <= prev.start_position);
+            let gap_end = document.partition_point(|e| e.start_position < entry.start_position);
+            let gap = document.get(gap_start..gap_end).unwrap_or_default();
+
+            if !gap.iter().all(|e| e.text.trim().is_empty()) {
+                stretch += 1;
+            }
+        }
+        prev_hunk = Some(hunk_idx);
+        changed[i].1 = stretch;
+    }
+    if let Some(first) = changed.first_mut() {
+        first.1 = 0;
+    }
+}
+
+/// Find the maximal runs of deleted entries that match a run of added entries.
+///
+/// The candidates are sorted from longest to shortest, ties are broken by document order so the
+/// results are deterministic.
+fn find_move_candidates(
+    deleted: &[ChangedEntry],
+    added: &[ChangedEntry],
+    min_len: usize,
+) -> Vec<MoveCandidate> {
+    let mut positions: HashMap<&Entry, Vec<usize>> = HashMap::new();
+
+    for (i, (entry, _)) in added.iter().enumerate() {
+        positions.entry(*entry).or_default().push(i);
+    }
+    // Two entries can only be part of the same run if they're equal and come from the same stretch
+    // as the previous entries in the run.
+    let continues = |i: usize, j: usize| {
+        deleted[i].0 == added[j].0
+            && deleted[i].1 == deleted[i - 1].1
+            && added[j].1 == added[j - 1].1
+    };
+    let mut candidates = Vec::new();
+
+    for (i, (entry, _)) in deleted.iter().enumerate() {
+        let Some(starts) = positions.get(entry) else {
+            continue;
+        };
+
+        // Very common entries (like braces) would make this quadratic, and any run that contains
+        // them will still be found starting from the next entry.
+        if starts.len() > HISTOGRAM_MAX_CHAIN_LEN {
+            continue;
+        }
+
+        for &j in starts {
+            // Only consider runs that can't be extended backwards
+            if i > 0 && j > 0 && continues(i, j) {
+                continue;
+            }
+            let mut len = 1;
+
+            while i + len < deleted.len() && j + len < added.len() && continues(i + len, j + len) {
+                len += 1;
+            }
+            let text_len = deleted[i..i + len]
+                .iter()
+                .map(|(entry, _)| entry.text.len())
+                .sum();
+
+            if text_len >= min_len {
+                candidates.push(MoveCandidate {
+                    text_len,
+                    old: i,
+                    new: j,
+                    len,
+                });
+            }
+        }
+    }
+    candidates.sort_by_key(|c| (std::cmp::Reverse(c.text_len), c.old, c.new));
+    candidates
+}
+
+/// Types of errors that come up when applying an edit script to a document
+#[derive(Debug, Error)]
+pub enum ApplyError {
+    #[error("The hunks contain an entry that doesn't belong to the {document} document")]
+    ForeignEntry { document: &'static str },
+
+    #[error(
+        "An entry at line {line}, column {column} is outside of the {document} document's text"
+    )]
+    OutOfBounds {
+        document: &'static str,
+        line: usize,
+        column: usize,
+    },
+
+    #[error(
+        "The entry {entry_text:?} doesn't match the text at its position in the {document} document ({document_text:?})"
+    )]
+    EntryText {
+        document: &'static str,
+        entry_text: String,
+        document_text: String,
+    },
+
+    #[error(
+        "An entry on line {entry_line} of the {document} document is in a hunk line with index {line_index}"
+    )]
+    LineIndex {
+        document: &'static str,
+        entry_line: usize,
+        line_index: usize,
+    },
+
+    #[error(
+        "The old document has {old} unchanged entries but the new document has {new} unchanged entries"
+    )]
+    UnchangedCount { old: usize, new: usize },
+
+    #[error(
+        "The unchanged entry {old:?} in the old document is paired with {new:?} in the new document"
+    )]
+    UnchangedText { old: String, new: String },
+
+    #[error(
+        "Ignoring whitespace, the patched document differs from the new document at byte {offset} of the new document"
+    )]
+    Mismatch { offset: usize },
+}
+
+/// Converts [points](Point) in a document to byte offsets.
+pub(crate) struct LineOffsets<'a> {
+    text: &'a str,
+
+    /// The byte offset of the start of each line
+    starts: Vec<usize>,
+}
+
+impl<'a> LineOffsets<'a> {
+    pub(crate) fn new(text: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        LineOffsets { text, starts }
+    }
+
+    /// Get the byte offset of a point, if it's a valid position in the text.
+    pub(crate) fn offset(&self, point: Point) -> Option<usize> {
+        let offset = self.starts.get(point.row)? + point.column;
+        self.text.is_char_boundary(offset).then_some(offset)
+    }
+}
+
+/// A document that an edit script refers to, with the byte range of each of its entries.
+struct ApplyDocument<'a> {
+    name: &'static str,
+    entries: &'a [Entry<'a>],
+    ranges: Vec<Range<usize>>,
+
+    /// Whether each entry was deleted or added by the edit script
+    changed: Vec<bool>,
+}
+
+impl<'a> ApplyDocument<'a> {
+    /// Resolve the byte range of each entry from its position and check that it matches the text.
+    fn new(
+        name: &'static str,
+        text: &'a str,
+        entries: &'a [Entry<'a>],
+    ) -> Result<Self, ApplyError> {
+        let offsets = LineOffsets::new(text);
+        let ranges = entries
+            .iter()
+            .map(|entry| {
+                let out_of_bounds = |point: Point| ApplyError::OutOfBounds {
+                    document: name,
+                    line: point.row,
+                    column: point.column,
+                };
+                let start = offsets
+                    .offset(entry.start_position)
+                    .ok_or_else(|| out_of_bounds(entry.start_position))?;
+                let end = offsets
+                    .offset(entry.end_position)
+                    .filter(|&end| end >= start)
+                    .ok_or_else(|| out_of_bounds(entry.end_position))?;
+
+                if text[start..end] != entry.text {
+                    return Err(ApplyError::EntryText {
+                        document: name,
+                        entry_text: entry.text.to_string(),
+                        document_text: text[start..end].to_string(),
+                    });
+                }
+                Ok(start..end)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(ApplyDocument {
+            name,
+            entries,
+            ranges,
+            changed: vec![false; entries.len()],
+        })
+    }
+
+    /// Mark an entry from one of the hunks as changed.
+    fn mark(&mut self, entry: &Entry) -> Result<(), ApplyError> {
+        let in_document = self
+            .entries
+            .as_ptr_range()
+            .contains(&std::ptr::from_ref(entry));
+
+        if !in_document {
+            return Err(ApplyError::ForeignEntry {
+                document: self.name,
+            });
+        }
+        self.changed[entry_index(self.entries, entry)] = true;
+        Ok(())
+    }
+
+    /// Mark the entries in a hunk as changed, checking that each line of the hunk has the right
+    /// index.
+    fn mark_hunk(&mut self, hunk: &Hunk) -> Result<(), ApplyError> {
+        for line in &hunk.0 {
+            for entry in &line.entries {
+                if !(entry.start_position.row..=entry.last_row()).contains(&line.line_index) {
+                    return Err(ApplyError::LineIndex {
+                        document: self.name,
+                        entry_line: entry.start_position.row,
+                        line_index: line.line_index,
+                    });
+                }
+                self.mark(entry)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The indices of the entries that weren't changed by the edit script.
+    fn unchanged(&self) -> Vec<usize> {
+        (0..self.entries.len())
+            .filter(|&i| !self.changed[i])
+            .collect()
+    }
+}
+
+/// Apply the hunks of an edit script to the old document.
+///
+/// The byte range of each entry is resolved from its position, so this checks that the positions
+/// of the entries match their text. The entries that weren't changed by the hunks are paired up in
+/// order and have to have the same text in both documents. The text between two unchanged entries
+/// is copied from the new document if there are changes between them, otherwise it's copied from
+/// the old document.
+///
+/// The entries of the hunks must be borrowed from `old_entries` and `new_entries`.
+pub fn apply_hunks<'a>(
+    old_text: &'a str,
+    old_entries: &'a [Entry<'a>],
+    new_text: &'a str,
+    new_entries: &'a [Entry<'a>],
+    hunks: &RichHunks<'a>,
+) -> Result<String, ApplyError> {
+    let mut old = ApplyDocument::new("old", old_text, old_entries)?;
+    let mut new = ApplyDocument::new("new", new_text, new_entries)?;
+
+    for hunk in &hunks.0 {
+        match hunk {
+            RichHunk::Old(hunk) => old.mark_hunk(hunk)?,
+            RichHunk::New(hunk) => new.mark_hunk(hunk)?,
+            RichHunk::Changed(ChangedHunk {
+                old: old_hunk,
+                new: new_hunk,
+            }) => {
+                old.mark_hunk(old_hunk)?;
+                new.mark_hunk(new_hunk)?;
+            }
+            RichHunk::Moved(MovedHunk {
+                old: old_hunks,
+                new: new_hunks,
+            }) => {
+                for hunk in &old_hunks.0 {
+                    old.mark_hunk(hunk)?;
+                }
+                for hunk in &new_hunks.0 {
+                    new.mark_hunk(hunk)?;
+                }
+            }
+            RichHunk::Renamed(rename) => {
+                for occurrence in &rename.occurrences {
+                    occurrence
+                        .old
+                        .iter()
+                        .try_for_each(|entry| old.mark(entry))?;
+                    occurrence
+                        .new
+                        .iter()
+                        .try_for_each(|entry| new.mark(entry))?;
+                }
+            }
+        }
+    }
+    let old_unchanged = old.unchanged();
+    let new_unchanged = new.unchanged();
+
+    if old_unchanged.len() != new_unchanged.len() {
+        return Err(ApplyError::UnchangedCount {
+            old: old_unchanged.len(),
+            new: new_unchanged.len(),
+        });
+    }
+    let mut patched = String::with_capacity(new_text.len());
+    let (mut old_next, mut new_next): (usize, usize) = (0, 0);
+    let anchors = old_unchanged
+        .into_iter()
+        .zip(new_unchanged)
+        .map(Some)
+        .chain([None]);
+
+    for anchor in anchors {
+        let (old_end, new_end) = anchor.unwrap_or((old_entries.len(), new_entries.len()));
+        let old_start_byte = old_next.checked_sub(1).map_or(0, |i| old.ranges[i].end);
+        let new_start_byte = new_next.checked_sub(1).map_or(0, |i| new.ranges[i].end);
+        let old_end_byte = old.ranges.get(old_end).map_or(old_text.len(), |r| r.start);
+        let new_end_byte = new.ranges.get(new_end).map_or(new_text.len(), |r| r.start);
+
+        if old_end > old_next || new_end > new_next {
+            patched.push_str(&new_text[new_start_byte..new_end_byte]);
+        } else {
+            patched.push_str(&old_text[old_start_byte..old_end_byte]);
+        }
+
+        if let Some((old_index, new_index)) = anchor {
+            let old_anchor = &old_text[old.ranges[old_index].clone()];
+            let new_anchor = &new_text[new.ranges[new_index].clone()];
+
+            if old_anchor != new_anchor {
+                return Err(ApplyError::UnchangedText {
+                    old: old_anchor.to_string(),
+                    new: new_anchor.to_string(),
+                });
+            }
+            patched.push_str(old_anchor);
+            (old_next, new_next) = (old_index + 1, new_index + 1);
+        }
+    }
+    Ok(patched)
+}
+
+/// Check that applying the hunks of an edit script to the old document reproduces the new
+/// document.
+///
+/// See [`apply_hunks`] for the checks that are made while applying the hunks. The patched document
+/// is compared to the new document ignoring whitespace, since whitespace isn't part of the entries
+/// and an edit script can't describe whitespace changes between unchanged entries. This means that
+/// whitespace-only changes, such as changes to indentation or to spaces in a string literal, are
+/// never checked.
+pub fn verify_hunks<'a>(
+    old_text: &'a str,
+    old_entries: &'a [Entry<'a>],
+    new_text: &'a str,
+    new_entries: &'a [Entry<'a>],
+    hunks: &RichHunks<'a>,
+) -> Result<(), ApplyError> {
+    let patched = apply_hunks(old_text, old_entries, new_text, new_entries, hunks)?;
+    let mut patched_chars = patched.chars().filter(|c| !c.is_whitespace());
+    let mut new_chars = new_text.char_indices().filter(|(_, c)| !c.is_whitespace());
+
+    loop {
+        match (patched_chars.next(), new_chars.next()) {
+            (None, None) => return Ok(()),
+            (Some(a), Some((_, b))) if a == b => {}
+            (_, Some((offset, _))) => return Err(ApplyError::Mismatch { offset }),
+            (Some(_), None) => {
+                return Err(ApplyError::Mismatch {
+                    offset: new_text.len(),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq as p_assert_eq;
+    use test_case::test_case;
+
+    /// A convenience function to invoke the a Myers diff
+    fn myers_diff<'a, T>(a: &'a [T], b: &'a [T]) -> Vec<EditType<&'a T>>
+    where
+        T: 'a + Eq + Debug,
+    {
+        let myers = Myers::default();
+        myers.diff(a, b)
+    }
+
+    #[test]
+    fn mid_snake_empty_input() {
+        let input_a = b"";
+        let input_b = b"";
+
+        let mut frontiers = MyersFrontiers::new(input_a.len(), input_b.len());
+        let mid_snake = Myers::middle_snake(
+            &input_a[..],
+            0..input_a.len(),
+            &input_b[..],
+            0..input_b.len(),
+            &mut frontiers,
+        );
+        let expected = Coordinates { old: 0, new: 0 };
+        p_assert_eq!(expected, mid_snake);
+    }
+
+    #[test]
+    fn mid_snake() {
+        let input_a = &b"ABCABBA"[..];
+        let input_b = &b"CBABAC"[..];
+        let mut frontiers = MyersFrontiers::new(input_a.len(), input_b.len());
+        let mid_snake = Myers::middle_snake(
+            input_a,
+            0..input_a.len(),
+            input_b,
+            0..input_b.len(),
+            &mut frontiers,
+        );
+        let expected = Coordinates { old: 4, new: 1 };
+        p_assert_eq!(expected, mid_snake);
+    }
+
+    #[test]
+    fn myers_diff_empty_inputs() {
+        let input_a: Vec<i32> = vec![];
+        let input_b: Vec<i32> = vec![];
+        let edit_script = myers_diff(&input_a, &input_b);
+        assert!(edit_script.is_empty());
+    }
+
+    #[test]
+    fn myers_diff_no_diff() {
+        let input_a: Vec<i32> = vec![0; 4];
+        let input_b: Vec<i32> = vec![0; 4];
+        let edit_script = myers_diff(&input_a, &input_b);
+        assert!(edit_script.is_empty());
+    }
+
+    #[test]
+    fn myers_diff_one_addition() {
+        let input_a: Vec<i32> = Vec::new();
+        let input_b: Vec<i32> = vec![0];
+        let expected = vec![EditType::Addition(&input_b[0])];
+        let edit_script = myers_diff(&input_a, &input_b);
+        p_assert_eq!(expected, edit_script);
+    }
+
+    #[test]
+    fn myers_diff_one_deletion() {
+        let input_a: Vec<i32> = vec![0];
+        let input_b: Vec<i32> = Vec::new();
+        let expected = vec![EditType::Deletion(&input_a[0])];
+        let edit_script = myers_diff(&input_a, &input_b);
+        p_assert_eq!(expected, edit_script);
+    }
+
+    #[test]
+    fn myers_diff_single_substitution() {
+        let myers = Myers::default();
+        let input_a = [1];
+        let input_b = [2];
+        let edit_script = myers.diff(&input_a[..], &input_b[..]);
+        let expected = vec![
+            EditType::Addition(&input_b[0]),
+            EditType::Deletion(&input_a[0]),
+        ];
+        p_assert_eq!(expected, edit_script);
+    }
+
+    #[test]
+    fn myers_diff_single_substitution_with_common_elements() {
+        let myers = Myers::default();
+        let input_a = [0, 0, 0];
+        let input_b = [0, 1, 0];
+        let edit_script = myers.diff(&input_a[..], &input_b[..]);
         let expected = vec![
             EditType::Addition(&input_b[1]),
             EditType::Deletion(&input_a[1]),
@@ -896,6 +2523,281 @@ mod tests {
         p_assert_eq!(expected, edit_script);
     }
 
+    /// Check that applying an edit script to `a` yields `b`.
+    ///
+    /// Edit scripts only reference elements, so we remove every deleted element from `a` and every
+    /// added element from `b`, and check that what remains in both is identical.
+    fn assert_valid_edit_script<T: Eq + Debug>(a: &[T], b: &[T], script: &[EditType<&T>]) {
+        let is_in_script = |x: &T, is_addition: bool| {
+            script.iter().any(|edit| match edit {
+                EditType::Addition(e) => is_addition && std::ptr::eq(*e, x),
+                EditType::Deletion(e) => !is_addition && std::ptr::eq(*e, x),
+            })
+        };
+        let kept_a: Vec<&T> = a.iter().filter(|x| !is_in_script(x, false)).collect();
+        let kept_b: Vec<&T> = b.iter().filter(|x| !is_in_script(x, true)).collect();
+        p_assert_eq!(kept_a, kept_b);
+    }
+
+    #[test_case(DiffEngine::Myers, b"", b"" ; "myers empty")]
+    #[test_case(DiffEngine::Myers, b"ABCABBA", b"CBABAC" ; "myers interleaved")]
+    #[test_case(DiffEngine::Myers, b"{a}{b}", b"{b}{a}{c}" ; "myers reordered")]
+    #[test_case(DiffEngine::Patience, b"", b"" ; "patience empty")]
+    #[test_case(DiffEngine::Patience, b"ABCABBA", b"CBABAC" ; "patience interleaved")]
+    #[test_case(DiffEngine::Patience, b"{a}{b}", b"{b}{a}{c}" ; "patience reordered")]
+    #[test_case(DiffEngine::Patience, b"AAAA", b"AAB" ; "patience no unique elements")]
+    #[test_case(DiffEngine::Histogram, b"", b"" ; "histogram empty")]
+    #[test_case(DiffEngine::Histogram, b"ABCABBA", b"CBABAC" ; "histogram interleaved")]
+    #[test_case(DiffEngine::Histogram, b"{a}{b}", b"{b}{a}{c}" ; "histogram reordered")]
+    #[test_case(DiffEngine::Histogram, b"AAAA", b"AAB" ; "histogram no unique elements")]
+    fn engine_produces_valid_edit_script(engine: DiffEngine, a: &[u8], b: &[u8]) {
+        let edit_script = engine.diff(a, b);
+        assert_valid_edit_script(a, b, &edit_script);
+    }
+
+    #[test_case(DiffEngine::Patience ; "patience")]
+    #[test_case(DiffEngine::Histogram ; "histogram")]
+    fn engine_no_diff(engine: DiffEngine) {
+        let input_a = b"{a}{b}";
+        let input_b = b"{a}{b}";
+        assert!(engine.diff(&input_a[..], &input_b[..]).is_empty());
+    }
+
+    #[test]
+    fn patience_ignores_repeated_elements() {
+        let input_a = [1, 2, 3, 2];
+        let input_b = [3, 2, 1, 2];
+        let anchors = Patience::unique_anchors(
+            &input_a[..],
+            0..input_a.len(),
+            &input_b[..],
+            0..input_b.len(),
+        );
+        p_assert_eq!(vec![Coordinates { old: 2, new: 0 }], anchors);
+    }
+
+    #[test]
+    fn patience_anchors_are_increasing() {
+        let input_a = [1, 2, 3, 4, 5];
+        let input_b = [1, 3, 2, 4, 5];
+        let anchors = Patience::unique_anchors(
+            &input_a[..],
+            0..input_a.len(),
+            &input_b[..],
+            0..input_b.len(),
+        );
+        let expected = vec![
+            Coordinates { old: 0, new: 0 },
+            Coordinates { old: 2, new: 1 },
+            Coordinates { old: 3, new: 3 },
+            Coordinates { old: 4, new: 4 },
+        ];
+        p_assert_eq!(expected, anchors);
+    }
+
+    #[test]
+    fn histogram_anchors_on_least_frequent_element() {
+        let input_a = [0, 0, 1, 0, 0];
+        let input_b = [1, 0, 0, 2];
+        let region = Histogram::find_anchor_region(
+            &input_a[..],
+            0..input_a.len(),
+            &input_b[..],
+            0..input_b.len(),
+        );
+        let expected = CommonRegion {
+            old: 2,
+            new: 0,
+            len: 3,
+            occurrences: 1,
+        };
+        p_assert_eq!(Some(expected), region);
+    }
+
+    #[test_case("myers" => DiffEngine::Myers)]
+    #[test_case("patience" => DiffEngine::Patience)]
+    #[test_case("histogram" => DiffEngine::Histogram)]
+    fn diff_engine_from_str(s: &str) -> DiffEngine {
+        s.parse().unwrap()
+    }
+
+    #[test_case(&[] => Vec::<usize>::new() ; "empty")]
+    #[test_case(&[0, 1, 2] => vec![0, 1, 2] ; "sorted")]
+    #[test_case(&[2, 1, 0] => vec![2] ; "reversed")]
+    #[test_case(&[3, 0, 1, 4, 2] => vec![1, 2, 4] ; "mixed")]
+    fn longest_increasing_subsequence_indices(values: &[usize]) -> Vec<usize> {
+        longest_increasing_subsequence(values)
+    }
+
+    #[test_case(DiffEngine::Myers)]
+    #[test_case(DiffEngine::Patience)]
+    #[test_case(DiffEngine::Histogram)]
+    fn diff_with_budget_matches_unbounded_diff(engine: DiffEngine) {
+        let old = b"fn foo() { bar(); }";
+        let new = b"fn foo() { baz(); qux(); }";
+        let expected = engine.diff(&old[..], &new[..]);
+        let cost = expected.len();
+        let edits = engine
+            .diff_with_budget(&old[..], &new[..], CostBudget::Limited(cost))
+            .unwrap();
+        p_assert_eq!(expected, edits);
+        assert!(
+            engine
+                .diff_with_budget(&old[..], &new[..], CostBudget::Limited(cost - 1))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn diff_region_updates_budget() {
+        let old = b"abcabba";
+        let new = b"cbabac";
+        let mut res = Vec::new();
+        let mut frontiers = MyersFrontiers::new(old.len(), new.len());
+        let mut budget = CostBudget::Limited(10);
+        Myers::diff_region(
+            &mut res,
+            &old[..],
+            0..old.len(),
+            &new[..],
+            0..new.len(),
+            &mut frontiers,
+            &mut budget,
+        )
+        .unwrap();
+        // This is the example from Myers' paper, which has an edit distance of 5
+        p_assert_eq!(5, res.len());
+        p_assert_eq!(CostBudget::Limited(5), budget);
+
+        let mut res = Vec::new();
+        let mut budget = CostBudget::Limited(4);
+        let result = Myers::diff_region(
+            &mut res,
+            &old[..],
+            0..old.len(),
+            &new[..],
+            0..new.len(),
+            &mut frontiers,
+            &mut budget,
+        );
+        assert!(result.is_err());
+        assert!(res.is_empty());
+    }
+
+    #[test_case(DiffEngine::Myers)]
+    #[test_case(DiffEngine::Patience)]
+    #[test_case(DiffEngine::Histogram)]
+    fn timed_budget_stops_at_the_deadline(engine: DiffEngine) {
+        let old = b"fn foo() { bar(); }";
+        let new = b"fn foo() { baz(); qux(); }";
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let edits = engine
+            .diff_with_budget(&old[..], &new[..], CostBudget::Timed(usize::MAX, deadline))
+            .unwrap();
+        p_assert_eq!(engine.diff(&old[..], &new[..]), edits);
+        assert!(
+            engine
+                .diff_with_budget(
+                    &old[..],
+                    &new[..],
+                    CostBudget::Timed(usize::MAX, Instant::now())
+                )
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn edit_script_falls_back_when_out_of_time() {
+        let processor = crate::input_processing::TreeSitterProcessor::default();
+        let old = processor.process_text("let a = b;\nlet c = d;\n");
+        let new = processor.process_text("let a = e;\nlet f = d;\n");
+        let config = DiffConfig {
+            max_time: Some(0),
+            ..Default::default()
+        };
+        let (edits, strategy) = edit_script(&old, &new, &config);
+        p_assert_eq!(DiffStrategy::Anchored, strategy);
+        assert!(!edits.is_empty());
+
+        let config = DiffConfig {
+            max_time: Some(60_000),
+            ..Default::default()
+        };
+        p_assert_eq!(DiffStrategy::Exact, edit_script(&old, &new, &config).1);
+    }
+
+    #[test]
+    fn replace_budget_replaces_regions_without_anchors() {
+        let old = b"xaby";
+        let new = b"xcdy";
+        let edits = DiffEngine::Histogram
+            .diff_with_budget(&old[..], &new[..], CostBudget::Replace)
+            .unwrap();
+        let expected = vec![
+            EditType::Deletion(&old[1]),
+            EditType::Deletion(&old[2]),
+            EditType::Addition(&new[1]),
+            EditType::Addition(&new[2]),
+        ];
+        p_assert_eq!(expected, edits);
+    }
+
+    #[test_case(&[] => Vec::<usize>::new() ; "empty")]
+    #[test_case(&[false, false] => vec![0, 1] ; "unchanged")]
+    #[test_case(&[true, false, true, true, false] => vec![0, 0, 1, 1, 1] ; "mixed")]
+    fn unchanged_counts_before_each_element(changed: &[bool]) -> Vec<usize> {
+        unchanged_counts(changed)
+    }
+
+    #[test]
+    fn hunk_lines_cover_multiline_entries() {
+        let entry = |text: &'static str, (start_row, start_col), (end_row, end_col)| Entry {
+            reference: None,
+            text: text.into(),
+            start_position: Point {
+                row: start_row,
+                column: start_col,
+            },
+            end_position: Point {
+                row: end_row,
+                column: end_col,
+            },
+            kind_id: 0,
+        };
+        let entries = [
+            entry("/* a\n b\n */", (1, 4), (3, 3)),
+            entry("x", (3, 4), (3, 5)),
+            entry("// c\n", (4, 0), (5, 0)),
+        ];
+        let mut hunk = Hunk::new();
+
+        for entry in &entries {
+            hunk.push_back(entry).unwrap();
+        }
+        let lines: Vec<(usize, usize)> = hunk
+            .0
+            .iter()
+            .map(|line| (line.line_index, line.entries.len()))
+            .collect();
+        p_assert_eq!(lines, vec![(1, 1), (2, 1), (3, 2), (4, 1)]);
+        p_assert_eq!(hunk.entries().count(), entries.len());
+        p_assert_eq!(hunk.0[0].columns(&entries[0], 8), 4..8);
+        p_assert_eq!(hunk.0[1].columns(&entries[0], 2), 0..2);
+        p_assert_eq!(hunk.0[2].columns(&entries[0], 5), 0..3);
+        p_assert_eq!(hunk.0[2].columns(&entries[1], 5), 4..5);
+    }
+
+    #[test]
+    fn similarity_of_empty_documents() {
+        p_assert_eq!(similarity(&[], &[], &DiffConfig::default()), 1.0);
+    }
+
+    #[test_case("flat" => DiffMode::Flat)]
+    #[test_case("tree" => DiffMode::Tree)]
+    fn diff_mode_from_str(s: &str) -> DiffMode {
+        s.parse().unwrap()
+    }
+
     #[test_case(b"BAAA", b"CAAA" => 0 ; "no common prefix")]
     #[test_case(b"AAABA", b"AAACA" => 3 ; "with common prefix")]
     fn common_prefix(a: &[u8], b: &[u8]) -> usize {
@@ -907,4 +2809,165 @@ mod tests {
     fn common_suffix(a: &[u8], b: &[u8]) -> usize {
         common_suffix_len(a, 0..a.len(), b, 0..b.len())
     }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn detect_moves_pairs_reordered_methods() {
+        use crate::input_processing::TreeSitterProcessor;
+        use crate::test_util::rust_entries;
+
+        let text_a = "impl X {\n    fn one(&self) -> usize {\n        self.value + 1\n    }\n\n    \
+            fn two(&self) {\n        println!(\"two\");\n    }\n}\n";
+        let text_b = "impl X {\n    fn two(&self) {\n        println!(\"two\");\n    }\n\n    \
+            fn one(&self) -> usize {\n        self.value + 1\n    }\n}\n";
+        let (entries_a, entries_b) = rust_entries(text_a, text_b, &TreeSitterProcessor::default());
+        let config = DiffConfig {
+            detect_moves: true,
+            ..Default::default()
+        };
+        let hunks = compute_edit_script(&entries_a, &entries_b, &config).unwrap();
+        assert!(
+            hunks
+                .0
+                .iter()
+                .any(|hunk| matches!(hunk, RichHunk::Moved(_))),
+            "{hunks:#?}"
+        );
+
+        // Moves are only reported if they're long enough
+        let config = DiffConfig {
+            detect_moves: true,
+            min_move_len: usize::MAX,
+            ..Default::default()
+        };
+        let hunks = compute_edit_script(&entries_a, &entries_b, &config).unwrap();
+        assert!(
+            !hunks
+                .0
+                .iter()
+                .any(|hunk| matches!(hunk, RichHunk::Moved(_))),
+            "{hunks:#?}"
+        );
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn detect_moves_across_blank_lines() {
+        use crate::input_processing::{Granularity, TreeSitterProcessor};
+        use crate::test_util::rust_entries;
+
+        // Neither half of `moved` is long enough to be a move on its own
+        let moved = "fn moved() {\n    let x = 1;\n\n    let y = 2;\n}\n";
+        let other = "fn other(first: usize, second: usize) -> usize {\n    first * second + 1\n}\n";
+        let text_a = format!("{moved}\n{other}");
+        let text_b = format!("{other}\n{moved}");
+        let processor = TreeSitterProcessor {
+            granularity: Granularity::Node,
+            ..Default::default()
+        };
+        let (entries_a, entries_b) = rust_entries(&text_a, &text_b, &processor);
+        let config = DiffConfig {
+            detect_moves: true,
+            ..Default::default()
+        };
+        let hunks = compute_edit_script(&entries_a, &entries_b, &config).unwrap();
+        let moved_hunks: Vec<&MovedHunk> = hunks
+            .0
+            .iter()
+            .filter_map(|hunk| match hunk {
+                RichHunk::Moved(moved_hunk) => Some(moved_hunk),
+                _ => None,
+            })
+            .collect();
+        let [moved_hunk] = moved_hunks.as_slice() else {
+            panic!("Expected a single moved hunk: {hunks:#?}");
+        };
+        let texts = |hunks: &Hunks| -> String {
+            hunks
+                .0
+                .iter()
+                .flat_map(Hunk::entries)
+                .map(|entry| entry.text.as_ref())
+                .collect()
+        };
+        // The diff is free to align the braces and keywords at the edges of the functions
+        for text in [texts(&moved_hunk.old), texts(&moved_hunk.new)] {
+            assert!(text.contains("moved(){letx=1;lety=2;"), "{text}");
+        }
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn pair_hunks_pairs_replaced_lines() {
+        use crate::input_processing::TreeSitterProcessor;
+        use crate::test_util::rust_entries;
+
+        let text_a = "fn main() {\n    let x = 1;\n}\n\nfn other() {}\n";
+        let text_b = "fn main() {\n    let x = 2;\n}\n\nfn other() {}\nfn added() {}\n";
+        let (entries_a, entries_b) = rust_entries(text_a, text_b, &TreeSitterProcessor::default());
+        let config = DiffConfig {
+            pair_hunks: true,
+            ..Default::default()
+        };
+        let hunks = compute_edit_script(&entries_a, &entries_b, &config).unwrap();
+
+        // The replaced literal is paired, the added function has nothing to pair with
+        assert_eq!(hunks.0.len(), 2, "{hunks:#?}");
+        assert!(
+            matches!(&hunks.0[0], RichHunk::Changed(hunk) if hunk.old.first_line() == Some(1)),
+            "{hunks:#?}"
+        );
+        assert!(matches!(&hunks.0[1], RichHunk::New(_)), "{hunks:#?}");
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn verify_hunks_reproduces_new_document() {
+        use crate::input_processing::{Granularity, TreeSitterProcessor};
+        use crate::test_util::rust_entries;
+
+        let text_a = "fn main() {\n    let x = 1;\n    foo(x);\n}\n";
+        let text_b = "fn main() {\n    let x = 2;\n    foo(x, y);\n    bar();\n}\n";
+
+        for granularity in [Granularity::Grapheme, Granularity::Word, Granularity::Node] {
+            let processor = TreeSitterProcessor {
+                granularity,
+                ..Default::default()
+            };
+            let (entries_a, entries_b) = rust_entries(text_a, text_b, &processor);
+            let mut hunks =
+                compute_edit_script(&entries_a, &entries_b, &DiffConfig::default()).unwrap();
+            verify_hunks(text_a, &entries_a, text_b, &entries_b, &hunks).unwrap();
+
+            // An edit script that's missing a hunk can't reproduce the new document
+            hunks.0.pop();
+            assert!(verify_hunks(text_a, &entries_a, text_b, &entries_b, &hunks).is_err());
+        }
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn similarity_is_weighted_by_kind() {
+        use crate::input_processing::{Granularity, TreeSitterProcessor};
+        use crate::test_util::rust_entries;
+
+        let text_a = "fn main() { foo(); }\n";
+        let text_b = "fn main() { bar(); }\n";
+        let processor = TreeSitterProcessor {
+            granularity: Granularity::Node,
+            ..Default::default()
+        };
+        let (entries_a, entries_b) = rust_entries(text_a, text_b, &processor);
+
+        // 9 of the 10 entries in each document are unchanged
+        let config = DiffConfig::default();
+        p_assert_eq!(similarity(&entries_a, &entries_b, &config), 0.9);
+        p_assert_eq!(similarity(&entries_a, &entries_a, &config), 1.0);
+
+        let config = DiffConfig {
+            similarity_weights: HashMap::from([("identifier".to_string(), 0)]),
+            ..Default::default()
+        };
+        p_assert_eq!(similarity(&entries_a, &entries_b, &config), 1.0);
+    }
 }
@@ -0,0 +1,367 @@
+//! Decoding documents that aren't UTF-8 and recording how their text is formatted.
+//!
+//! Tree-sitter and the diff engines work on UTF-8 text, so documents are decoded before they're
+//! parsed. A byte order mark is removed and line endings are kept as they are, so the byte offsets
+//! of entries are always offsets into the decoded text. Text that's written back out, like the
+//! result of a merge, is [encoded](encode) in the format of the document it came from.
+
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::Path};
+use strum::Display;
+use thiserror::Error;
+
+/// The UTF-8 byte order mark
+const UTF8_BOM: [u8; 3] = [0xEF, 0xBB, 0xBF];
+
+/// The UTF-16 little endian byte order mark
+const UTF16_LE_BOM: [u8; 2] = [0xFF, 0xFE];
+
+/// The UTF-16 big endian byte order mark
+const UTF16_BE_BOM: [u8; 2] = [0xFE, 0xFF];
+
+/// The encodings that documents can be decoded from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize, Display)]
+pub enum Encoding {
+    #[default]
+    #[serde(rename = "utf-8")]
+    #[strum(serialize = "UTF-8")]
+    Utf8,
+
+    #[serde(rename = "utf-16le")]
+    #[strum(serialize = "UTF-16LE")]
+    Utf16Le,
+
+    #[serde(rename = "utf-16be")]
+    #[strum(serialize = "UTF-16BE")]
+    Utf16Be,
+
+    /// ISO-8859-1, where every byte is the Unicode code point with the same value
+    #[serde(rename = "latin-1")]
+    #[strum(serialize = "Latin-1")]
+    Latin1,
+}
+
+/// The line endings that a document uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Display)]
+#[serde(rename_all = "snake_case")]
+pub enum LineEnding {
+    /// The document doesn't have any line breaks
+    #[default]
+    #[strum(serialize = "none")]
+    None,
+
+    /// Every line ends with `\n`
+    #[strum(serialize = "LF")]
+    Lf,
+
+    /// Every line ends with `\r\n`
+    #[strum(serialize = "CRLF")]
+    Crlf,
+
+    /// Some lines end with `\n` and others with `\r\n`
+    #[strum(serialize = "mixed")]
+    Mixed,
+}
+
+impl LineEnding {
+    /// Find the line endings that a text uses.
+    #[must_use]
+    pub fn of(text: &str) -> Self {
+        let line_breaks = text.matches('\n').count();
+        let crlf = text.matches("\r\n").count();
+
+        match (line_breaks, crlf) {
+            (0, _) => LineEnding::None,
+            (_, 0) => LineEnding::Lf,
+            (n, crlf) if n == crlf => LineEnding::Crlf,
+            _ => LineEnding::Mixed,
+        }
+    }
+}
+
+/// How the text of a document was stored before it was decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize)]
+pub struct TextFormat {
+    /// The encoding the document was decoded from
+    pub encoding: Encoding,
+
+    /// Whether the document started with a byte order mark
+    pub bom: bool,
+
+    /// The line endings of the document
+    pub line_ending: LineEnding,
+}
+
+impl TextFormat {
+    /// Describe how the format of a document changed from `self` to `new`.
+    ///
+    /// These changes don't show up in a diff of the entries, since the text is decoded and
+    /// whitespace is usually stripped, so renderers can use this to mention them.
+    #[must_use]
+    pub fn changes(&self, new: &TextFormat) -> Vec<String> {
+        let mut changes = Vec::new();
+
+        if self.encoding != new.encoding {
+            changes.push(format!(
+                "encoding changed from {} to {}",
+                self.encoding, new.encoding
+            ));
+        }
+        match (self.bom, new.bom) {
+            (false, true) => changes.push("byte order mark added".to_string()),
+            (true, false) => changes.push("byte order mark removed".to_string()),
+            _ => (),
+        }
+        // A document without line breaks doesn't have a line ending style to change
+        if self.line_ending != new.line_ending
+            && self.line_ending != LineEnding::None
+            && new.line_ending != LineEnding::None
+        {
+            changes.push(format!(
+                "line endings changed from {} to {}",
+                self.line_ending, new.line_ending
+            ));
+        }
+        changes
+    }
+}
+
+/// The possible errors that can arise when decoding a document
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("Failed to read the file")]
+    Io(#[from] io::Error),
+
+    #[error(
+        "The text isn't valid UTF-8 (at byte {0}), set `grammar.fallback-encoding` to decode it with another encoding"
+    )]
+    InvalidUtf8(usize),
+
+    #[error("The text isn't valid {0}")]
+    InvalidUtf16(Encoding),
+}
+
+/// The possible errors that can arise when encoding a document
+#[derive(Debug, Error)]
+pub enum EncodeError {
+    #[error("The character {character:?} can't be encoded as {encoding}")]
+    Unrepresentable { character: char, encoding: Encoding },
+}
+
+/// Read a file and decode it.
+///
+/// See [`decode`] for how the encoding is detected.
+///
+/// # Errors
+///
+/// Returns an error if the file can't be read or decoded.
+pub fn read_file(path: &Path, fallback: Encoding) -> Result<(String, TextFormat), DecodeError> {
+    decode(&fs::read(path)?, fallback)
+}
+
+/// Decode the bytes of a document.
+///
+/// The encoding is detected from the byte order mark if there is one, which is removed from the
+/// text. Otherwise the bytes are decoded as UTF-8, and with the `fallback` encoding if they
+/// aren't valid UTF-8 or if they contain null bytes.
+///
+/// # Errors
+///
+/// Returns an error if the bytes aren't valid in the detected encoding.
+pub fn decode(bytes: &[u8], fallback: Encoding) -> Result<(String, TextFormat), DecodeError> {
+    let (encoding, bom, text) = if let Some(rest) = bytes.strip_prefix(&UTF8_BOM) {
+        (Encoding::Utf8, true, rest)
+    } else if let Some(rest) = bytes.strip_prefix(&UTF16_LE_BOM) {
+        (Encoding::Utf16Le, true, rest)
+    } else if let Some(rest) = bytes.strip_prefix(&UTF16_BE_BOM) {
+        (Encoding::Utf16Be, true, rest)
+    } else if std::str::from_utf8(bytes).is_ok()
+        // UTF-16 text without a byte order mark is often valid UTF-8, but it's full of null bytes,
+        // which text in other encodings rarely has
+        && (fallback == Encoding::Utf8 || !bytes.contains(&0))
+    {
+        (Encoding::Utf8, false, bytes)
+    } else {
+        (fallback, false, bytes)
+    };
+    let text = decode_with(encoding, text)?;
+    let format = TextFormat {
+        encoding,
+        bom,
+        line_ending: LineEnding::of(&text),
+    };
+    Ok((text, format))
+}
+
+/// Encode text in the given format.
+///
+/// This is the reverse of [`decode`]: the text is encoded with the format's encoding, prefixed
+/// with a byte order mark if the format has one, and its line breaks are converted to the format's
+/// line endings. Text in a format with mixed line endings or without line breaks keeps its line
+/// endings as they are.
+///
+/// # Errors
+///
+/// Returns an error if the text contains a character that the encoding can't represent.
+pub fn encode(text: &str, format: &TextFormat) -> Result<Vec<u8>, EncodeError> {
+    let text = match format.line_ending {
+        LineEnding::Lf => text.replace("\r\n", "\n"),
+        LineEnding::Crlf => text.replace("\r\n", "\n").replace('\n', "\r\n"),
+        LineEnding::None | LineEnding::Mixed => text.to_string(),
+    };
+    let bom: &[u8] = match (format.bom, format.encoding) {
+        (false, _) | (true, Encoding::Latin1) => &[],
+        (true, Encoding::Utf8) => &UTF8_BOM,
+        (true, Encoding::Utf16Le) => &UTF16_LE_BOM,
+        (true, Encoding::Utf16Be) => &UTF16_BE_BOM,
+    };
+    let mut bytes = bom.to_vec();
+
+    match format.encoding {
+        Encoding::Utf8 => bytes.extend_from_slice(text.as_bytes()),
+        Encoding::Utf16Le => bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes)),
+        Encoding::Utf16Be => bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes)),
+        Encoding::Latin1 => {
+            for character in text.chars() {
+                let byte = u8::try_from(character).map_err(|_| EncodeError::Unrepresentable {
+                    character,
+                    encoding: Encoding::Latin1,
+                })?;
+                bytes.push(byte);
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+/// Decode bytes with a known encoding.
+fn decode_with(encoding: Encoding, bytes: &[u8]) -> Result<String, DecodeError> {
+    let to_unit: fn([u8; 2]) -> u16 = match encoding {
+        Encoding::Utf8 => {
+            return String::from_utf8(bytes.to_vec())
+                .map_err(|e| DecodeError::InvalidUtf8(e.utf8_error().valid_up_to()));
+        }
+        Encoding::Latin1 => return Ok(bytes.iter().map(|&byte| char::from(byte)).collect()),
+        Encoding::Utf16Le => u16::from_le_bytes,
+        Encoding::Utf16Be => u16::from_be_bytes,
+    };
+    if bytes.len() % 2 != 0 {
+        return Err(DecodeError::InvalidUtf16(encoding));
+    }
+    let units = bytes
+        .chunks_exact(2)
+        .map(|pair| to_unit([pair[0], pair[1]]));
+    char::decode_utf16(units)
+        .collect::<Result<String, _>>()
+        .map_err(|_| DecodeError::InvalidUtf16(encoding))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("", LineEnding::None ; "empty")]
+    #[test_case("a", LineEnding::None ; "single line")]
+    #[test_case("a\nb\n", LineEnding::Lf ; "lf")]
+    #[test_case("a\r\nb\r\n", LineEnding::Crlf ; "crlf")]
+    #[test_case("a\r\nb\n", LineEnding::Mixed ; "mixed")]
+    fn test_line_ending(text: &str, expected: LineEnding) {
+        assert_eq!(LineEnding::of(text), expected);
+    }
+
+    #[test_case(b"fn a() {}\n", Encoding::Utf8, false ; "utf-8")]
+    #[test_case(b"\xEF\xBB\xBFfn a() {}\n", Encoding::Utf8, true ; "utf-8 bom")]
+    #[test_case(b"\xFF\xFEf\0n\0 \0a\0(\0)\0 \0{\0}\0\n\0", Encoding::Utf16Le, true ; "utf-16le bom")]
+    #[test_case(b"\xFE\xFF\0f\0n\0 \0a\0(\0)\0 \0{\0}\0\n", Encoding::Utf16Be, true ; "utf-16be bom")]
+    fn test_decode(bytes: &[u8], encoding: Encoding, bom: bool) {
+        let (text, format) = decode(bytes, Encoding::Utf8).unwrap();
+        assert_eq!(text, "fn a() {}\n");
+        assert_eq!(
+            format,
+            TextFormat {
+                encoding,
+                bom,
+                line_ending: LineEnding::Lf,
+            }
+        );
+    }
+
+    #[test]
+    fn test_decode_fallback() {
+        let latin1 = b"caf\xE9\r\n";
+        assert!(matches!(
+            decode(latin1, Encoding::Utf8),
+            Err(DecodeError::InvalidUtf8(3))
+        ));
+        let (text, format) = decode(latin1, Encoding::Latin1).unwrap();
+        assert_eq!(text, "café\r\n");
+        assert_eq!(format.encoding, Encoding::Latin1);
+        assert_eq!(format.line_ending, LineEnding::Crlf);
+        let (text, format) = decode(b"a\0\n\0", Encoding::Utf16Le).unwrap();
+        assert_eq!(text, "a\n");
+        assert_eq!(format.encoding, Encoding::Utf16Le);
+        assert!(matches!(
+            decode(b"\xFF\xFEa", Encoding::Utf8),
+            Err(DecodeError::InvalidUtf16(Encoding::Utf16Le))
+        ));
+    }
+
+    #[test]
+    fn test_format_changes() {
+        let old = TextFormat {
+            encoding: Encoding::Utf8,
+            bom: false,
+            line_ending: LineEnding::Lf,
+        };
+        let new = TextFormat {
+            encoding: Encoding::Utf16Le,
+            bom: true,
+            line_ending: LineEnding::Crlf,
+        };
+        assert!(old.changes(&old).is_empty());
+        assert_eq!(
+            old.changes(&new),
+            [
+                "encoding changed from UTF-8 to UTF-16LE",
+                "byte order mark added",
+                "line endings changed from LF to CRLF",
+            ]
+        );
+    }
+
+    #[test_case(b"fn a() {}\n" ; "utf-8")]
+    #[test_case(b"\xEF\xBB\xBFfn a() {}\r\n" ; "utf-8 bom crlf")]
+    #[test_case(b"\xFF\xFEf\0n\0 \0a\0(\0)\0 \0{\0}\0\n\0" ; "utf-16le bom")]
+    #[test_case(b"\xFE\xFF\0f\0n\0 \0a\0(\0)\0 \0{\0}\0\r\0\n" ; "utf-16be bom crlf")]
+    fn test_encode_round_trip(bytes: &[u8]) {
+        let (text, format) = decode(bytes, Encoding::Utf8).unwrap();
+        assert_eq!(encode(&text, &format).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_encode() {
+        let format = TextFormat {
+            encoding: Encoding::Latin1,
+            bom: false,
+            line_ending: LineEnding::Crlf,
+        };
+        // Line breaks are converted to the line endings of the format
+        assert_eq!(
+            encode("caf\u{e9}\na\r\n", &format).unwrap(),
+            b"caf\xE9\r\na\r\n"
+        );
+        assert!(matches!(
+            encode("\u{2603}", &format),
+            Err(EncodeError::Unrepresentable {
+                character: '\u{2603}',
+                encoding: Encoding::Latin1,
+            })
+        ));
+        let format = TextFormat {
+            line_ending: LineEnding::Lf,
+            ..Default::default()
+        };
+        assert_eq!(encode("a\r\nb\n", &format).unwrap(), b"a\nb\n");
+    }
+}
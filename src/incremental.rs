@@ -0,0 +1,624 @@
+//! Incremental diffs of a document that is being edited.
+//!
+//! Editor integrations diff the same buffer against a base document every time the buffer
+//! changes. Instead of reparsing and diffing from scratch, [`IncrementalDiff`] reparses the buffer
+//! with the tree from the previous parse and only diffs the entries around the edited region
+//! again, reusing the rest of the previous edit script.
+//!
+//! Only parsing and the diff itself are incremental. The entries of both documents are still
+//! generated from their whole trees for every diff, so each update takes time proportional to the
+//! size of the document, even if that's much less time than a diff from scratch.
+
+use crate::diff::{DiffConfig, DiffOutput, edit_script, entry_index, hunks_from_edit_script};
+use crate::input_processing::{EditType, Entry, TreeSitterProcessor, VectorData};
+use crate::parse::{GrammarConfig, LoadingError, generate_language};
+use anyhow::Result;
+use log::{debug, info};
+use logging_timer::time;
+use std::cell::RefCell;
+use std::ops::Range;
+use thiserror::Error;
+use tree_sitter::Range as TSRange;
+use tree_sitter::{InputEdit, Parser, Tree};
+
+/// The errors that can arise when applying an edit to a document.
+#[derive(Debug, Error)]
+pub enum EditError {
+    #[error("The edit ends at byte {end} but the document is only {len} bytes long")]
+    OutOfBounds { end: usize, len: usize },
+
+    #[error("The edited text should be {expected} bytes long but it is {actual} bytes long")]
+    TextLength { expected: usize, actual: usize },
+
+    #[error("Failed to parse the edited document")]
+    ParseFailure,
+}
+
+/// The edit script from the last diff, which is reused by the next diff.
+struct PreviousDiff {
+    /// The edit script, as indices into the base and current entries
+    edits: Vec<EditType<usize>>,
+
+    /// The pairs of entries in the base and current document that the edit script left unchanged
+    matches: Vec<(usize, usize)>,
+
+    /// The number of entries in the base document
+    base_len: usize,
+
+    /// The number of entries in the current document
+    current_len: usize,
+
+    /// The tree of the current document, with every edit since the diff applied to it
+    tree: Tree,
+
+    /// The region of the current document that was edited since the diff
+    edited: Option<TSRange>,
+}
+
+/// The part of the previous edit script that is still valid for the current document.
+struct ReusedDiff {
+    /// The matches before the edited region
+    prefix: Vec<(usize, usize)>,
+
+    /// The matches after the edited region, as indices into the current entries
+    suffix: Vec<(usize, usize)>,
+
+    /// The edits outside of the edited region, as indices into the current entries
+    edits: Vec<EditType<usize>>,
+}
+
+/// A diff between a base document and a current document that is edited in place.
+///
+/// The current document is reparsed incrementally when it's [edited](IncrementalDiff::edit). When
+/// it's [diffed](IncrementalDiff::diff) the entries that were unchanged in the previous diff and
+/// that aren't in a region that was edited or parsed differently are kept, and only the entries
+/// between them are diffed again. This means the result can differ slightly from a diff from
+/// scratch, just like the diff of a file that was edited in a few places can differ from the diff
+/// of the whole file.
+///
+/// The entry vectors aren't generated incrementally: [`IncrementalDiff::current_entries`]
+/// processes the whole tree of the current document every time, which is cheap compared to
+/// parsing and diffing but still scales with the size of the document.
+pub struct IncrementalDiff {
+    base: VectorData,
+    current: VectorData,
+    parser: Parser,
+    processor: TreeSitterProcessor,
+    config: DiffConfig,
+    previous: RefCell<Option<PreviousDiff>>,
+}
+
+impl IncrementalDiff {
+    /// Create an incremental diff between two parsed documents.
+    ///
+    /// The current document is reparsed with the language it was parsed with. Embedded code isn't
+    /// reparsed when the current document is edited, so the injections of both documents are
+    /// dropped and embedded code is diffed as part of the leaves that contain it.
+    pub fn new(
+        mut base: VectorData,
+        mut current: VectorData,
+        grammar_config: &GrammarConfig,
+        processor: TreeSitterProcessor,
+        config: DiffConfig,
+    ) -> Result<Self, LoadingError> {
+        let mut parser = Parser::new();
+        parser.set_language(&generate_language(
+            &current.resolved_language,
+            grammar_config,
+        )?)?;
+        base.injections.clear();
+        current.injections.clear();
+        Ok(IncrementalDiff {
+            base,
+            current,
+            parser,
+            processor,
+            config,
+            previous: RefCell::new(None),
+        })
+    }
+
+    /// The base document.
+    #[must_use]
+    pub fn base(&self) -> &VectorData {
+        &self.base
+    }
+
+    /// The current document, with every edit applied to it.
+    #[must_use]
+    pub fn current(&self) -> &VectorData {
+        &self.current
+    }
+
+    /// Generate the entries of the base document.
+    #[must_use]
+    pub fn base_entries(&self) -> Vec<Entry<'_>> {
+        self.processor.process_vec_data(&self.base)
+    }
+
+    /// Generate the entries of the current document.
+    #[must_use]
+    pub fn current_entries(&self) -> Vec<Entry<'_>> {
+        self.processor.process_vec_data(&self.current)
+    }
+
+    /// Apply an edit to the current document and reparse it.
+    ///
+    /// `text` is the full text of the document after the edit, and `edit` describes how it
+    /// differs from the text before the edit.
+    pub fn edit(&mut self, edit: &InputEdit, text: String) -> Result<(), EditError> {
+        let len = self.current.text.len();
+
+        if edit.start_byte > edit.old_end_byte.min(edit.new_end_byte) || edit.old_end_byte > len {
+            return Err(EditError::OutOfBounds {
+                end: edit.old_end_byte,
+                len,
+            });
+        }
+        let expected = len - edit.old_end_byte + edit.new_end_byte;
+
+        if text.len() != expected {
+            return Err(EditError::TextLength {
+                expected,
+                actual: text.len(),
+            });
+        }
+        self.current.tree.edit(edit);
+        let tree = self
+            .parser
+            .parse(&text, Some(&self.current.tree))
+            .ok_or(EditError::ParseFailure)?;
+
+        if let Some(previous) = self.previous.get_mut() {
+            previous.tree.edit(edit);
+            let edited = TSRange {
+                start_byte: edit.start_byte,
+                end_byte: edit.new_end_byte,
+                start_point: edit.start_position,
+                end_point: edit.new_end_position,
+            };
+            previous.edited = Some(match previous.edited {
+                Some(region) => union(edited, edit_range(region, edit)),
+                None => edited,
+            });
+        }
+        self.current.text = text;
+        self.current.tree = tree;
+        Ok(())
+    }
+
+    /// Diff the base document against the current document.
+    ///
+    /// Only the entries in the region that changed since the last diff are diffed again, but
+    /// every entry is still compared with the previous diff to find that region.
+    ///
+    /// `base` and `current` have to be the entries from [`IncrementalDiff::base_entries`] and
+    /// [`IncrementalDiff::current_entries`].
+    #[time("info", "incremental::{}")]
+    pub fn diff<'a>(
+        &'a self,
+        base: &'a [Entry<'a>],
+        current: &'a [Entry<'a>],
+    ) -> Result<DiffOutput<'a>> {
+        let reused = self
+            .previous
+            .borrow_mut()
+            .take()
+            .and_then(|previous| self.reuse(&previous, base, current))
+            .unwrap_or_else(|| ReusedDiff {
+                prefix: Vec::new(),
+                suffix: Vec::new(),
+                edits: Vec::new(),
+            });
+        let (base_start, current_start) = reused
+            .prefix
+            .last()
+            .map_or((0, 0), |&(a, b)| (a + 1, b + 1));
+        let (base_end, current_end) = reused
+            .suffix
+            .first()
+            .copied()
+            .unwrap_or((base.len(), current.len()));
+        info!(
+            "Diffing entries {base_start}..{base_end} of the base document against entries \
+            {current_start}..{current_end} of the current document"
+        );
+        let (window_edits, strategy) = edit_script(
+            &base[base_start..base_end],
+            &current[current_start..current_end],
+            &self.config,
+        );
+        let window_edits: Vec<EditType<usize>> = window_edits
+            .into_iter()
+            .map(|edit| match edit {
+                EditType::Deletion(entry) => EditType::Deletion(entry_index(base, entry)),
+                EditType::Addition(entry) => EditType::Addition(entry_index(current, entry)),
+            })
+            .collect();
+        let window_matches = unchanged_pairs(
+            base_start..base_end,
+            current_start..current_end,
+            &window_edits,
+        );
+
+        // The reused edits are in document order, so the window goes between the edits before
+        // and after it
+        let (before, after): (Vec<_>, Vec<_>) =
+            reused.edits.into_iter().partition(|edit| match edit {
+                EditType::Deletion(i) => *i < base_start,
+                EditType::Addition(j) => *j < current_start,
+            });
+        let edits: Vec<EditType<usize>> = before
+            .into_iter()
+            .chain(window_edits)
+            .chain(after)
+            .collect();
+        let matches = reused
+            .prefix
+            .into_iter()
+            .chain(window_matches)
+            .chain(reused.suffix)
+            .collect();
+        let edit_script = edits
+            .iter()
+            .map(|edit| match *edit {
+                EditType::Deletion(i) => EditType::Deletion(&base[i]),
+                EditType::Addition(j) => EditType::Addition(&current[j]),
+            })
+            .collect();
+        *self.previous.borrow_mut() = Some(PreviousDiff {
+            edits,
+            matches,
+            base_len: base.len(),
+            current_len: current.len(),
+            tree: self.current.tree.clone(),
+            edited: None,
+        });
+        let hunks = hunks_from_edit_script(base, current, edit_script, &self.config)?;
+        Ok(DiffOutput { hunks, strategy })
+    }
+
+    /// Find the part of the previous diff that can be reused for the current entries.
+    ///
+    /// Entries that end before the changed region of the current document or start after it are
+    /// the same entries they were in the previous diff, just shifted. This returns [None] if
+    /// nothing can be reused.
+    fn reuse(
+        &self,
+        previous: &PreviousDiff,
+        base: &[Entry],
+        current: &[Entry],
+    ) -> Option<ReusedDiff> {
+        if previous.base_len != base.len() {
+            return None;
+        }
+        let changed = previous
+            .tree
+            .changed_ranges(&self.current.tree)
+            .chain(previous.edited)
+            .reduce(union);
+        let (prefix_len, suffix_len) = match changed {
+            Some(changed) => (
+                current.partition_point(|entry| entry.end_position <= changed.start_point),
+                current.len()
+                    - current.partition_point(|entry| entry.start_position < changed.end_point),
+            ),
+            None => (current.len(), 0),
+        };
+
+        if prefix_len + suffix_len > previous.current_len {
+            debug!("The changed region doesn't line up with the previous diff");
+            return None;
+        }
+        // The first entry after the changed region in the previous diff
+        let previous_suffix = previous.current_len - suffix_len;
+        let shift = |j: usize| j + current.len() - previous.current_len;
+        let prefix: Vec<(usize, usize)> = previous
+            .matches
+            .iter()
+            .copied()
+            .filter(|&(_, j)| j < prefix_len)
+            .collect();
+        let suffix: Vec<(usize, usize)> = previous
+            .matches
+            .iter()
+            .filter(|&&(_, j)| j >= previous_suffix)
+            .map(|&(i, j)| (i, shift(j)))
+            .collect();
+
+        if prefix
+            .iter()
+            .chain(&suffix)
+            .any(|&(i, j)| base[i] != current[j])
+        {
+            debug!("The entries around the changed region don't match the previous diff");
+            return None;
+        }
+        let base_start = prefix.last().map_or(0, |&(i, _)| i + 1);
+        let base_end = suffix.first().map_or(base.len(), |&(i, _)| i);
+        let current_start = prefix.last().map_or(0, |&(_, j)| j + 1);
+        let previous_end = previous
+            .matches
+            .iter()
+            .find(|&&(_, j)| j >= previous_suffix)
+            .map_or(previous.current_len, |&(_, j)| j);
+        let edits = previous
+            .edits
+            .iter()
+            .filter_map(|edit| match *edit {
+                EditType::Deletion(i) => {
+                    (i < base_start || i >= base_end).then_some(EditType::Deletion(i))
+                }
+                EditType::Addition(j) if j < current_start => Some(EditType::Addition(j)),
+                EditType::Addition(j) if j >= previous_end => Some(EditType::Addition(shift(j))),
+                EditType::Addition(_) => None,
+            })
+            .collect();
+        info!(
+            "Reusing {} matches before and {} matches after the changed region",
+            prefix.len(),
+            suffix.len()
+        );
+        Some(ReusedDiff {
+            prefix,
+            suffix,
+            edits,
+        })
+    }
+}
+
+/// Pair up the entries in two ranges that an edit script leaves unchanged.
+fn unchanged_pairs(
+    base: Range<usize>,
+    current: Range<usize>,
+    edits: &[EditType<usize>],
+) -> Vec<(usize, usize)> {
+    let mut base_changed = vec![false; base.len()];
+    let mut current_changed = vec![false; current.len()];
+
+    for edit in edits {
+        match *edit {
+            EditType::Deletion(i) => base_changed[i - base.start] = true,
+            EditType::Addition(j) => current_changed[j - current.start] = true,
+        }
+    }
+    let unchanged = |range: Range<usize>, changed: Vec<bool>| {
+        range
+            .zip(changed)
+            .filter(|(_, changed)| !changed)
+            .map(|(i, _)| i)
+    };
+    unchanged(base, base_changed)
+        .zip(unchanged(current, current_changed))
+        .collect()
+}
+
+/// The smallest range that covers both ranges.
+fn union(a: TSRange, b: TSRange) -> TSRange {
+    let (start_byte, start_point) = if a.start_byte <= b.start_byte {
+        (a.start_byte, a.start_point)
+    } else {
+        (b.start_byte, b.start_point)
+    };
+    let (end_byte, end_point) = if a.end_byte >= b.end_byte {
+        (a.end_byte, a.end_point)
+    } else {
+        (b.end_byte, b.end_point)
+    };
+    TSRange {
+        start_byte,
+        end_byte,
+        start_point,
+        end_point,
+    }
+}
+
+/// Move a range of the document to where it is after an edit.
+fn edit_range(mut range: TSRange, edit: &InputEdit) -> TSRange {
+    edit.edit_point(&mut range.start_point, &mut range.start_byte);
+    edit.edit_point(&mut range.end_point, &mut range.end_byte);
+    range
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "static-grammar-libs")]
+    use super::*;
+    #[cfg(feature = "static-grammar-libs")]
+    use crate::encoding::TextFormat;
+
+    /// Parse a rust document.
+    #[cfg(feature = "static-grammar-libs")]
+    fn document(text: &str) -> VectorData {
+        let language = generate_language("rust", &GrammarConfig::default()).unwrap();
+        let mut parser = Parser::new();
+        parser.set_language(&language).unwrap();
+        VectorData {
+            text: text.to_string(),
+            tree: parser.parse(text, None).unwrap(),
+            path: "test.rs".into(),
+            resolved_language: "rust".into(),
+            injections: Vec::new(),
+            format: TextFormat::default(),
+        }
+    }
+
+    /// Replace a range of a document, returning the edit and the edited text.
+    #[cfg(feature = "static-grammar-libs")]
+    fn replace(text: &str, range: Range<usize>, replacement: &str) -> (InputEdit, String) {
+        let point = |text: &str, byte: usize| tree_sitter::Point {
+            row: text[..byte].matches('\n').count(),
+            column: byte - text[..byte].rfind('\n').map_or(0, |i| i + 1),
+        };
+        let new_text = format!(
+            "{}{replacement}{}",
+            &text[..range.start],
+            &text[range.end..]
+        );
+        let new_end = range.start + replacement.len();
+        let edit = InputEdit {
+            start_byte: range.start,
+            old_end_byte: range.end,
+            new_end_byte: new_end,
+            start_position: point(text, range.start),
+            old_end_position: point(text, range.end),
+            new_end_position: point(&new_text, new_end),
+        };
+        (edit, new_text)
+    }
+
+    /// Diff the current document incrementally and check that it's the same as a diff from scratch.
+    #[cfg(feature = "static-grammar-libs")]
+    fn assert_matches_diff_from_scratch(diff: &IncrementalDiff) {
+        use crate::diff::compute_diff;
+
+        let base_entries = diff.base_entries();
+        let current_entries = diff.current_entries();
+        let incremental = diff.diff(&base_entries, &current_entries).unwrap();
+        let from_scratch =
+            compute_diff(&base_entries, &current_entries, &DiffConfig::default()).unwrap();
+        assert_eq!(incremental, from_scratch, "{}", diff.current().text);
+    }
+
+    /// Create an incremental diff where both documents start out as `text`.
+    #[cfg(feature = "static-grammar-libs")]
+    fn unchanged_diff(text: &str) -> IncrementalDiff {
+        IncrementalDiff::new(
+            document(text),
+            document(text),
+            &GrammarConfig::default(),
+            TreeSitterProcessor::default(),
+            DiffConfig::default(),
+        )
+        .unwrap()
+    }
+
+    /// Replace the text of the current document in `range`.
+    #[cfg(feature = "static-grammar-libs")]
+    fn edit(diff: &mut IncrementalDiff, range: Range<usize>, replacement: &str) {
+        let (edit, text) = replace(&diff.current().text, range, replacement);
+        diff.edit(&edit, text).unwrap();
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn incremental_diff_matches_diff_from_scratch() {
+        use crate::diff::compute_diff;
+
+        let base = "fn main() {\n    let x = 1;\n    foo(x);\n}\n\nfn other() {\n    bar();\n}\n";
+        let config = DiffConfig::default();
+        let mut diff = IncrementalDiff::new(
+            document(base),
+            document(base),
+            &GrammarConfig::default(),
+            TreeSitterProcessor::default(),
+            config.clone(),
+        )
+        .unwrap();
+        let edits = [
+            ("foo(x", ", y"),
+            ("bar(", "1"),
+            ("let x = 1", ";\n    let z = \"a"),
+            ("let x = 1;\n    let z = \"a", "\""),
+        ];
+
+        for (anchor, insertion) in edits {
+            let text = &diff.current().text;
+            let offset = text.find(anchor).unwrap() + anchor.len();
+            let (edit, text) = replace(text, offset..offset, insertion);
+            diff.edit(&edit, text).unwrap();
+
+            let base_entries = diff.base_entries();
+            let current_entries = diff.current_entries();
+            let incremental = diff.diff(&base_entries, &current_entries).unwrap();
+            let from_scratch = compute_diff(&base_entries, &current_entries, &config).unwrap();
+            assert_eq!(incremental, from_scratch, "{}", diff.current().text);
+        }
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn edit_checks_text_length() {
+        let text = "fn main() {}\n";
+        let mut diff = IncrementalDiff::new(
+            document(text),
+            document(text),
+            &GrammarConfig::default(),
+            TreeSitterProcessor::default(),
+            DiffConfig::default(),
+        )
+        .unwrap();
+        let (edit, _) = replace(text, 0..2, "pub fn");
+        assert!(matches!(
+            diff.edit(&edit, text.to_string()),
+            Err(EditError::TextLength {
+                expected: 17,
+                actual: 13
+            })
+        ));
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn incremental_diff_with_several_edits_between_diffs() {
+        let base = "fn main() {\n    let x = 1;\n    foo(x);\n}\n\nfn other() {\n    bar();\n}\n";
+        let mut diff = unchanged_diff(base);
+        assert_matches_diff_from_scratch(&diff);
+
+        // Edits far apart from each other, applied back to front and front to back
+        let offset = diff.current().text.find("bar(").unwrap() + 4;
+        edit(&mut diff, offset..offset, "y");
+        let offset = diff.current().text.find("foo(x").unwrap() + 5;
+        edit(&mut diff, offset..offset, ", z");
+        assert_matches_diff_from_scratch(&diff);
+
+        let offset = diff.current().text.find("1").unwrap();
+        edit(&mut diff, offset..offset + 1, "2");
+        let offset = diff.current().text.find("other").unwrap();
+        edit(&mut diff, offset..offset + 5, "renamed");
+        let offset = diff.current().text.find("let").unwrap();
+        edit(&mut diff, offset..offset + 3, "const");
+        assert_matches_diff_from_scratch(&diff);
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn incremental_diff_of_deletions() {
+        let base = "fn main() {\n    let x = 1;\n    foo(x, y);\n}\n\nstruct Other;\n";
+        let mut diff = unchanged_diff(base);
+        assert_matches_diff_from_scratch(&diff);
+
+        let deletions = [", y", "    let x = 1;\n", "\nstruct Other;\n"];
+
+        for deleted in deletions {
+            let offset = diff.current().text.find(deleted).unwrap();
+            edit(&mut diff, offset..offset + deleted.len(), "");
+            assert_matches_diff_from_scratch(&diff);
+        }
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn incremental_diff_at_document_edges() {
+        let base = "fn main() {\n    foo();\n}\n";
+        let mut diff = unchanged_diff(base);
+        assert_matches_diff_from_scratch(&diff);
+
+        edit(&mut diff, 0..0, "use a::b;\n\n");
+        assert_matches_diff_from_scratch(&diff);
+
+        let len = diff.current().text.len();
+        edit(&mut diff, len..len, "\nconst LAST: u8 = 0;\n");
+        assert_matches_diff_from_scratch(&diff);
+
+        // Replace the first and the last leaves of the document
+        edit(&mut diff, 0..3, "pub use");
+        let len = diff.current().text.len();
+        edit(&mut diff, len - 3..len, "1;\n");
+        assert_matches_diff_from_scratch(&diff);
+
+        let len = diff.current().text.len();
+        edit(&mut diff, 0..len, "");
+        assert_matches_diff_from_scratch(&diff);
+    }
+}
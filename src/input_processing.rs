@@ -3,16 +3,25 @@
 //! These methods handle preprocessing the input data so it can be fed into the diff engines to
 //! compute diff data.
 
+use crate::ast_navigation::{NavigationError, compile_query, query_capture_ranges};
+use crate::encoding::TextFormat;
+use crate::parse::Injection;
+use log::warn;
 use logging_timer::time;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
-use std::ops::{Deref, DerefMut};
+use std::ops::{Deref, DerefMut, Range};
+use std::str::FromStr;
 use std::{cell::RefCell, ops::Index, path::PathBuf};
+use thiserror::Error;
+use tree_sitter::Language;
 use tree_sitter::Node as TSNode;
 use tree_sitter::Point;
 use tree_sitter::Tree as TSTree;
+use unicode_normalization::UnicodeNormalization;
 use unicode_segmentation as us;
 
 #[cfg(test)]
@@ -29,11 +38,10 @@ trait TSNodeTrait {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case", default)]
 pub struct TreeSitterProcessor {
-    /// Whether we should split the nodes graphemes.
+    /// How finely to split the text of each leaf into entries.
     ///
-    /// If this is disabled, then the direct tree-sitter nodes will be used and diffs will be less
-    /// granular. This has the advantage of being faster and using less memory.
-    pub split_graphemes: bool,
+    /// Coarser granularities make diffs less granular, but are faster and use less memory.
+    pub granularity: Granularity,
 
     /// The kinds of nodes to exclude from processing. This takes precedence over `include_kinds`.
     ///
@@ -58,23 +66,251 @@ pub struct TreeSitterProcessor {
     /// This is useful for grammars like markdown, which has an "inline" node type should be diffed
     /// on. Users can set global leaf types using "all" as the language key.
     pub pseudo_leaf_types: HashMap<String, HashSet<String>>,
+
+    /// A mapping of node kinds to the normalizers to apply to their text before diffing.
+    ///
+    /// Keys can be node kinds, one of the categories `identifier`, `string`, `number` and
+    /// `comment`, which match node kinds that contain those words (or `integer` and `float` for
+    /// numbers), or `all` for every node. Normalizers are applied in order, starting with the ones
+    /// for `all`, then the ones for the node's category and then the ones for its kind.
+    ///
+    /// Normalized nodes aren't split into graphemes, since normalizing can change the length of
+    /// the text.
+    pub normalizers: HashMap<String, Vec<Normalizer>>,
+
+    /// A mapping of node kinds to the granularity to use for them instead of `granularity`.
+    ///
+    /// Keys can be node kinds or categories, like the keys of `normalizers`. The granularity for a
+    /// node's kind takes precedence over the one for its category. This lets comments and strings
+    /// be diffed word by word like prose while code is diffed by grapheme or by node.
+    pub kind_granularity: HashMap<String, Granularity>,
+
+    /// A mapping of tree sitter languages to tree-sitter queries that select nodes to ignore.
+    ///
+    /// Every leaf under a node that one of the queries for the document's language captures as
+    /// `@ignore` is dropped before diffing. This can ignore nodes more precisely than
+    /// `exclude_kinds`, such as doc attributes or logging calls.
+    pub ignore_queries: HashMap<String, Vec<String>>,
+
+    /// Regexes that mask volatile parts of the text of leaves, such as timestamps or UUIDs.
+    ///
+    /// Every match of a pattern is replaced with the same placeholder before diffing, so leaves
+    /// that only differ in the matched text compare as equal. Masked leaves aren't split into
+    /// graphemes or words.
+    pub ignore_patterns: Vec<IgnorePattern>,
+}
+
+/// How finely the text of a leaf is split into entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    /// Every leaf is a single entry
+    Node,
+
+    /// Leaves are split on Unicode word boundaries
+    Word,
+
+    /// Leaves are split into individual graphemes
+    #[default]
+    Grapheme,
 }
 
-// TODO: if we want to do any string transformations we need to store Cow strings.
-// Most strings won't be modified so it's fine to use a pointer. For the few we do
-// modify we'll need to store the direct string.
-// We should add some abstractions to do input processing.
+/// Input processing options that override the global [`TreeSitterProcessor`] options for one
+/// language.
+///
+/// Each option that is set replaces the global option, and the options that aren't set fall back
+/// to the global ones. Pseudo-leaf types are already configured per language, so they can't be
+/// overridden here, and neither can ignore queries.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct ProcessorOverrides {
+    /// Overrides [`TreeSitterProcessor::granularity`]
+    pub granularity: Option<Granularity>,
+
+    /// Overrides [`TreeSitterProcessor::exclude_kinds`]
+    pub exclude_kinds: Option<HashSet<String>>,
+
+    /// Overrides [`TreeSitterProcessor::include_kinds`]
+    pub include_kinds: Option<HashSet<String>>,
+
+    /// Overrides [`TreeSitterProcessor::strip_whitespace`]
+    pub strip_whitespace: Option<bool>,
+
+    /// Overrides [`TreeSitterProcessor::normalizers`]
+    pub normalizers: Option<HashMap<String, Vec<Normalizer>>>,
+
+    /// Overrides [`TreeSitterProcessor::kind_granularity`]
+    pub kind_granularity: Option<HashMap<String, Granularity>>,
+
+    /// Overrides [`TreeSitterProcessor::ignore_patterns`]
+    pub ignore_patterns: Option<Vec<IgnorePattern>>,
+}
+
+/// A regex that masks the parts of the text of leaves that it matches.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct IgnorePattern {
+    /// The regex to match against the text of each leaf
+    pub regex: ConfigRegex,
+
+    /// The node kinds or categories that the pattern applies to, or every node if this isn't set
+    ///
+    /// Categories are the same as the ones for [`TreeSitterProcessor::normalizers`].
+    #[serde(default)]
+    pub kinds: Option<HashSet<String>>,
+}
+
+impl IgnorePattern {
+    /// Whether the pattern applies to a node kind.
+    fn applies_to(&self, kind: &str) -> bool {
+        self.kinds.as_ref().is_none_or(|kinds| {
+            kinds.contains(kind) || kind_categories(kind).any(|category| kinds.contains(category))
+        })
+    }
+}
+
+/// A compiled regex that's read from and written to the config as its pattern.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ConfigRegex(pub Regex);
+
+impl TryFrom<String> for ConfigRegex {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        Regex::new(&pattern).map(ConfigRegex)
+    }
+}
+
+impl From<ConfigRegex> for String {
+    fn from(regex: ConfigRegex) -> Self {
+        regex.0.as_str().to_string()
+    }
+}
+
+impl PartialEq for ConfigRegex {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for ConfigRegex {}
+
+/// A transformation that's applied to the text of a node before diffing, so that nodes with
+/// different text can compare as equal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Normalizer {
+    /// Compare text case-insensitively, which is useful for grammars like SQL
+    Lowercase,
+
+    /// Treat single quotes, double quotes and backticks around strings as equal
+    Quotes,
+
+    /// Compare integer literals by their value, so `0x10` and `16` or `1_000` and `1000` are equal
+    Numbers,
+
+    /// Apply Unicode NFC normalization
+    Nfc,
+}
+
+/// The name of the capture that selects the nodes to ignore in [`TreeSitterProcessor::ignore_queries`].
+const IGNORE_CAPTURE: &str = "ignore";
+
+/// The placeholder that [`TreeSitterProcessor::ignore_patterns`] replace their matches with.
+const IGNORED_TEXT: &str = "<ignored>";
+
+/// The characters that [`Normalizer::Quotes`] treats as equal.
+const QUOTES: [char; 3] = ['"', '\'', '`'];
+
+/// The categories of node kinds that processing options can be configured for, along with the
+/// words that the node kinds in each category contain.
+///
+/// Grammars name their nodes differently, so this lets users configure options for all kinds of
+/// strings, for example, without listing every grammar's node kinds.
+const KIND_CATEGORIES: [(&str, &[&str]); 4] = [
+    ("identifier", &["identifier"]),
+    ("string", &["string"]),
+    ("number", &["number", "integer", "float"]),
+    ("comment", &["comment"]),
+];
+
+/// The categories that a node kind belongs to, not including a category with the same name as the
+/// kind.
+fn kind_categories(kind: &str) -> impl Iterator<Item = &str> {
+    KIND_CATEGORIES
+        .iter()
+        .filter(move |(category, words)| {
+            *category != kind && words.iter().any(|word| kind.contains(word))
+        })
+        .map(|(category, _)| *category)
+}
+
+impl Normalizer {
+    /// Apply the normalizer to a string.
+    #[must_use]
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            Normalizer::Lowercase => text.to_lowercase(),
+            Normalizer::Quotes => normalize_quotes(text),
+            Normalizer::Numbers => normalize_number(text),
+            Normalizer::Nfc => text.nfc().collect(),
+        }
+    }
+}
+
+/// Replace the quotes at the start and end of a string with double quotes.
+fn normalize_quotes(text: &str) -> String {
+    let leading = text.len() - text.trim_start_matches(QUOTES).len();
+    let trailing = text.len() - text.trim_end_matches(QUOTES).len();
+
+    // The text is only quotes, like the delimiter nodes some grammars have for strings
+    if leading + trailing >= text.len() {
+        return "\"".repeat(text.len());
+    }
+    format!(
+        "{}{}{}",
+        "\"".repeat(leading),
+        &text[leading..text.len() - trailing],
+        "\"".repeat(trailing)
+    )
+}
+
+/// Write an integer literal in decimal without digit separators.
+///
+/// Literals that aren't integers, like floats, only have their digit separators removed.
+fn normalize_number(text: &str) -> String {
+    let digits: String = text.chars().filter(|&c| c != '_').collect();
+    let lowercase = digits.to_ascii_lowercase();
+    let value = [("0x", 16), ("0o", 8), ("0b", 2)]
+        .into_iter()
+        .find_map(|(prefix, radix)| {
+            lowercase
+                .strip_prefix(prefix)
+                .map(|rest| u128::from_str_radix(rest, radix))
+        })
+        .unwrap_or_else(|| lowercase.parse());
+
+    match value {
+        Ok(value) => value.to_string(),
+        Err(_) => digits,
+    }
+}
 
 impl Default for TreeSitterProcessor {
     fn default() -> Self {
         let pseudo_leaf_types: HashMap<String, HashSet<String>> =
             HashMap::from([("markdown".into(), HashSet::from(["inline".into()]))]);
         Self {
-            split_graphemes: true,
+            granularity: Granularity::default(),
             exclude_kinds: None,
             include_kinds: None,
             strip_whitespace: true,
             pseudo_leaf_types,
+            normalizers: HashMap::new(),
+            kind_granularity: HashMap::new(),
+            ignore_queries: HashMap::new(),
+            ignore_patterns: Vec::new(),
         }
     }
 }
@@ -89,54 +325,338 @@ impl TSNodeTrait for TSNodeWrapper<'_> {
 }
 
 impl TreeSitterProcessor {
+    /// Return a copy of these options with the options that are set in `overrides` replaced.
+    #[must_use]
+    pub fn with_overrides(&self, overrides: &ProcessorOverrides) -> Self {
+        let mut processor = self.clone();
+
+        if let Some(granularity) = overrides.granularity {
+            processor.granularity = granularity;
+        }
+        if let Some(exclude_kinds) = &overrides.exclude_kinds {
+            processor.exclude_kinds = Some(exclude_kinds.clone());
+        }
+        if let Some(include_kinds) = &overrides.include_kinds {
+            processor.include_kinds = Some(include_kinds.clone());
+        }
+        if let Some(strip_whitespace) = overrides.strip_whitespace {
+            processor.strip_whitespace = strip_whitespace;
+        }
+        if let Some(normalizers) = &overrides.normalizers {
+            processor.normalizers.clone_from(normalizers);
+        }
+        if let Some(kind_granularity) = &overrides.kind_granularity {
+            processor.kind_granularity.clone_from(kind_granularity);
+        }
+        if let Some(ignore_patterns) = &overrides.ignore_patterns {
+            processor.ignore_patterns.clone_from(ignore_patterns);
+        }
+        processor
+    }
+
     /// Convenience function to invoke process from a vector data object.
     ///
     /// This was done because dealing with lifetimes is awkward if you're passing references from
     /// the same struct, and having this makes some of the code in `bin/diffsitter.rs` less
     /// redundant.
     pub fn process_vec_data<'a>(&self, vec_data: &'a VectorData) -> Vec<Entry<'a>> {
-        self.process(&vec_data.tree, &vec_data.text, &vec_data.resolved_language)
+        self.process_node(
+            vec_data.tree.root_node(),
+            &vec_data.text,
+            &vec_data.resolved_language,
+            &vec_data.injections,
+        )
+    }
+
+    /// Process the whole document of a [`VectorData`], processing embedded code with the options
+    /// that `processor_for` returns for its language.
+    pub fn process_vec_data_with<'a, 'p>(
+        &self,
+        vec_data: &'a VectorData,
+        processor_for: impl Fn(&str) -> Cow<'p, TreeSitterProcessor>,
+    ) -> Vec<Entry<'a>> {
+        self.process_node_with(
+            vec_data.tree.root_node(),
+            &vec_data.text,
+            &vec_data.resolved_language,
+            &vec_data.injections,
+            processor_for,
+        )
     }
 
     #[time("info", "ast::{}")]
     pub fn process<'a>(&self, tree: &'a TSTree, text: &'a str, lang_name: &str) -> Vec<Entry<'a>> {
-        let empty_set: HashSet<String> = HashSet::new();
-        let pseudo_leaf_types = self.pseudo_leaf_types.get(lang_name).unwrap_or(&empty_set);
-        let ast_vector = from_ts_tree(tree, text, pseudo_leaf_types);
-        let iter = ast_vector
-            .leaves
-            .iter()
-            .filter(|leaf| self.should_include_node(&TSNodeWrapper(leaf.reference)));
-        // Splitting on graphemes generates a vector of entries instead of a direct mapping, which
-        // is why we have the branching here
-        if self.split_graphemes {
-            iter.flat_map(|leaf| leaf.split_on_graphemes(self.strip_whitespace))
+        self.process_node(tree.root_node(), text, lang_name, &[])
+    }
+
+    /// Process the subtree rooted at `node` instead of an entire document.
+    ///
+    /// `text` is the text of the whole document that the node belongs to, so the positions of the
+    /// resulting entries are relative to the document rather than the start of the node.
+    ///
+    /// The leaves of the node that are inside of the embedded code of `injections` are replaced
+    /// with the leaves of the embedded code, which are processed with the same options as the rest
+    /// of the node. Use [`Self::process_node_with`] to process embedded code with the options for
+    /// its language.
+    pub fn process_node<'a>(
+        &self,
+        node: TSNode<'a>,
+        text: &'a str,
+        lang_name: &str,
+        injections: &'a [Injection],
+    ) -> Vec<Entry<'a>> {
+        self.process_node_with(node, text, lang_name, injections, |_| Cow::Borrowed(self))
+    }
+
+    /// Process the subtree rooted at `node`, processing embedded code with the options that
+    /// `processor_for` returns for its language.
+    ///
+    /// See [`Self::process_node`] for how the node and embedded code are processed.
+    pub fn process_node_with<'a, 'p>(
+        &self,
+        node: TSNode<'a>,
+        text: &'a str,
+        lang_name: &str,
+        injections: &'a [Injection],
+        processor_for: impl Fn(&str) -> Cow<'p, TreeSitterProcessor>,
+    ) -> Vec<Entry<'a>> {
+        let mut leaves = self.leaves(node, text, lang_name);
+        let mut injected_entries = Vec::new();
+
+        for injection in injections {
+            let ranges = injection.tree.included_ranges();
+            if !ranges.iter().all(|range| {
+                node.start_byte() <= range.start_byte && range.end_byte <= node.end_byte()
+            }) {
+                continue;
+            }
+            leaves.retain(|leaf| {
+                let leaf_range = leaf.reference.byte_range();
+                !ranges.iter().any(|range| {
+                    range.start_byte <= leaf_range.start && leaf_range.end <= range.end_byte
+                })
+            });
+            injected_entries.extend(processor_for(&injection.language).process_node(
+                injection.tree.root_node(),
+                text,
+                &injection.language,
+                &[],
+            ));
+        }
+        let ignored = self.ignored_ranges(node, text, lang_name);
+        let iter = leaves.iter().filter(|leaf| {
+            let range = leaf.reference.byte_range();
+            self.should_include_node(&TSNodeWrapper(leaf.reference))
+                && !ignored
+                    .iter()
+                    .any(|ignored| ignored.start <= range.start && range.end <= ignored.end)
+        });
+        // Splitting leaves generates a vector of entries instead of a direct mapping, which is why
+        // we have the branching here
+        let mut entries: Vec<Entry<'a>> =
+            if self.granularity == Granularity::Node && self.kind_granularity.is_empty() {
+                iter.map(|&x| self.process_leaf(x)).collect()
+            } else {
+                iter.flat_map(|&leaf| {
+                    let kind = leaf.reference.kind();
+                    let granularity = self.granularity_for(kind);
+
+                    if granularity == Granularity::Node
+                        || !self.normalizers_for(kind).is_empty()
+                        || self.masks(kind, leaf.text)
+                    {
+                        vec![self.process_leaf(leaf)]
+                    } else {
+                        leaf.split(granularity, self.strip_whitespace)
+                    }
+                })
                 .collect()
-        } else {
-            iter.map(|&x| self.process_leaf(x)).collect()
+            };
+        if !injected_entries.is_empty() {
+            entries.extend(injected_entries);
+            // The sort is stable, so the entries that a leaf was split into stay in order
+            entries.sort_by_key(|entry| entry.start_position);
         }
+        entries
+    }
+
+    /// Split plain text into entries for words, punctuation and whitespace.
+    ///
+    /// This is the fallback for documents that don't have a grammar. Words are runs of
+    /// alphanumeric characters and underscores and every other grapheme is its own punctuation
+    /// entry. Runs of whitespace are dropped if `strip_whitespace` is set, and line breaks never
+    /// become entries. Plain text is always split into tokens, regardless of `granularity`.
+    #[time("info", "ast::{}")]
+    pub fn process_text<'a>(&self, text: &'a str) -> Vec<Entry<'a>> {
+        let mut entries = Vec::new();
+
+        for (row, line) in text.lines().enumerate() {
+            let mut graphemes = us::UnicodeSegmentation::grapheme_indices(line, true).peekable();
+
+            while let Some((start, grapheme)) = graphemes.next() {
+                let kind = TextKind::of(grapheme);
+                let mut end = start + grapheme.len();
+
+                if kind != TextKind::Punctuation {
+                    while let Some((idx, next)) =
+                        graphemes.next_if(|(_, next)| TextKind::of(next) == kind)
+                    {
+                        end = idx + next.len();
+                    }
+                }
+
+                if (self.strip_whitespace && kind == TextKind::Whitespace)
+                    || !self.should_include_node(&kind)
+                {
+                    continue;
+                }
+                entries.push(Entry {
+                    reference: None,
+                    text: self.normalize(kind.name(), Cow::from(&line[start..end])),
+                    start_position: Point { row, column: start },
+                    end_position: Point { row, column: end },
+                    kind_id: kind as u16,
+                });
+            }
+        }
+        entries
     }
 
     /// Process a vector leaf and turn it into an [Entry].
     ///
     /// This applies input processing according to the user provided options.
     fn process_leaf<'a>(&self, leaf: VectorLeaf<'a>) -> Entry<'a> {
-        let new_text = if self.strip_whitespace {
-            // This includes newlines
-            Cow::from(leaf.text.trim())
-        } else {
-            Cow::from(leaf.text)
-        };
+        let kind = leaf.reference.kind();
+
+        if !self.strip_whitespace {
+            let mut entry = Entry::from(leaf);
+            entry.text = self.normalize(kind, entry.text);
+            return entry;
+        }
+        // This includes newlines
+        let new_text = leaf.text.trim();
+        let leading_whitespace = leaf.text.len() - leaf.text.trim_start().len();
+        let start_position = advance_point(
+            leaf.reference.start_position(),
+            &leaf.text[..leading_whitespace],
+        );
 
         Entry {
-            reference: leaf.reference,
-            text: new_text,
-            start_position: leaf.reference.start_position(),
-            end_position: leaf.reference.start_position(),
+            reference: Some(leaf.reference),
+            text: self.normalize(kind, Cow::from(new_text)),
+            start_position,
+            end_position: advance_point(start_position, new_text),
             kind_id: leaf.reference.kind_id(),
         }
     }
 
+    /// Get the normalizers that apply to a node kind, in the order they should be applied.
+    fn normalizers_for(&self, kind: &str) -> Vec<Normalizer> {
+        if self.normalizers.is_empty() {
+            return Vec::new();
+        }
+        std::iter::once("all")
+            .chain(kind_categories(kind))
+            .chain(std::iter::once(kind))
+            .filter_map(|key| self.normalizers.get(key))
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    /// Get the granularity to split a node kind with.
+    fn granularity_for(&self, kind: &str) -> Granularity {
+        if self.kind_granularity.is_empty() {
+            return self.granularity;
+        }
+        std::iter::once(kind)
+            .chain(kind_categories(kind))
+            .find_map(|key| self.kind_granularity.get(key))
+            .copied()
+            .unwrap_or(self.granularity)
+    }
+
+    /// Apply the normalizers for a node kind to the text of an entry.
+    ///
+    /// The text is masked with the ignore patterns before it's normalized. This only allocates if
+    /// there are normalizers for the node kind or an ignore pattern matches the text.
+    fn normalize<'a>(&self, kind: &str, text: Cow<'a, str>) -> Cow<'a, str> {
+        let text = self.mask(kind, text);
+        self.normalizers_for(kind)
+            .into_iter()
+            .fold(text, |text, normalizer| Cow::from(normalizer.apply(&text)))
+    }
+
+    /// Whether an ignore pattern for a node kind matches the text.
+    fn masks(&self, kind: &str, text: &str) -> bool {
+        self.ignore_patterns
+            .iter()
+            .any(|pattern| pattern.applies_to(kind) && pattern.regex.0.is_match(text))
+    }
+
+    /// Replace the matches of the ignore patterns for a node kind with a placeholder.
+    fn mask<'a>(&self, kind: &str, text: Cow<'a, str>) -> Cow<'a, str> {
+        self.ignore_patterns
+            .iter()
+            .filter(|pattern| pattern.applies_to(kind))
+            .fold(text, |text, pattern| {
+                if pattern.regex.0.is_match(&text) {
+                    Cow::from(
+                        pattern
+                            .regex
+                            .0
+                            .replace_all(&text, IGNORED_TEXT)
+                            .into_owned(),
+                    )
+                } else {
+                    text
+                }
+            })
+    }
+
+    /// Collect the leaves of the subtree rooted at `node`.
+    fn leaves<'a>(&self, node: TSNode<'a>, text: &'a str, lang_name: &str) -> Vec<VectorLeaf<'a>> {
+        let empty_set: HashSet<String> = HashSet::new();
+        let pseudo_leaf_types = self.pseudo_leaf_types.get(lang_name).unwrap_or(&empty_set);
+        from_ts_node(node, text, pseudo_leaf_types).leaves
+    }
+
+    /// Check that the ignore queries for a language compile.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NavigationError::InvalidQuery`] for the first query that doesn't compile.
+    pub fn check_ignore_queries(
+        &self,
+        language: &Language,
+        lang_name: &str,
+    ) -> Result<(), NavigationError> {
+        for query in self.ignore_queries.get(lang_name).into_iter().flatten() {
+            compile_query(language, query)?;
+        }
+        Ok(())
+    }
+
+    /// The byte ranges of the nodes under `node` that the ignore queries for a language capture.
+    ///
+    /// Queries that don't compile are skipped with a warning, since processing can't fail. Use
+    /// [`Self::check_ignore_queries`] to report them.
+    fn ignored_ranges(&self, node: TSNode, text: &str, lang_name: &str) -> Vec<Range<usize>> {
+        let Some(queries) = self.ignore_queries.get(lang_name) else {
+            return Vec::new();
+        };
+        let language = node.language();
+        queries
+            .iter()
+            .filter_map(|query| {
+                query_capture_ranges(node, text, &language, query, IGNORE_CAPTURE)
+                    .inspect_err(|e| warn!("Skipping an ignore query for {lang_name}: {e}"))
+                    .ok()
+            })
+            .flatten()
+            .collect()
+    }
+
     /// A helper method to determine whether a node type should be filtered out based on the user's filtering
     /// preferences.
     ///
@@ -156,24 +676,38 @@ impl TreeSitterProcessor {
     }
 }
 
-/// Create a `DiffVector` from a `tree_sitter` tree
+/// Create a `DiffVector` from the subtree rooted at a `tree_sitter` node
 ///
 /// This method calls a helper function that does an in-order traversal of the tree and adds
 /// leaf nodes to a vector
 #[time("info", "ast::{}")]
-fn from_ts_tree<'a>(
-    tree: &'a TSTree,
+fn from_ts_node<'a>(
+    node: TSNode<'a>,
     text: &'a str,
     pseudo_leaf_types: &HashSet<String>,
 ) -> Vector<'a> {
     let leaves = RefCell::new(Vec::new());
-    build(&leaves, tree.root_node(), text, pseudo_leaf_types);
+    build(&leaves, node, text, pseudo_leaf_types);
     Vector {
         leaves: leaves.into_inner(),
         source_text: text,
     }
 }
 
+/// Find the position at the end of `text`, if `text` starts at `point`.
+fn advance_point(point: Point, text: &str) -> Point {
+    match text.rfind('\n') {
+        Some(last_newline) => Point {
+            row: point.row + text.matches('\n').count(),
+            column: text.len() - last_newline - 1,
+        },
+        None => Point {
+            row: point.row,
+            column: point.column + text.len(),
+        },
+    }
+}
+
 /// The leaves of an AST vector
 ///
 /// This is used as an intermediate struct for flattening the tree structure.
@@ -203,9 +737,9 @@ pub struct Entry<'node> {
     /// The node an entry in the diff vector refers to
     ///
     /// We keep a reference to the leaf node so that we can easily grab the text and other metadata
-    /// surrounding the syntax
+    /// surrounding the syntax. Entries that were split from plain text don't have a node.
     #[serde(skip_serializing)]
-    pub reference: TSNode<'node>,
+    pub reference: Option<TSNode<'node>>,
 
     /// A reference to the text the node refers to
     ///
@@ -227,6 +761,8 @@ pub struct Entry<'node> {
 
     /// The cached `kind_id` from the `TSNode` reference.
     ///
+    /// Entries from plain text use the ID of their [`TextKind`].
+    ///
     /// Caching it here saves some time because it is queried repeatedly later. If we don't store
     /// it inline then we have to cross the FFI boundary which incurs some overhead.
     // PERF: Use cross language LTO to see if LLVM can optimize across the FFI boundary.
@@ -234,14 +770,15 @@ pub struct Entry<'node> {
 }
 
 impl<'a> VectorLeaf<'a> {
-    /// Split an entry into a vector of entries per grapheme.
+    /// Split an entry into a vector of entries per grapheme or word.
     ///
-    /// Each grapheme will get its own [Entry] struct. This method will resolve the
-    /// indices/positioning of each grapheme from the `self.text` field.
+    /// Each grapheme or word will get its own [Entry] struct. This method will resolve the
+    /// indices/positioning of each segment from the `self.text` field. Words are split on Unicode
+    /// word boundaries, so the whitespace and punctuation between words are segments of their own.
     ///
     /// This effectively maps out the byte position for each node from the unicode text, accounting
-    /// for both newlines and grapheme splits.
-    fn split_on_graphemes(self, strip_whitespace: bool) -> Vec<Entry<'a>> {
+    /// for both newlines and splits.
+    fn split(self, granularity: Granularity, strip_whitespace: bool) -> Vec<Entry<'a>> {
         let mut entries: Vec<Entry<'a>> = Vec::new();
 
         // We have to split lines because newline characters might be in the text for a tree sitter
@@ -251,8 +788,14 @@ impl<'a> VectorLeaf<'a> {
         let lines = self.text.lines();
 
         for (line_offset, line) in lines.enumerate() {
-            let indices: Vec<(usize, &str)> =
-                us::UnicodeSegmentation::grapheme_indices(line, true).collect();
+            let indices: Vec<(usize, &str)> = match granularity {
+                Granularity::Word => {
+                    us::UnicodeSegmentation::split_word_bound_indices(line).collect()
+                }
+                Granularity::Node | Granularity::Grapheme => {
+                    us::UnicodeSegmentation::grapheme_indices(line, true).collect()
+                }
+            };
             entries.reserve(entries.len() + indices.len());
 
             for (idx, grapheme) in indices {
@@ -283,7 +826,7 @@ impl<'a> VectorLeaf<'a> {
                 };
                 debug_assert!(new_start_pos.row <= new_end_pos.row);
                 let entry = Entry {
-                    reference: self.reference,
+                    reference: Some(self.reference),
                     text: Cow::from(&line[idx..idx + grapheme.len()]),
                     start_position: new_start_pos,
                     end_position: new_end_pos,
@@ -316,10 +859,10 @@ impl<'a> VectorLeaf<'a> {
 impl<'a> From<VectorLeaf<'a>> for Entry<'a> {
     fn from(leaf: VectorLeaf<'a>) -> Self {
         Self {
-            reference: leaf.reference,
+            reference: Some(leaf.reference),
             text: Cow::from(leaf.text),
             start_position: leaf.reference.start_position(),
-            end_position: leaf.reference.start_position(),
+            end_position: leaf.reference.end_position(),
             kind_id: leaf.reference.kind_id(),
         }
     }
@@ -337,6 +880,244 @@ impl Entry<'_> {
     pub fn end_position(&self) -> Point {
         self.end_position
     }
+
+    /// Get the last row that an entry covers.
+    ///
+    /// Entries can span multiple rows, for example block comments. An entry that ends at the start
+    /// of a row, because its text ends with a line break, doesn't cover that row.
+    #[must_use]
+    pub fn last_row(&self) -> usize {
+        if self.end_position.column == 0 && self.end_position.row > self.start_position.row {
+            self.end_position.row - 1
+        } else {
+            self.end_position.row
+        }
+    }
+
+    /// Get the ID of the leaf an entry was generated from.
+    ///
+    /// Entries that were split from the same leaf have the same ID.
+    #[must_use]
+    pub fn leaf_id(&self) -> LeafId {
+        match self.reference {
+            Some(node) => LeafId::Node(node.id()),
+            None => LeafId::Text(self.start_position),
+        }
+    }
+
+    /// Get the kind of the leaf an entry was generated from.
+    ///
+    /// This is the tree-sitter node type, or the name of the [`TextKind`] for plain text.
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self.reference {
+            Some(node) => node.kind(),
+            None => TextKind::from_id(self.kind_id).name(),
+        }
+    }
+
+    /// Whether the leaf an entry was generated from is a named node.
+    ///
+    /// Anonymous nodes are punctuation and keywords. Only words are named in plain text.
+    #[must_use]
+    pub fn is_named(&self) -> bool {
+        match self.reference {
+            Some(node) => node.is_named(),
+            None => self.kind_id == TextKind::Word as u16,
+        }
+    }
+}
+
+/// Identifies the leaf that an [Entry] was generated from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LeafId {
+    /// A leaf node in a syntax tree, identified by the ID of the node
+    Node(usize),
+
+    /// A token in plain text, identified by its start position
+    Text(Point),
+}
+
+/// A range of a document to diff.
+///
+/// Line ranges are written as `START:END`. Lines are numbered from 1 and both ends are inclusive,
+/// like the line numbers that editors and the renderers display.
+///
+/// Byte ranges are written as `bytes:START-END`. Byte offsets start at 0 and the end is exclusive,
+/// like tree-sitter byte ranges. They are offsets into the text of the document as UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentRange {
+    /// A range of lines
+    Lines {
+        /// The first line in the range
+        start: usize,
+
+        /// The last line in the range
+        end: usize,
+    },
+
+    /// A range of byte offsets
+    Bytes {
+        /// The offset of the first byte in the range
+        start: usize,
+
+        /// The offset just past the last byte in the range
+        end: usize,
+    },
+}
+
+/// The possible errors that can arise when parsing a [`DocumentRange`].
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum DocumentRangeError {
+    #[error(
+        "Expected a line range like `120:240` or a byte range like `bytes:1200-2400`, got `{0}`"
+    )]
+    Malformed(String),
+
+    #[error("Line numbers start at 1")]
+    ZeroLine,
+
+    #[error("The range ends at {end}, before it starts at {start}")]
+    Reversed { start: usize, end: usize },
+}
+
+impl FromStr for DocumentRange {
+    type Err = DocumentRangeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (bytes, separator, bounds) = match s.trim().strip_prefix("bytes:") {
+            Some(bounds) => (true, '-', bounds),
+            None => (false, ':', s),
+        };
+        let (start, end) = bounds
+            .split_once(separator)
+            .and_then(|(start, end)| Some((start.trim().parse().ok()?, end.trim().parse().ok()?)))
+            .ok_or_else(|| DocumentRangeError::Malformed(s.to_string()))?;
+
+        if end < start {
+            return Err(DocumentRangeError::Reversed { start, end });
+        }
+        if bytes {
+            return Ok(DocumentRange::Bytes { start, end });
+        }
+        if start == 0 {
+            return Err(DocumentRangeError::ZeroLine);
+        }
+        Ok(DocumentRange::Lines { start, end })
+    }
+}
+
+impl DocumentRange {
+    /// Find the points in `text` that the range starts and ends at.
+    #[must_use]
+    pub fn to_points(&self, text: &str) -> PointRange {
+        match *self {
+            DocumentRange::Lines { start, end } => PointRange {
+                start: Point::new(start - 1, 0),
+                end: Point::new(end, 0),
+            },
+            DocumentRange::Bytes { start, end } => PointRange {
+                start: byte_to_point(text, start),
+                end: byte_to_point(text, end),
+            },
+        }
+    }
+}
+
+/// Find the point of a byte offset in `text`, clamping offsets past the end of the text.
+fn byte_to_point(text: &str, offset: usize) -> Point {
+    let before = &text.as_bytes()[..offset.min(text.len())];
+    match before.iter().rposition(|&byte| byte == b'\n') {
+        Some(newline) => Point::new(
+            before.iter().filter(|&&byte| byte == b'\n').count(),
+            before.len() - newline - 1,
+        ),
+        None => Point::new(0, before.len()),
+    }
+}
+
+/// The span of a document that a [`DocumentRange`] covers.
+///
+/// The end is exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PointRange {
+    /// The first point in the range
+    pub start: Point,
+
+    /// The point just past the end of the range
+    pub end: Point,
+}
+
+impl PointRange {
+    /// Whether the span from `start` to `end` overlaps the range.
+    #[must_use]
+    pub fn overlaps(&self, start: Point, end: Point) -> bool {
+        start < self.end && self.start < end
+    }
+
+    /// Whether all of an entry is in the range.
+    #[must_use]
+    pub fn contains(&self, entry: &Entry) -> bool {
+        self.start <= entry.start_position
+            && entry.start_position < self.end
+            && entry.end_position <= self.end
+    }
+}
+
+/// The kinds of tokens that plain text is split into when there is no grammar for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum TextKind {
+    /// A run of alphanumeric characters and underscores
+    Word,
+
+    /// A single grapheme that isn't part of a word or whitespace
+    Punctuation,
+
+    /// A run of whitespace within a line
+    Whitespace,
+}
+
+impl TextKind {
+    /// Classify a grapheme.
+    fn of(grapheme: &str) -> Self {
+        if grapheme.chars().all(char::is_whitespace) {
+            TextKind::Whitespace
+        } else if grapheme
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_')
+        {
+            TextKind::Word
+        } else {
+            TextKind::Punctuation
+        }
+    }
+
+    /// Get the kind that corresponds to the `kind_id` of a plain text entry.
+    fn from_id(kind_id: u16) -> Self {
+        match kind_id {
+            0 => TextKind::Word,
+            1 => TextKind::Punctuation,
+            _ => TextKind::Whitespace,
+        }
+    }
+
+    /// The name of the kind, which is what `include_kinds` and `exclude_kinds` match against.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            TextKind::Word => "word",
+            TextKind::Punctuation => "punctuation",
+            TextKind::Whitespace => "whitespace",
+        }
+    }
+}
+
+impl TSNodeTrait for TextKind {
+    fn kind(&self) -> &str {
+        self.name()
+    }
 }
 
 impl<'a> From<&'a Vector<'a>> for Vec<Entry<'a>> {
@@ -344,7 +1125,7 @@ impl<'a> From<&'a Vector<'a>> for Vec<Entry<'a>> {
         ast_vector
             .leaves
             .iter()
-            .flat_map(|entry| entry.split_on_graphemes(true))
+            .flat_map(|entry| entry.split(Granularity::Grapheme, true))
             .collect()
     }
 }
@@ -383,6 +1164,12 @@ pub struct VectorData {
 
     /// The identifier for the language that was inferred.
     pub resolved_language: String,
+
+    /// The code from other languages that is embedded in the text
+    pub injections: Vec<Injection>,
+
+    /// How the text was stored in the file before it was decoded
+    pub format: TextFormat,
 }
 
 impl<'a> Vector<'a> {
@@ -438,6 +1225,15 @@ impl PartialEq for Entry<'_> {
     }
 }
 
+// This has to stay consistent with the `PartialEq` implementation, some of the diff engines
+// bucket entries by their hash.
+impl Hash for Entry<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.kind_id.hash(state);
+        self.text.hash(state);
+    }
+}
+
 impl PartialEq for Vector<'_> {
     fn eq(&self, other: &Vector) -> bool {
         if self.leaves.len() != other.leaves.len() {
@@ -539,10 +1335,13 @@ impl<T> DerefMut for EditType<T> {
 mod tests {
     use super::*;
     use crate::GrammarConfig;
+    use test_case::test_case;
     use tree_sitter::Parser;
 
     #[cfg(feature = "static-grammar-libs")]
     use crate::parse::generate_language;
+    #[cfg(feature = "static-grammar-libs")]
+    use crate::test_util::{parse_rust, rust_entries};
 
     #[test]
     fn test_should_filter_node() {
@@ -552,7 +1351,7 @@ mod tests {
 
         // basic scenario - expect that the excluded kind is ignored
         let processor = TreeSitterProcessor {
-            split_graphemes: false,
+            granularity: Granularity::Node,
             exclude_kinds: Some(exclude_kinds.clone()),
             include_kinds: None,
             ..Default::default()
@@ -561,7 +1360,7 @@ mod tests {
 
         // expect that it's still excluded if the included list also has an element that was excluded
         let processor = TreeSitterProcessor {
-            split_graphemes: false,
+            granularity: Granularity::Node,
             exclude_kinds: Some(exclude_kinds.clone()),
             include_kinds: Some(exclude_kinds),
             ..Default::default()
@@ -574,7 +1373,7 @@ mod tests {
             "yet another type".to_string(),
         ]);
         let processor = TreeSitterProcessor {
-            split_graphemes: false,
+            granularity: Granularity::Node,
             exclude_kinds: None,
             include_kinds: Some(include_kinds),
             ..Default::default()
@@ -584,7 +1383,7 @@ mod tests {
         // include our node type
         let include_kinds: HashSet<String> = HashSet::from(["comment".to_string()]);
         let processor = TreeSitterProcessor {
-            split_graphemes: false,
+            granularity: Granularity::Node,
             exclude_kinds: None,
             include_kinds: Some(include_kinds),
             ..Default::default()
@@ -593,7 +1392,7 @@ mod tests {
 
         // don't filter anything
         let processor = TreeSitterProcessor {
-            split_graphemes: false,
+            granularity: Granularity::Node,
             exclude_kinds: None,
             include_kinds: None,
             ..Default::default()
@@ -601,6 +1400,94 @@ mod tests {
         assert!(processor.should_include_node(&mock_node));
     }
 
+    #[test_case("", (3, 4) => (3, 4) ; "empty")]
+    #[test_case("foo", (3, 4) => (3, 7) ; "single line")]
+    #[test_case("foo\nbar baz", (3, 4) => (4, 7) ; "multiple lines")]
+    #[test_case("foo\r\n", (3, 4) => (4, 0) ; "trailing newline")]
+    fn test_advance_point(text: &str, (row, column): (usize, usize)) -> (usize, usize) {
+        let point = advance_point(Point { row, column }, text);
+        (point.row, point.column)
+    }
+
+    #[test_case(true, &["foo_bar", "(", "1", ")", "é", "x", "!"] ; "strip whitespace")]
+    #[test_case(false, &["foo_bar", "(", "1", ")", "  ", "é", " ", "x", "!"] ; "keep whitespace")]
+    fn test_process_text(strip_whitespace: bool, expected: &[&str]) {
+        let processor = TreeSitterProcessor {
+            strip_whitespace,
+            ..Default::default()
+        };
+        let entries = processor.process_text("foo_bar(1)  é\r\n x!\n");
+        let texts: Vec<&str> = entries.iter().map(|entry| entry.text.as_ref()).collect();
+        assert_eq!(texts, expected);
+
+        let last = entries.last().unwrap();
+        assert_eq!(last.start_position, Point { row: 1, column: 2 });
+        assert_eq!(last.end_position, Point { row: 1, column: 3 });
+        assert_eq!(last.kind(), "punctuation");
+        assert!(entries[0].is_named());
+        assert_ne!(entries[0].leaf_id(), entries[2].leaf_id());
+    }
+
+    #[test]
+    fn test_process_text_filters_kinds() {
+        let processor = TreeSitterProcessor {
+            exclude_kinds: Some(HashSet::from(["punctuation".to_string()])),
+            ..Default::default()
+        };
+        let entries = processor.process_text("a, b.");
+        let texts: Vec<&str> = entries.iter().map(|entry| entry.text.as_ref()).collect();
+        assert_eq!(texts, ["a", "b"]);
+    }
+
+    #[test_case("120:240" => Ok(DocumentRange::Lines { start: 120, end: 240 }) ; "range")]
+    #[test_case(" 7 : 7 " => Ok(DocumentRange::Lines { start: 7, end: 7 }) ; "single line")]
+    #[test_case("bytes:1200-2400" => Ok(DocumentRange::Bytes { start: 1200, end: 2400 }) ; "bytes")]
+    #[test_case("bytes:0-0" => Ok(DocumentRange::Bytes { start: 0, end: 0 }) ; "empty bytes")]
+    #[test_case("120" => Err(DocumentRangeError::Malformed("120".into())) ; "no end")]
+    #[test_case("a:b" => Err(DocumentRangeError::Malformed("a:b".into())) ; "not numbers")]
+    #[test_case("bytes:3:5" => Err(DocumentRangeError::Malformed("bytes:3:5".into())) ; "bytes with colon")]
+    #[test_case("0:3" => Err(DocumentRangeError::ZeroLine) ; "zero")]
+    #[test_case("5:3" => Err(DocumentRangeError::Reversed { start: 5, end: 3 }) ; "reversed")]
+    #[test_case("bytes:5-3" => Err(DocumentRangeError::Reversed { start: 5, end: 3 }) ; "reversed bytes")]
+    fn test_parse_document_range(range: &str) -> Result<DocumentRange, DocumentRangeError> {
+        range.parse()
+    }
+
+    #[test]
+    fn test_line_range_contains() {
+        let processor = TreeSitterProcessor::default();
+        let text = "a\nb\nc\nd\n";
+        let entries = processor.process_text(text);
+        let range = DocumentRange::Lines { start: 2, end: 3 }.to_points(text);
+        let texts: Vec<&str> = entries
+            .iter()
+            .filter(|entry| range.contains(entry))
+            .map(|entry| entry.text.as_ref())
+            .collect();
+        assert_eq!(texts, ["b", "c"]);
+
+        assert!(range.overlaps(Point::new(0, 0), Point::new(1, 1)));
+        assert!(range.overlaps(Point::new(2, 0), Point::new(5, 0)));
+        assert!(!range.overlaps(Point::new(3, 0), Point::new(5, 0)));
+    }
+
+    #[test_case(0, 4 => vec!["ab", "c"] ; "first line")]
+    #[test_case(5, 11 => vec!["de", "fg"] ; "second line")]
+    #[test_case(3, 7 => vec!["c", "de"] ; "across lines")]
+    #[test_case(6, 8 => Vec::<&str>::new() ; "inside a token")]
+    #[test_case(8, 100 => vec!["fg"] ; "past the end")]
+    fn test_byte_range_contains(start: usize, end: usize) -> Vec<String> {
+        let processor = TreeSitterProcessor::default();
+        let text = "ab c\nde fg\n";
+        let entries = processor.process_text(text);
+        let range = DocumentRange::Bytes { start, end }.to_points(text);
+        entries
+            .iter()
+            .filter(|entry| range.contains(entry))
+            .map(|entry| entry.text.to_string())
+            .collect()
+    }
+
     // NOTE: this has to be gated behind the 'static-grammar-libs' cargo feature, otherwise the
     // crate won't be built with the grammars bundled into the binary which means this won't be
     // able to load the markdown parser. It's possible that the markdown dynamic library is
@@ -636,4 +1523,244 @@ mod tests {
             assert_ne!(entries_a, entries_b);
         }
     }
+
+    #[test_case(Normalizer::Lowercase, "SELECT", "select")]
+    #[test_case(Normalizer::Quotes, "'x'", "\"x\"")]
+    #[test_case(Normalizer::Quotes, "`it's`", "\"it's\"")]
+    #[test_case(Normalizer::Quotes, "'''", "\"\"\"")]
+    #[test_case(Normalizer::Numbers, "0x10", "16")]
+    #[test_case(Normalizer::Numbers, "0B1_0000", "16")]
+    #[test_case(Normalizer::Numbers, "1_000", "1000")]
+    #[test_case(Normalizer::Numbers, "1_000.5", "1000.5")]
+    #[test_case(Normalizer::Numbers, "0x10u8", "0x10u8")]
+    #[test_case(Normalizer::Nfc, "e\u{301}", "\u{e9}")]
+    fn test_normalizer(normalizer: Normalizer, text: &str, expected: &str) {
+        assert_eq!(normalizer.apply(text), expected);
+    }
+
+    #[test_case(r#"{"granularity": "word"}"#, Granularity::Word)]
+    #[test_case(r#"{"granularity": "node"}"#, Granularity::Node)]
+    #[test_case("{}", Granularity::Grapheme)]
+    fn test_deserialize_granularity(config: &str, expected: Granularity) {
+        let processor: TreeSitterProcessor = serde_json::from_str(config).unwrap();
+        assert_eq!(processor.granularity, expected);
+    }
+
+    #[test]
+    fn test_ignore_patterns() {
+        let processor: TreeSitterProcessor = serde_json::from_str(
+            r#"{"ignore-patterns": [{"regex": "\\d{4}-\\d{2}-\\d{2}", "kinds": ["string"]}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            processor.mask("string_content", Cow::from("built on 2024-01-31")),
+            "built on <ignored>"
+        );
+        assert_eq!(
+            processor.mask("identifier", Cow::from("2024-01-31")),
+            "2024-01-31"
+        );
+        assert!(processor.masks("string", "2024-01-31"));
+        assert!(!processor.masks("string", "v1.2.3"));
+        assert!(
+            serde_json::from_str::<TreeSitterProcessor>(r#"{"ignore-patterns": [{"regex": "("}]}"#)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_normalizers_for() {
+        let processor = TreeSitterProcessor {
+            normalizers: HashMap::from([
+                ("all".into(), vec![Normalizer::Nfc]),
+                ("identifier".into(), vec![Normalizer::Lowercase]),
+                ("integer_literal".into(), vec![Normalizer::Numbers]),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            processor.normalizers_for("type_identifier"),
+            [Normalizer::Nfc, Normalizer::Lowercase]
+        );
+        assert_eq!(
+            processor.normalizers_for("identifier"),
+            [Normalizer::Nfc, Normalizer::Lowercase]
+        );
+        assert_eq!(
+            processor.normalizers_for("integer_literal"),
+            [Normalizer::Nfc, Normalizer::Numbers]
+        );
+        assert_eq!(processor.normalizers_for("comment"), [Normalizer::Nfc]);
+    }
+
+    #[test]
+    fn test_granularity_for() {
+        let processor = TreeSitterProcessor {
+            granularity: Granularity::Grapheme,
+            kind_granularity: HashMap::from([
+                ("comment".into(), Granularity::Word),
+                ("string".into(), Granularity::Word),
+                ("string_content".into(), Granularity::Node),
+                ("identifier".into(), Granularity::Node),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(processor.granularity_for("line_comment"), Granularity::Word);
+        assert_eq!(processor.granularity_for("comment"), Granularity::Word);
+        assert_eq!(
+            processor.granularity_for("string_fragment"),
+            Granularity::Word
+        );
+        assert_eq!(
+            processor.granularity_for("string_content"),
+            Granularity::Node
+        );
+        assert_eq!(
+            processor.granularity_for("type_identifier"),
+            Granularity::Node
+        );
+        assert_eq!(processor.granularity_for("+"), Granularity::Grapheme);
+    }
+
+    #[test]
+    fn test_process_text_normalizes_words() {
+        let processor = TreeSitterProcessor {
+            normalizers: HashMap::from([("word".into(), vec![Normalizer::Lowercase])]),
+            ..Default::default()
+        };
+        let entries = processor.process_text("Hello, World");
+        let texts: Vec<&str> = entries.iter().map(|entry| entry.text.as_ref()).collect();
+
+        assert_eq!(texts, ["hello", ",", "world"]);
+        assert_eq!(entries[2].start_position, Point::new(0, 7));
+        assert_eq!(entries[2].end_position, Point::new(0, 12));
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn test_process_node() {
+        let text = "fn first() {}\n\nfn second() {\n    call();\n}\n";
+        let tree = parse_rust(text);
+        let second = tree.root_node().named_child(1).unwrap();
+        let processor = TreeSitterProcessor {
+            granularity: Granularity::Node,
+            ..Default::default()
+        };
+        let entries = processor.process_node(second, text, "rust", &[]);
+        let texts: Vec<&str> = entries.iter().map(|entry| entry.text.as_ref()).collect();
+
+        assert_eq!(
+            texts,
+            ["fn", "second", "(", ")", "{", "call", "(", ")", ";", "}"]
+        );
+        // Positions are relative to the document rather than the node
+        assert_eq!(entries[0].start_position, Point::new(2, 0));
+        assert_eq!(entries[5].start_position, Point::new(3, 4));
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn test_word_granularity() {
+        let text = "let s = \"Hello, big world\";\nfoo_bar();\n";
+        let tree = parse_rust(text);
+        let processor = TreeSitterProcessor {
+            granularity: Granularity::Word,
+            ..Default::default()
+        };
+        let entries = processor.process(&tree, text, "rust");
+        let texts: Vec<&str> = entries.iter().map(|entry| entry.text.as_ref()).collect();
+
+        assert_eq!(
+            texts,
+            [
+                "let", "s", "=", "\"", "Hello", ",", "big", "world", "\"", ";", "foo_bar", "(",
+                ")", ";"
+            ]
+        );
+        assert_eq!(entries[4].start_position, Point::new(0, 9));
+        assert_eq!(entries[4].end_position, Point::new(0, 14));
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn test_kind_granularity() {
+        let text = "let foo_bar = \"hello big world\";\n";
+        let tree = parse_rust(text);
+        let processor = TreeSitterProcessor {
+            granularity: Granularity::Grapheme,
+            kind_granularity: HashMap::from([
+                ("string".into(), Granularity::Word),
+                ("identifier".into(), Granularity::Node),
+            ]),
+            ..Default::default()
+        };
+        let entries = processor.process(&tree, text, "rust");
+        let texts: Vec<&str> = entries.iter().map(|entry| entry.text.as_ref()).collect();
+
+        assert_eq!(
+            texts,
+            [
+                "l", "e", "t", "foo_bar", "=", "\"", "hello", "big", "world", "\"", ";"
+            ]
+        );
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn test_ignore_queries() {
+        let text = "#[doc = \"hidden\"]\nfn f() {\n    println!(\"{}\", x);\n    y();\n}\n";
+        let tree = parse_rust(text);
+        let processor = TreeSitterProcessor {
+            granularity: Granularity::Node,
+            ignore_queries: HashMap::from([(
+                "rust".into(),
+                vec![
+                    "(attribute_item) @ignore".into(),
+                    "((macro_invocation macro: (identifier) @name) @ignore (#eq? @name \"println\"))"
+                        .into(),
+                ],
+            )]),
+            ..Default::default()
+        };
+        processor
+            .check_ignore_queries(&tree.language(), "rust")
+            .unwrap();
+        let entries = processor.process(&tree, text, "rust");
+        let texts: Vec<&str> = entries.iter().map(|entry| entry.text.as_ref()).collect();
+
+        assert_eq!(
+            texts,
+            ["fn", "f", "(", ")", "{", ";", "y", "(", ")", ";", "}"]
+        );
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn test_ignore_patterns_mask_leaves() {
+        let text_a = "const BUILT: &str = \"2024-01-31 12:00\";\n";
+        let text_b = "const BUILT: &str = \"2025-06-01 08:30\";\n";
+        let processor: TreeSitterProcessor = serde_json::from_str(
+            r#"{"ignore-patterns": [{"regex": "\\d{4}-\\d{2}-\\d{2} \\d{2}:\\d{2}"}]}"#,
+        )
+        .unwrap();
+        let (entries_a, entries_b) = rust_entries(text_a, text_b, &processor);
+
+        assert_eq!(entries_a, entries_b);
+        assert!(entries_a.iter().any(|entry| entry.text == "<ignored>"));
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn test_invalid_ignore_query() {
+        let language = generate_language("rust", &GrammarConfig::default()).unwrap();
+        let processor = TreeSitterProcessor {
+            ignore_queries: HashMap::from([("rust".into(), vec!["(not_a_node) @ignore".into()])]),
+            ..Default::default()
+        };
+        assert!(matches!(
+            processor.check_ignore_queries(&language, "rust"),
+            Err(NavigationError::InvalidQuery(_))
+        ));
+        assert!(processor.check_ignore_queries(&language, "python").is_ok());
+    }
 }
@@ -13,19 +13,28 @@ pub mod cli;
 pub mod config;
 pub mod console_utils;
 pub mod diff;
+pub mod encoding;
 mod figment_utils;
+pub mod incremental;
 pub mod input_processing;
 #[cfg(feature = "mcp-server")]
 pub mod mcp_server;
+pub mod merge;
 pub mod neg_idx_vec;
 pub mod parse;
+pub mod rename;
 pub mod render;
+pub mod stats;
+pub mod symbol_summary;
+#[cfg(all(test, feature = "static-grammar-libs"))]
+mod test_util;
+pub mod tree_diff;
 
 use anyhow::Result;
 use input_processing::VectorData;
 use log::{debug, info};
 use parse::GrammarConfig;
-use std::{fs, path::PathBuf};
+use std::path::PathBuf;
 
 /// Create an AST vector from a path
 ///
@@ -43,20 +52,25 @@ pub fn generate_ast_vector_data(
     file_type: Option<&str>,
     grammar_config: &GrammarConfig,
 ) -> Result<VectorData> {
-    let text = fs::read_to_string(&path)?;
     let file_name = path.to_string_lossy();
     debug!("Reading {file_name} to string");
+    let (text, format) = encoding::read_file(&path, grammar_config.fallback_encoding)?;
 
     if let Some(file_type) = file_type {
         info!("Using user-set filetype \"{file_type}\" for {file_name}");
     } else {
         info!("Will deduce filetype from file extension");
     };
-    let (tree, resolved_language) = parse::parse_file(&path, file_type, grammar_config)?;
+    let resolved_language = parse::resolve_file_language(&path, file_type, grammar_config)?;
+    let (tree, resolved_language) =
+        parse::parse_text(&text, &path, resolved_language, grammar_config)?;
+    let injections = parse::parse_injections(&tree, &text, &resolved_language, grammar_config);
     Ok(VectorData {
         text,
         tree,
         path,
         resolved_language,
+        injections,
+        format,
     })
 }
@@ -0,0 +1,515 @@
+//! Three-way merges at the granularity of leaf nodes.
+//!
+//! Both sides of a merge are diffed against their common ancestor, and the changes that don't
+//! touch the same leaves are combined. This resolves many changes that a line-based merge would
+//! report as conflicts, such as two people adding different arguments to the same call. Changes
+//! that do touch the same leaves are written out with conflict markers, expanded to whole lines
+//! so the output can be edited like the output of `git merge`. Lines that are identical in all
+//! three documents are kept outside of the markers.
+
+use crate::diff::{DiffEngine, Engine, LineOffsets, NodeEntries, entry_index, group_by_node};
+use crate::input_processing::{EditType, Entry};
+use log::info;
+use logging_timer::time;
+use std::ops::Range;
+
+/// The marker at the start of a conflict, followed by our side of the conflict
+const OURS_MARKER: &str = "<<<<<<< ours";
+
+/// The marker between the two sides of a conflict
+const SEPARATOR_MARKER: &str = "=======";
+
+/// The marker at the end of a conflict, preceded by their side of the conflict
+const THEIRS_MARKER: &str = ">>>>>>> theirs";
+
+/// The index of each document in the arrays used by the merge
+const BASE: usize = 0;
+const OURS: usize = 1;
+const THEIRS: usize = 2;
+
+/// A document that takes part in a merge.
+#[derive(Debug, Clone, Copy)]
+pub struct MergeDocument<'a> {
+    /// The full text of the document
+    pub text: &'a str,
+
+    /// The entries that were processed from the document's syntax tree
+    pub entries: &'a [Entry<'a>],
+}
+
+/// The result of a three-way merge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeOutput {
+    /// The merged text, including conflict markers for any conflicts
+    pub text: String,
+
+    /// The number of conflicts in the merged text
+    pub conflicts: usize,
+}
+
+impl MergeOutput {
+    /// Whether the merge left conflicts that have to be resolved by hand.
+    #[must_use]
+    pub fn has_conflicts(&self) -> bool {
+        self.conflicts > 0
+    }
+}
+
+/// Which text is used for a chunk in the merged document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resolution {
+    Ours,
+    Theirs,
+    Conflict,
+}
+
+/// A span of text that lines up across the three documents.
+///
+/// Chunks either contain a leaf that is unchanged in every document or the text between two such
+/// leaves.
+#[derive(Debug, Clone)]
+struct Chunk {
+    /// The range of the chunk in each document, indexed by [`BASE`], [`OURS`] and [`THEIRS`]
+    ranges: [Range<usize>; 3],
+    resolution: Resolution,
+}
+
+/// The byte range a group of entries covers in its document.
+///
+/// The range is resolved from the positions of the entries, so it doesn't include whitespace that
+/// was stripped from the leaf.
+fn byte_range(node: &NodeEntries, offsets: &LineOffsets) -> Range<usize> {
+    let last = &node.entries[node.entries.len() - 1];
+    // Entries always have valid positions in the document they were generated from
+    let start = offsets
+        .offset(node.entries[0].start_position)
+        .unwrap_or_default();
+    let end = offsets.offset(last.end_position).unwrap_or(start);
+    start..end.max(start)
+}
+
+/// Map each leaf in `old` to the leaf in `new` that it's unchanged in, if there is one.
+fn unchanged_matches(
+    old: &[NodeEntries],
+    new: &[NodeEntries],
+    engine: DiffEngine,
+) -> Vec<Option<usize>> {
+    let mut old_changed = vec![false; old.len()];
+    let mut new_changed = vec![false; new.len()];
+
+    for edit in engine.diff(old, new) {
+        match edit {
+            EditType::Deletion(node) => old_changed[entry_index(old, node)] = true,
+            EditType::Addition(node) => new_changed[entry_index(new, node)] = true,
+        }
+    }
+    let mut unchanged_new = (0..new.len()).filter(|&i| !new_changed[i]);
+    old_changed
+        .iter()
+        .map(|&changed| if changed { None } else { unchanged_new.next() })
+        .collect()
+}
+
+/// Decide which side of a region between two unchanged leaves should be used.
+///
+/// A side that has the same leaves as the base only changed whitespace or text that isn't part of
+/// the diff, so the other side's changes take priority.
+fn resolve(texts: [&str; 3], leaves: [&[NodeEntries]; 3]) -> Resolution {
+    if texts[OURS] == texts[THEIRS] || texts[THEIRS] == texts[BASE] {
+        Resolution::Ours
+    } else if texts[OURS] == texts[BASE] || leaves[OURS] == leaves[BASE] {
+        Resolution::Theirs
+    } else if leaves[THEIRS] == leaves[BASE] || leaves[OURS] == leaves[THEIRS] {
+        Resolution::Ours
+    } else {
+        Resolution::Conflict
+    }
+}
+
+/// Split the chunks into their lines.
+///
+/// This only applies to chunks that are identical in every document, which lets conflicts be
+/// expanded to whole lines without pulling in the surrounding lines.
+fn split_lines(chunks: Vec<Chunk>, texts: [&str; 3]) -> Vec<Chunk> {
+    let mut split = Vec::with_capacity(chunks.len());
+
+    for chunk in chunks {
+        let text = &texts[OURS][chunk.ranges[OURS].clone()];
+        let identical = (0..3).all(|doc| &texts[doc][chunk.ranges[doc].clone()] == text);
+
+        if !identical || !text.contains('\n') {
+            split.push(chunk);
+            continue;
+        }
+        let mut offset = 0;
+
+        for line in text.split_inclusive('\n') {
+            split.push(Chunk {
+                ranges: chunk
+                    .ranges
+                    .clone()
+                    .map(|range| range.start + offset..range.start + offset + line.len()),
+                resolution: chunk.resolution,
+            });
+            offset += line.len();
+        }
+    }
+    split
+}
+
+/// Whether the given offset in each document is at the start of a line or the end of the document.
+fn at_line_boundary(texts: [&str; 3], offsets: [usize; 3]) -> bool {
+    (0..3).all(|doc| {
+        let offset = offsets[doc];
+        offset == 0 || offset == texts[doc].len() || texts[doc].as_bytes()[offset - 1] == b'\n'
+    })
+}
+
+/// Find the ranges of chunks that make up each conflict.
+///
+/// Each conflict is expanded to whole lines, and conflicts that end up sharing a line are combined.
+fn conflict_spans(chunks: &[Chunk], texts: [&str; 3]) -> Vec<Range<usize>> {
+    let mut spans: Vec<Range<usize>> = Vec::new();
+
+    for (i, chunk) in chunks.iter().enumerate() {
+        if chunk.resolution != Resolution::Conflict {
+            continue;
+        }
+        let mut start = i;
+
+        while !at_line_boundary(texts, chunks[start].ranges.clone().map(|range| range.start)) {
+            start -= 1;
+        }
+        let mut end = i;
+
+        while !at_line_boundary(texts, chunks[end].ranges.clone().map(|range| range.end)) {
+            end += 1;
+        }
+
+        match spans.last_mut() {
+            Some(last) if start < last.end => last.end = last.end.max(end + 1),
+            _ => spans.push(start..end + 1),
+        }
+    }
+    spans
+}
+
+/// Split the sides of a conflict into the lines at its start that are identical in every document,
+/// the lines that conflict, and the lines at its end that are identical in every document.
+///
+/// Expanding a conflict to whole lines can pull in lines that none of the documents changed, those
+/// are written once outside of the conflict markers.
+fn trim_identical_lines<'t>(sides: [&'t str; 3]) -> (&'t str, [&'t str; 3], &'t str) {
+    let mut prefix = 0;
+
+    while let Some(line) = sides[OURS][prefix..].split_inclusive('\n').next() {
+        if !sides
+            .iter()
+            .all(|side| side[prefix..].split_inclusive('\n').next() == Some(line))
+        {
+            break;
+        }
+        prefix += line.len();
+    }
+    let middle = |side: &'t str, suffix: usize| side.get(prefix..side.len() - suffix).unwrap_or("");
+    let mut suffix = 0;
+
+    while let Some(line) = middle(sides[OURS], suffix)
+        .split_inclusive('\n')
+        .next_back()
+    {
+        if !sides
+            .iter()
+            .all(|side| middle(side, suffix).split_inclusive('\n').next_back() == Some(line))
+        {
+            break;
+        }
+        suffix += line.len();
+    }
+    (
+        &sides[OURS][..prefix],
+        sides.map(|side| middle(side, suffix)),
+        &sides[OURS][sides[OURS].len() - suffix..],
+    )
+}
+
+/// Make sure the next text that's pushed starts on a new line.
+fn push_line_break(output: &mut String) {
+    if !output.is_empty() && !output.ends_with('\n') {
+        output.push('\n');
+    }
+}
+
+/// Merge the changes that `ours` and `theirs` made to `base`.
+///
+/// Leaves are compared using the text of their entries, so the merge follows the same input
+/// processing that is used for diffs. The text that is written out is always the original text of
+/// the documents.
+#[time("info", "merge::{}")]
+pub fn merge(
+    base: MergeDocument,
+    ours: MergeDocument,
+    theirs: MergeDocument,
+    engine: DiffEngine,
+) -> MergeOutput {
+    let texts = [base.text, ours.text, theirs.text];
+    let offsets = texts.map(LineOffsets::new);
+    let nodes = [
+        group_by_node(base.entries),
+        group_by_node(ours.entries),
+        group_by_node(theirs.entries),
+    ];
+    let ours_matches = unchanged_matches(&nodes[BASE], &nodes[OURS], engine);
+    let theirs_matches = unchanged_matches(&nodes[BASE], &nodes[THEIRS], engine);
+
+    // The leaves that are unchanged on both sides anchor the regions that have to be merged
+    let anchors = (0..nodes[BASE].len())
+        .filter_map(|i| Some([i, ours_matches[i]?, theirs_matches[i]?]))
+        .map(Some)
+        .chain([None]);
+    let mut chunks = Vec::new();
+    let mut next_leaf = [0; 3];
+    let mut next_byte = [0; 3];
+
+    for anchor in anchors {
+        let (leaf_ends, byte_ends) = match anchor {
+            Some(indices) => (
+                indices,
+                [BASE, OURS, THEIRS]
+                    .map(|doc| byte_range(&nodes[doc][indices[doc]], &offsets[doc]).start),
+            ),
+            None => (
+                [BASE, OURS, THEIRS].map(|doc| nodes[doc].len()),
+                texts.map(str::len),
+            ),
+        };
+        let ranges = [BASE, OURS, THEIRS].map(|doc| next_byte[doc]..byte_ends[doc]);
+
+        if ranges.iter().any(|range| !range.is_empty()) {
+            let region_texts = [BASE, OURS, THEIRS].map(|doc| &texts[doc][ranges[doc].clone()]);
+            let leaves =
+                [BASE, OURS, THEIRS].map(|doc| &nodes[doc][next_leaf[doc]..leaf_ends[doc]]);
+            chunks.push(Chunk {
+                ranges,
+                resolution: resolve(region_texts, leaves),
+            });
+        }
+
+        if let Some(indices) = anchor {
+            let ranges = [BASE, OURS, THEIRS]
+                .map(|doc| byte_range(&nodes[doc][indices[doc]], &offsets[doc]));
+            next_leaf = indices.map(|i| i + 1);
+            next_byte = ranges.clone().map(|range| range.end);
+            chunks.push(Chunk {
+                ranges,
+                resolution: Resolution::Ours,
+            });
+        }
+    }
+    let chunks = split_lines(chunks, texts);
+    let spans = conflict_spans(&chunks, texts);
+    let mut output = String::with_capacity(ours.text.len());
+    let mut spans_iter = spans.iter().peekable();
+    let mut i = 0;
+
+    while i < chunks.len() {
+        if let Some(span) = spans_iter.next_if(|span| span.start == i) {
+            let (prefix, sides, suffix) = trim_identical_lines([BASE, OURS, THEIRS].map(|doc| {
+                &texts[doc]
+                    [chunks[span.start].ranges[doc].start..chunks[span.end - 1].ranges[doc].end]
+            }));
+            output.push_str(prefix);
+            push_line_break(&mut output);
+            output.push_str(OURS_MARKER);
+            output.push('\n');
+            output.push_str(sides[OURS]);
+            push_line_break(&mut output);
+            output.push_str(SEPARATOR_MARKER);
+            output.push('\n');
+            output.push_str(sides[THEIRS]);
+            push_line_break(&mut output);
+            output.push_str(THEIRS_MARKER);
+            output.push('\n');
+            output.push_str(suffix);
+            i = span.end;
+            continue;
+        }
+        let chunk = &chunks[i];
+        let doc = match chunk.resolution {
+            Resolution::Theirs => THEIRS,
+            Resolution::Ours | Resolution::Conflict => OURS,
+        };
+        output.push_str(&texts[doc][chunk.ranges[doc].clone()]);
+        i += 1;
+    }
+    info!("Merged documents with {} conflicts", spans.len());
+    MergeOutput {
+        text: output,
+        conflicts: spans.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "static-grammar-libs")]
+    use super::*;
+
+    #[cfg(feature = "static-grammar-libs")]
+    fn merge_texts(base: &str, ours: &str, theirs: &str) -> MergeOutput {
+        use crate::input_processing::{Granularity, TreeSitterProcessor};
+        use crate::test_util::parse_rust;
+
+        let trees = [base, ours, theirs].map(parse_rust);
+        let processor = TreeSitterProcessor {
+            granularity: Granularity::Node,
+            ..Default::default()
+        };
+        let entries = [
+            processor.process(&trees[BASE], base, "rust"),
+            processor.process(&trees[OURS], ours, "rust"),
+            processor.process(&trees[THEIRS], theirs, "rust"),
+        ];
+        let document = |doc: usize, text| MergeDocument {
+            text,
+            entries: &entries[doc],
+        };
+        merge(
+            document(BASE, base),
+            document(OURS, ours),
+            document(THEIRS, theirs),
+            DiffEngine::default(),
+        )
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn merge_changes_to_the_same_line() {
+        let output = merge_texts(
+            "fn main() {\n    call(a, b);\n}\n",
+            "fn main() {\n    call(x, a, b);\n}\n",
+            "fn main() {\n    call(a, b, y);\n}\n",
+        );
+        assert_eq!(
+            output,
+            MergeOutput {
+                text: "fn main() {\n    call(x, a, b, y);\n}\n".into(),
+                conflicts: 0,
+            }
+        );
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn merge_conflicting_changes() {
+        let output = merge_texts(
+            "fn main() {\n    call(a, b);\n    other();\n}\n",
+            "fn main() {\n    call(a, c);\n    other();\n}\n",
+            "fn main() {\n    call(a, d);\n    other(e);\n}\n",
+        );
+        assert_eq!(
+            output,
+            MergeOutput {
+                text: "fn main() {\n<<<<<<< ours\n    call(a, c);\n=======\n    call(a, d);\n\
+                    >>>>>>> theirs\n    other(e);\n}\n"
+                    .into(),
+                conflicts: 1,
+            }
+        );
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn merge_deletion_and_modification() {
+        let output = merge_texts(
+            "fn main() {\n    first();\n    second(a);\n}\n",
+            "fn main() {\n    first();\n}\n",
+            "fn main() {\n    first();\n    second(b);\n}\n",
+        );
+        assert_eq!(
+            output,
+            MergeOutput {
+                text: "fn main() {\n    first();\n<<<<<<< ours\n=======\n    second(b);\n\
+                    >>>>>>> theirs\n}\n"
+                    .into(),
+                conflicts: 1,
+            }
+        );
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn merge_insertions_at_the_same_place() {
+        let base = "fn main() {\n    first();\n}\n";
+        let output = merge_texts(
+            base,
+            "fn main() {\n    first();\n    ours();\n}\n",
+            "fn main() {\n    first();\n    theirs();\n}\n",
+        );
+        assert_eq!(
+            output,
+            MergeOutput {
+                text: "fn main() {\n    first();\n<<<<<<< ours\n    ours();\n=======\n\
+                    \x20   theirs();\n>>>>>>> theirs\n}\n"
+                    .into(),
+                conflicts: 1,
+            }
+        );
+
+        // Both sides making the same insertion isn't a conflict
+        let both = "fn main() {\n    first();\n    both();\n}\n";
+        assert_eq!(
+            merge_texts(base, both, both),
+            MergeOutput {
+                text: both.into(),
+                conflicts: 0,
+            }
+        );
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn merge_whitespace_changes() {
+        let output = merge_texts(
+            "fn main() {\n    call(a,b);\n}\n",
+            "fn main() {\n        call(a, b);\n}\n",
+            "fn main() {\n    call(a,c);\n}\n",
+        );
+        assert_eq!(
+            output,
+            MergeOutput {
+                text: "fn main() {\n        call(a,c);\n}\n".into(),
+                conflicts: 0,
+            }
+        );
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn merge_encoded_documents() {
+        use crate::encoding::{Encoding, decode, encode};
+
+        let utf16 = |text: &str| {
+            let mut bytes = vec![0xFF, 0xFE];
+            bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+            bytes
+        };
+        let bom = |text: &str| [b"\xEF\xBB\xBF", text.as_bytes()].concat();
+        // Our side is UTF-16 with CRLF line endings, their side is UTF-8 with a byte order mark
+        let base = utf16("fn main() {\r\n    call(a, b);\r\n}\r\n");
+        let ours = utf16("fn main() {\r\n    call(x, a, b);\r\n}\r\n");
+        let theirs = bom("fn main() {\n    call(a, b, y);\n}\n");
+        let [base, ours, theirs] =
+            [base, ours, theirs].map(|bytes| decode(&bytes, Encoding::Utf8).unwrap());
+
+        let output = merge_texts(&base.0, &ours.0, &theirs.0);
+        assert_eq!(output.conflicts, 0);
+        assert_eq!(
+            encode(&output.text, &ours.1).unwrap(),
+            utf16("fn main() {\r\n    call(x, a, b, y);\r\n}\r\n")
+        );
+        assert_eq!(
+            encode(&output.text, &theirs.1).unwrap(),
+            bom("fn main() {\n    call(x, a, b, y);\n}\n")
+        );
+    }
+}
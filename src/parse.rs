@@ -27,16 +27,20 @@ use phf::phf_map;
 #[cfg(not(feature = "static-grammar-libs"))]
 use tree_sitter::Language;
 
-use log::{debug, error, info};
+use crate::encoding::{self, DecodeError, Encoding};
+use log::{debug, error, info, warn};
 use logging_timer::time;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
-    fs, io,
+    io,
     path::{Path, PathBuf},
 };
 use thiserror::Error;
-use tree_sitter::{LANGUAGE_VERSION, MIN_COMPATIBLE_LANGUAGE_VERSION, Parser, Tree};
+use tree_sitter::{
+    LANGUAGE_VERSION, MIN_COMPATIBLE_LANGUAGE_VERSION, Parser, Query, QueryCursor,
+    StreamingIterator, Tree,
+};
 
 /// A mapping of file extensions to their associated languages
 ///
@@ -95,6 +99,9 @@ pub enum LoadingError {
     #[error("tree-sitter had an error")]
     LanguageError(#[from] tree_sitter::LanguageError),
 
+    #[error("Failed to decode the file")]
+    DecodeError(#[from] DecodeError),
+
     #[error("could not parse {0} with tree-sitter")]
     TSParseFailure(PathBuf),
 
@@ -129,6 +136,45 @@ pub struct GrammarConfig {
     /// "cpp" => "cpp"
     /// ```
     pub file_associations: Option<StringMap>,
+
+    /// Tree-sitter queries that find code from other languages embedded in a document, keyed by
+    /// the language of the document.
+    ///
+    /// The queries use the same captures as the `injections.scm` files of tree-sitter grammars:
+    /// `@injection.content` captures the embedded code, and either `@injection.language` captures
+    /// the name of its language or `(#set! injection.language "name")` sets it. The name can be a
+    /// language or a file extension. These replace the built-in queries for the language, see
+    /// [`DEFAULT_INJECTIONS`].
+    #[serde(default)]
+    pub injections: HashMap<String, Vec<String>>,
+
+    /// The encoding to decode files with if they don't start with a byte order mark and aren't
+    /// valid UTF-8.
+    #[serde(default)]
+    pub fallback_encoding: Encoding,
+}
+
+/// The injection queries that are used for a language if [`GrammarConfig::injections`] doesn't
+/// have any for it.
+///
+/// These are taken from the `injections.scm` files of the grammars, since the query files aren't
+/// available at runtime.
+pub const DEFAULT_INJECTIONS: [(&str, &str); 1] = [(
+    "markdown",
+    "(fenced_code_block (info_string (language) @injection.language) (code_fence_content) @injection.content)",
+)];
+
+/// Code from another language that is embedded in a document, such as a code block in markdown.
+#[derive(Debug)]
+pub struct Injection {
+    /// The name of the language of the embedded code
+    pub language: String,
+
+    /// The syntax tree of the embedded code
+    ///
+    /// This is parsed from the text of the whole document with the embedded code as the only
+    /// included range, so the positions of its nodes are relative to the start of the document.
+    pub tree: Tree,
 }
 
 /// Generate a [tree sitter language](Language) from a language string for a static language.
@@ -399,6 +445,103 @@ pub fn lang_name_from_file_ext<'cfg>(
     }
 }
 
+/// Find and parse the code from other languages that is embedded in a document.
+///
+/// The injection queries for the document's language are taken from `config`, or from
+/// [`DEFAULT_INJECTIONS`]. Embedded code is skipped if its language can't be loaded, and queries
+/// that are invalid are skipped with a warning, since injections are a best effort.
+#[time("info", "parse::{}")]
+pub fn parse_injections(
+    tree: &Tree,
+    text: &str,
+    language: &str,
+    config: &GrammarConfig,
+) -> Vec<Injection> {
+    let queries: Vec<&str> = match config.injections.get(language) {
+        Some(queries) => queries.iter().map(String::as_str).collect(),
+        None => DEFAULT_INJECTIONS
+            .iter()
+            .filter(|(name, _)| *name == language)
+            .map(|(_, query)| *query)
+            .collect(),
+    };
+    let mut languages: HashMap<String, Option<Language>> = HashMap::new();
+    let mut injections = Vec::new();
+
+    for query_str in queries {
+        let query = match Query::new(&tree.language(), query_str) {
+            Ok(query) => query,
+            Err(e) => {
+                warn!("Skipping an invalid injection query for {language}: {e}");
+                continue;
+            }
+        };
+        let (Some(content_idx), language_idx) = (
+            query.capture_index_for_name("injection.content"),
+            query.capture_index_for_name("injection.language"),
+        ) else {
+            warn!(
+                "Skipping an injection query for {language} without an @injection.content capture"
+            );
+            continue;
+        };
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(&query, tree.root_node(), text.as_bytes());
+
+        while let Some(m) = matches.next() {
+            let content = m.captures.iter().find(|cap| cap.index == content_idx);
+            let name = m
+                .captures
+                .iter()
+                .find(|cap| Some(cap.index) == language_idx)
+                .map(|cap| text[cap.node.byte_range()].trim().to_string())
+                .or_else(|| {
+                    query
+                        .property_settings(m.pattern_index)
+                        .iter()
+                        .find(|property| &*property.key == "injection.language")
+                        .and_then(|property| property.value.as_deref().map(str::to_string))
+                });
+            let (Some(content), Some(name)) = (content, name) else {
+                continue;
+            };
+            let name = injected_language_name(&name, config);
+            let injected_language = languages
+                .entry(name.to_string())
+                .or_insert_with(|| generate_language(name, config).ok());
+            let Some(injected_language) = injected_language else {
+                debug!("Not parsing code embedded in {language} as {name}, which isn't supported");
+                continue;
+            };
+            let mut parser = Parser::new();
+            let parsed = parser
+                .set_language(injected_language)
+                .ok()
+                .and_then(|()| parser.set_included_ranges(&[content.node.range()]).ok())
+                .and_then(|()| parser.parse(text, None));
+            if let Some(tree) = parsed {
+                injections.push(Injection {
+                    language: name.to_string(),
+                    tree,
+                });
+            }
+        }
+    }
+    injections
+}
+
+/// Resolve the name of the language of embedded code, which can be a language name or a file
+/// extension, like the info string of a markdown code block.
+fn injected_language_name<'a>(name: &'a str, config: &'a GrammarConfig) -> &'a str {
+    config
+        .file_associations
+        .as_ref()
+        .and_then(|associations| associations.get(name))
+        .map(String::as_str)
+        .or_else(|| FILE_EXTS.get(name).copied())
+        .unwrap_or(name)
+}
+
 /// A convenience function to check of a tree-sitter language has a compatible ABI version for
 /// `diffsitter`.
 ///
@@ -420,6 +563,26 @@ pub fn ts_language_abi_checked(ts_language: &Language) -> Result<(), LoadingErro
     Ok(())
 }
 
+/// Resolve the language to parse a file with.
+///
+/// This is `language` if it's set, otherwise the language is inferred from the file's extension.
+pub fn resolve_file_language<'a>(
+    p: &Path,
+    language: Option<&'a str>,
+    config: &'a GrammarConfig,
+) -> Result<&'a str, LoadingError> {
+    match language {
+        Some(lang) => Ok(lang),
+        None => {
+            if let Some(ext) = p.extension() {
+                lang_name_from_file_ext(&ext.to_string_lossy(), config)
+            } else {
+                Err(LoadingError::NoFileExt(p.to_string_lossy().to_string()))
+            }
+        }
+    }
+}
+
 /// Parse a file to an AST
 ///
 /// The user may optionally supply the language to use. If the language is not supplied, it will be
@@ -432,21 +595,24 @@ pub fn parse_file(
 ) -> Result<(Tree, String), LoadingError> {
     // Either use the provided language or infer the language to use with the parser from the file
     // extension
-    let resolved_language = match language {
-        Some(lang) => Ok(lang),
-        None => {
-            if let Some(ext) = p.extension() {
-                lang_name_from_file_ext(&ext.to_string_lossy(), config)
-            } else {
-                Err(LoadingError::NoFileExt(p.to_string_lossy().to_string()))
-            }
-        }
-    }?;
+    let resolved_language = resolve_file_language(p, language, config)?;
+    let (text, _) = encoding::read_file(p, config.fallback_encoding)?;
+    parse_text(&text, p, resolved_language, config)
+}
+
+/// Parse text that was read from a file to an AST with the given language.
+///
+/// The path is only used for error messages.
+pub fn parse_text(
+    text: &str,
+    p: &Path,
+    resolved_language: &str,
+    config: &GrammarConfig,
+) -> Result<(Tree, String), LoadingError> {
     let mut parser = Parser::new();
     let ts_lang = generate_language(resolved_language, config)?;
     parser.set_language(&ts_lang)?;
-    let text = fs::read_to_string(p)?;
-    match parser.parse(&text, None) {
+    match parser.parse(text, None) {
         Some(ast) => {
             debug!("Parsed AST");
             Ok((ast, resolved_language.to_string()))
@@ -513,4 +679,102 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn test_injected_language_name() {
+        let config = GrammarConfig {
+            file_associations: Some(HashMap::from([("zsh".into(), "bash".into())])),
+            ..Default::default()
+        };
+        assert_eq!(injected_language_name("rs", &config), "rust");
+        assert_eq!(injected_language_name("zsh", &config), "bash");
+        assert_eq!(injected_language_name("python", &config), "python");
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn test_parse_markdown_injections() {
+        use crate::input_processing::{Granularity, TreeSitterProcessor};
+
+        let text = "# Example\n\n```rust\nfn main() {}\n```\n\n```unknown\nfoo\n```\n";
+        let config = GrammarConfig::default();
+        let mut parser = Parser::new();
+        parser
+            .set_language(&generate_language("markdown", &config).unwrap())
+            .unwrap();
+        let tree = parser.parse(text, None).unwrap();
+        let injections = parse_injections(&tree, text, "markdown", &config);
+
+        assert_eq!(injections.len(), 1);
+        assert_eq!(injections[0].language, "rust");
+        let processor = TreeSitterProcessor {
+            granularity: Granularity::Node,
+            ..Default::default()
+        };
+        let entries = processor.process_node(tree.root_node(), text, "markdown", &injections);
+        let kinds: Vec<&str> = entries
+            .iter()
+            .filter(|entry| entry.start_position().row == 3)
+            .map(|entry| entry.kind())
+            .collect();
+        assert_eq!(kinds, ["fn", "identifier", "(", ")", "{", "}"]);
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn test_injections_use_language_overrides() {
+        use crate::config::{Config, LanguageConfig};
+        use crate::input_processing::{Granularity, ProcessorOverrides};
+        use std::borrow::Cow;
+        use std::collections::HashSet;
+
+        let text = "# Example text\n\n```rust\nfn main() {}\n```\n";
+        let language_config = |overrides| LanguageConfig {
+            input_processing: overrides,
+        };
+        let config = Config {
+            languages: HashMap::from([
+                (
+                    "markdown".into(),
+                    language_config(ProcessorOverrides {
+                        granularity: Some(Granularity::Word),
+                        ..Default::default()
+                    }),
+                ),
+                (
+                    "rust".into(),
+                    language_config(ProcessorOverrides {
+                        granularity: Some(Granularity::Node),
+                        exclude_kinds: Some(HashSet::from(["identifier".into()])),
+                        ..Default::default()
+                    }),
+                ),
+            ]),
+            ..Default::default()
+        };
+        let mut parser = Parser::new();
+        parser
+            .set_language(&generate_language("markdown", &config.grammar).unwrap())
+            .unwrap();
+        let tree = parser.parse(text, None).unwrap();
+        let injections = parse_injections(&tree, text, "markdown", &config.grammar);
+        let entries = config.input_processing_for("markdown").process_node_with(
+            tree.root_node(),
+            text,
+            "markdown",
+            &injections,
+            |language| Cow::Owned(config.input_processing_for(language)),
+        );
+        let row = |row| -> Vec<&str> {
+            entries
+                .iter()
+                .filter(|entry| entry.start_position().row == row)
+                .map(|entry| entry.text.as_ref())
+                .collect()
+        };
+
+        // The host is split into words and the embedded code is kept whole without identifiers
+        assert_eq!(row(0), ["#", "Example", "text"]);
+        assert_eq!(row(3), ["fn", "(", ")", "{", "}"]);
+    }
 }
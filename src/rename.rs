@@ -0,0 +1,255 @@
+//! Detection of identifiers that were consistently renamed.
+//!
+//! When an identifier is renamed, every place it shows up in the file turns into a small
+//! substitution in the diff. This module looks for leaves whose text was substituted the same way
+//! everywhere in the file, so they can be reported as a single rename instead.
+
+use crate::diff::{Alignment, entry_index};
+use crate::input_processing::{EditType, Entry};
+use log::info;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// The minimum number of times a substitution has to show up to be considered a rename.
+///
+/// A substitution that only happens once is just an edit.
+const MIN_OCCURRENCES: usize = 2;
+
+/// A leaf that was renamed in every place it appears in the document.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Rename<'a> {
+    /// The text of the leaf in the old document
+    pub old_name: String,
+
+    /// The text of the leaf in the new document
+    pub new_name: String,
+
+    /// Each place the leaf was renamed, in document order
+    pub occurrences: Vec<RenameOccurrence<'a>>,
+}
+
+/// A single place where a leaf was renamed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RenameOccurrence<'a> {
+    /// The entries of the leaf in the old document
+    pub old: &'a [Entry<'a>],
+
+    /// The entries of the leaf in the new document
+    pub new: &'a [Entry<'a>],
+}
+
+/// A leaf node along with the entries that were generated from it.
+#[derive(Debug)]
+struct Leaf {
+    /// The range of entries from the leaf
+    entries: Range<usize>,
+
+    /// The text of the leaf, which is the concatenated text of its entries
+    label: String,
+
+    kind_id: u16,
+
+    /// Whether the leaf is a named node in the grammar.
+    ///
+    /// Anonymous nodes are punctuation and keywords, which can't be renamed.
+    named: bool,
+}
+
+/// Group the entries of a document by the leaf they came from.
+///
+/// The entries for a leaf are always contiguous.
+fn leaves(entries: &[Entry]) -> Vec<Leaf> {
+    let mut leaves: Vec<Leaf> = Vec::new();
+
+    for (i, entry) in entries.iter().enumerate() {
+        if let Some(leaf) = leaves.last_mut() {
+            if entries[leaf.entries.start].leaf_id() == entry.leaf_id() {
+                leaf.entries.end = i + 1;
+                leaf.label.push_str(&entry.text);
+                continue;
+            }
+        }
+        leaves.push(Leaf {
+            entries: i..i + 1,
+            label: entry.text.to_string(),
+            kind_id: entry.kind_id,
+            named: entry.is_named(),
+        });
+    }
+    leaves
+}
+
+/// Pair up the leaves in each document that occupy the same position relative to the unchanged
+/// entries.
+///
+/// Leaves are grouped by their kind and the [alignment](Alignment) of their first entry. If a group
+/// has the same number of leaves in both documents, the leaves are paired in order. Ambiguous
+/// groups are skipped.
+fn pair_leaves(
+    old_leaves: &[Leaf],
+    new_leaves: &[Leaf],
+    alignment: &Alignment,
+) -> Vec<(usize, usize)> {
+    let group = |leaves: &[Leaf], counts: &[usize]| {
+        let mut groups: HashMap<(usize, u16), Vec<usize>> = HashMap::new();
+
+        for (i, leaf) in leaves.iter().enumerate() {
+            groups
+                .entry((counts[leaf.entries.start], leaf.kind_id))
+                .or_default()
+                .push(i);
+        }
+        groups
+    };
+    let old_groups = group(old_leaves, &alignment.old);
+    let new_groups = group(new_leaves, &alignment.new);
+    let mut pairs = Vec::new();
+
+    for (key, old_group) in &old_groups {
+        if let Some(new_group) = new_groups.get(key) {
+            if old_group.len() == new_group.len() {
+                pairs.extend(old_group.iter().copied().zip(new_group.iter().copied()));
+            }
+        }
+    }
+    pairs.sort_unstable();
+    pairs
+}
+
+/// Find the leaves that were consistently renamed and remove their edits from the edit script.
+///
+/// A substitution from `a` to `b` is considered a rename if it shows up at least twice, every
+/// substitution of `a` is to `b`, `a` no longer shows up in the new document, `b` didn't show up in
+/// the old document, and no other leaf is renamed to `b`. Only leaves with the same kind are
+/// considered.
+///
+/// The renames are returned in the order they first show up in the old document.
+pub fn extract_renames<'a>(
+    old: &'a [Entry<'a>],
+    new: &'a [Entry<'a>],
+    edits: &mut Vec<EditType<&'a Entry<'a>>>,
+) -> Vec<Rename<'a>> {
+    let old_leaves = leaves(old);
+    let new_leaves = leaves(new);
+    let pairs = pair_leaves(&old_leaves, &new_leaves, &Alignment::new(old, new, edits));
+
+    // Group the substitutions by the leaf text in the old document
+    let mut substitutions: HashMap<(u16, &str), Vec<(usize, usize)>> = HashMap::new();
+
+    for (a, b) in pairs {
+        let (old_leaf, new_leaf) = (&old_leaves[a], &new_leaves[b]);
+
+        if old_leaf.named && old_leaf.label != new_leaf.label {
+            substitutions
+                .entry((old_leaf.kind_id, &old_leaf.label))
+                .or_default()
+                .push((a, b));
+        }
+    }
+    let old_names: HashSet<(u16, &str)> = old_leaves
+        .iter()
+        .map(|leaf| (leaf.kind_id, leaf.label.as_str()))
+        .collect();
+    let new_names: HashSet<(u16, &str)> = new_leaves
+        .iter()
+        .map(|leaf| (leaf.kind_id, leaf.label.as_str()))
+        .collect();
+    let mut candidates: Vec<Vec<(usize, usize)>> = substitutions
+        .into_iter()
+        .filter(|((kind_id, old_name), occurrences)| {
+            let new_name = new_leaves[occurrences[0].1].label.as_str();
+            occurrences.len() >= MIN_OCCURRENCES
+                && occurrences
+                    .iter()
+                    .all(|&(_, b)| new_leaves[b].label == new_name)
+                && !new_names.contains(&(*kind_id, *old_name))
+                && !old_names.contains(&(*kind_id, new_name))
+        })
+        .map(|(_, mut occurrences)| {
+            occurrences.sort_unstable();
+            occurrences
+        })
+        .collect();
+    candidates.sort_unstable();
+
+    // Two different leaves can't both be renamed to the same thing
+    let mut new_name_counts: HashMap<(u16, &str), usize> = HashMap::new();
+
+    for occurrences in &candidates {
+        let new_leaf = &new_leaves[occurrences[0].1];
+        *new_name_counts
+            .entry((new_leaf.kind_id, &new_leaf.label))
+            .or_default() += 1;
+    }
+    candidates.retain(|occurrences| {
+        let new_leaf = &new_leaves[occurrences[0].1];
+        new_name_counts[&(new_leaf.kind_id, new_leaf.label.as_str())] == 1
+    });
+
+    let mut renamed_old = vec![false; old.len()];
+    let mut renamed_new = vec![false; new.len()];
+    let renames: Vec<Rename<'a>> = candidates
+        .into_iter()
+        .map(|occurrences| {
+            let (a, b) = occurrences[0];
+            Rename {
+                old_name: old_leaves[a].label.clone(),
+                new_name: new_leaves[b].label.clone(),
+                occurrences: occurrences
+                    .into_iter()
+                    .map(|(a, b)| {
+                        let old_range = old_leaves[a].entries.clone();
+                        let new_range = new_leaves[b].entries.clone();
+                        renamed_old[old_range.clone()].fill(true);
+                        renamed_new[new_range.clone()].fill(true);
+                        RenameOccurrence {
+                            old: &old[old_range],
+                            new: &new[new_range],
+                        }
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+    edits.retain(|edit| match edit {
+        EditType::Deletion(entry) => !renamed_old[entry_index(old, entry)],
+        EditType::Addition(entry) => !renamed_new[entry_index(new, entry)],
+    });
+    info!("Detected {} renames", renames.len());
+    renames
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "static-grammar-libs")]
+    use super::*;
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn test_extract_renames() {
+        use crate::diff::{Engine, Myers};
+        use crate::input_processing::TreeSitterProcessor;
+        use crate::test_util::rust_entries;
+
+        let text_a =
+            "fn main() {\n    let foo = 1;\n    let x = 2;\n    println!(\"{}\", foo + x);\n}\n";
+        let text_b =
+            "fn main() {\n    let bar = 1;\n    let y = 2;\n    println!(\"{}\", bar + y);\n}\n";
+        let (entries_a, entries_b) = rust_entries(text_a, text_b, &TreeSitterProcessor::default());
+        let mut edits = Myers::default().diff(&entries_a, &entries_b);
+        let renames = extract_renames(&entries_a, &entries_b, &mut edits);
+        let names: Vec<(&str, &str, usize)> = renames
+            .iter()
+            .map(|rename| {
+                (
+                    rename.old_name.as_str(),
+                    rename.new_name.as_str(),
+                    rename.occurrences.len(),
+                )
+            })
+            .collect();
+        assert_eq!(names, vec![("foo", "bar", 2), ("x", "y", 2)]);
+        assert!(edits.is_empty(), "{edits:#?}");
+    }
+}
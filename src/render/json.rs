@@ -1,5 +1,6 @@
 use super::DisplayData;
 use crate::render::Renderer;
+use crate::stats::{ChangeClass, DiffStats};
 use console::Term;
 use logging_timer::time;
 use serde::{Deserialize, Serialize};
@@ -27,15 +28,34 @@ impl Renderer for Json {
     }
 }
 
+/// The JSON output, which is the display data along with statistics about the diff.
+#[derive(Serialize)]
+struct JsonOutput<'a> {
+    #[serde(flatten)]
+    data: &'a DisplayData<'a>,
+    stats: DiffStats,
+    classification: ChangeClass,
+
+    /// The changes to how the text is stored, such as its line endings, which the hunks can't show
+    format_changes: Vec<String>,
+}
+
 impl Json {
     /// Create a JSON string from the display data.
     ///
     /// This method handles display options that are set in the config.
     #[time("trace")]
     fn generate_json_str(&self, data: &DisplayData) -> Result<String, serde_json::Error> {
+        let output = JsonOutput {
+            data,
+            stats: DiffStats::from(&data.hunks),
+            classification: ChangeClass::classify(data.old.text, data.new.text, &data.hunks),
+            format_changes: data.old.format.changes(&data.new.format),
+        };
+
         if self.pretty_print {
-            return serde_json::to_string_pretty(data);
+            return serde_json::to_string_pretty(&output);
         }
-        serde_json::to_string(data)
+        serde_json::to_string(&output)
     }
 }
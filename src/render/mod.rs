@@ -9,10 +9,16 @@
 //! This module also defines utilities that may be useful for `Renderer` implementations.
 
 mod json;
+mod stat;
+mod summary;
 mod unified;
 
 use self::json::Json;
-use crate::diff::RichHunks;
+use self::stat::Stat;
+use self::summary::Summary;
+use crate::diff::{DiffStrategy, RichHunks};
+use crate::encoding::TextFormat;
+use crate::symbol_summary::SymbolSummary;
 use anyhow::anyhow;
 use console::{Color, Style, Term};
 use enum_dispatch::enum_dispatch;
@@ -28,6 +34,8 @@ pub struct DocumentDiffData<'a> {
     pub filename: &'a str,
     /// The full text of the document
     pub text: &'a str,
+    /// How the text of the document was stored in its file
+    pub format: TextFormat,
 }
 
 /// The parameters a [Renderer] instance receives to render a diff.
@@ -39,6 +47,12 @@ pub struct DisplayData<'a> {
     pub old: DocumentDiffData<'a>,
     /// The parameters that correspond to the new document
     pub new: DocumentDiffData<'a>,
+    /// How the edit script was computed, if the diff was a flat diff
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<DiffStrategy>,
+    /// How the symbols in the documents changed, if the documents have symbols
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symbols: Option<Vec<SymbolSummary>>,
 }
 
 #[enum_dispatch]
@@ -48,6 +62,8 @@ pub struct DisplayData<'a> {
 pub enum Renderers {
     Unified,
     Json,
+    Stat,
+    Summary,
 }
 
 impl Default for Renderers {
@@ -211,6 +227,8 @@ pub struct RenderConfig {
 
     unified: unified::Unified,
     json: json::Json,
+    stat: stat::Stat,
+    summary: summary::Summary,
 }
 
 impl Default for RenderConfig {
@@ -220,6 +238,8 @@ impl Default for RenderConfig {
             default: default_renderer.to_string(),
             unified: Unified::default(),
             json: Json::default(),
+            stat: Stat::default(),
+            summary: Summary::default(),
         }
     }
 }
@@ -249,6 +269,8 @@ mod tests {
 
     #[test_case("unified")]
     #[test_case("json")]
+    #[test_case("stat")]
+    #[test_case("summary")]
     fn test_get_renderer_custom_tag(tag: &str) {
         let cfg = RenderConfig::default();
         let res = cfg.get_renderer(Some(tag.into()));
@@ -0,0 +1,142 @@
+use super::DisplayData;
+use crate::render::Renderer;
+use crate::stats::DiffStats;
+use console::{Style, Term};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+/// A renderer that prints a summary of the changes in a diff instead of the diff itself.
+///
+/// This is useful to get a sense of the size and kind of a change before reading it.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug, Default)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Stat {
+    /// The maximum number of node kinds to list.
+    ///
+    /// Node kinds are listed from most to least changed nodes. All of them are listed if this
+    /// isn't set.
+    pub max_kinds: Option<usize>,
+}
+
+impl Renderer for Stat {
+    fn render(
+        &self,
+        writer: &mut dyn Write,
+        data: &DisplayData,
+        _term_info: Option<&Term>,
+    ) -> anyhow::Result<()> {
+        let stats = DiffStats::from(&data.hunks);
+        let added = Style::new().green();
+        let deleted = Style::new().red();
+
+        writeln!(writer, "{} -> {}", data.old.filename, data.new.filename)?;
+
+        // Changes to how the text is stored don't show up in the statistics
+        for change in data.old.format.changes(&data.new.format) {
+            writeln!(writer, "note: {change}")?;
+        }
+        writeln!(
+            writer,
+            "{} hunks, {} lines added, {} lines deleted",
+            stats.hunks,
+            added.apply_to(stats.lines_added),
+            deleted.apply_to(stats.lines_deleted),
+        )?;
+        writeln!(
+            writer,
+            "{} entries added, {} entries deleted",
+            added.apply_to(stats.entries_added),
+            deleted.apply_to(stats.entries_deleted),
+        )?;
+
+        if stats.lines_moved > 0 {
+            writeln!(
+                writer,
+                "{} entries moved on {} lines",
+                stats.entries_moved, stats.lines_moved
+            )?;
+        }
+
+        if stats.renames > 0 {
+            writeln!(writer, "{} renames", stats.renames)?;
+        }
+
+        if stats.kinds.is_empty() {
+            return Ok(());
+        }
+        let mut kinds: Vec<_> = stats.kinds.iter().collect();
+        kinds.sort_by_key(|(kind, counts)| (std::cmp::Reverse(counts.total()), *kind));
+        kinds.truncate(self.max_kinds.unwrap_or(usize::MAX));
+        let width = kinds.iter().map(|(kind, _)| kind.len()).max().unwrap_or(0);
+
+        writeln!(writer, "\nChanged nodes by kind:")?;
+
+        for (kind, counts) in kinds {
+            writeln!(
+                writer,
+                "  {kind:width$}  {} {}",
+                added.apply_to(format!("+{}", counts.added)),
+                deleted.apply_to(format!("-{}", counts.deleted)),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::RichHunks;
+    use crate::encoding::{LineEnding, TextFormat};
+    use crate::render::DocumentDiffData;
+
+    /// Display data for two documents without any changes in their entries.
+    fn unchanged_data(old_format: TextFormat, new_format: TextFormat) -> DisplayData<'static> {
+        DisplayData {
+            hunks: RichHunks(Vec::new()),
+            old: DocumentDiffData {
+                filename: "a.rs",
+                text: "",
+                format: old_format,
+            },
+            new: DocumentDiffData {
+                filename: "b.rs",
+                text: "",
+                format: new_format,
+            },
+            strategy: None,
+            symbols: None,
+        }
+    }
+
+    #[test]
+    fn render_empty_diff() {
+        let data = unchanged_data(TextFormat::default(), TextFormat::default());
+        let mut output = Vec::new();
+        Stat::default().render(&mut output, &data, None).unwrap();
+        assert_eq!(
+            console::strip_ansi_codes(&String::from_utf8(output).unwrap()),
+            "a.rs -> b.rs\n0 hunks, 0 lines added, 0 lines deleted\n0 entries added, 0 entries deleted\n"
+        );
+    }
+
+    #[test]
+    fn render_format_changes() {
+        let old_format = TextFormat {
+            line_ending: LineEnding::Crlf,
+            ..Default::default()
+        };
+        let new_format = TextFormat {
+            line_ending: LineEnding::Lf,
+            ..Default::default()
+        };
+        let data = unchanged_data(old_format, new_format);
+        let mut output = Vec::new();
+        Stat::default().render(&mut output, &data, None).unwrap();
+        assert_eq!(
+            console::strip_ansi_codes(&String::from_utf8(output).unwrap()),
+            "a.rs -> b.rs\nnote: line endings changed from CRLF to LF\n0 hunks, 0 lines added, \
+            0 lines deleted\n0 entries added, 0 entries deleted\n"
+        );
+    }
+}
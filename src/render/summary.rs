@@ -0,0 +1,112 @@
+use super::DisplayData;
+use crate::render::Renderer;
+use crate::render::unified::Unified;
+use crate::symbol_summary::SymbolChange;
+use console::{Style, Term};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+/// A renderer that prints an outline of the symbols that changed above the diff.
+///
+/// This lets reviewers see which functions and types were added, removed or modified before
+/// reading the hunks, which are displayed the same way as the unified renderer.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug, Default)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Summary {
+    /// Whether to list the symbols that didn't change instead of only counting them.
+    pub list_unchanged: bool,
+}
+
+impl Renderer for Summary {
+    fn render(
+        &self,
+        writer: &mut dyn Write,
+        data: &DisplayData,
+        term_info: Option<&Term>,
+    ) -> anyhow::Result<()> {
+        if let Some(symbols) = data.symbols.as_ref().filter(|symbols| !symbols.is_empty()) {
+            writeln!(writer, "Symbols:")?;
+
+            for (change, style) in [
+                (SymbolChange::Modified, Style::new().yellow()),
+                (SymbolChange::Added, Style::new().green()),
+                (SymbolChange::Removed, Style::new().red()),
+                (SymbolChange::Unchanged, Style::new()),
+            ] {
+                let names: Vec<_> = symbols
+                    .iter()
+                    .filter(|symbol| symbol.change == change)
+                    .map(|symbol| style.apply_to(&symbol.name).to_string())
+                    .collect();
+
+                if names.is_empty() {
+                    continue;
+                }
+
+                if change == SymbolChange::Unchanged && !self.list_unchanged {
+                    let noun = if names.len() == 1 {
+                        "symbol"
+                    } else {
+                        "symbols"
+                    };
+                    writeln!(writer, "  {change}: {} {noun}", names.len())?;
+                } else {
+                    writeln!(writer, "  {change}: {}", names.join(", "))?;
+                }
+            }
+            writeln!(writer)?;
+        }
+        Unified::default().render(writer, data, term_info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast_navigation::{Position, Span};
+    use crate::diff::RichHunks;
+    use crate::encoding::TextFormat;
+    use crate::render::DocumentDiffData;
+    use crate::symbol_summary::SymbolSummary;
+
+    #[test]
+    fn render_symbols_above_diff() {
+        let span = Span {
+            start: Position { line: 0, column: 0 },
+            end: Position { line: 0, column: 1 },
+        };
+        let summary = |name: &str, change| SymbolSummary {
+            name: name.into(),
+            kind: "function_item".into(),
+            change,
+            old_span: Some(span.clone()),
+            new_span: Some(span.clone()),
+        };
+        let data = DisplayData {
+            hunks: RichHunks(Vec::new()),
+            old: DocumentDiffData {
+                filename: "a.rs",
+                text: "",
+                format: TextFormat::default(),
+            },
+            new: DocumentDiffData {
+                filename: "b.rs",
+                text: "",
+                format: TextFormat::default(),
+            },
+            strategy: None,
+            symbols: Some(vec![
+                summary("main", SymbolChange::Unchanged),
+                summary("Config::load", SymbolChange::Modified),
+                summary("Config::save", SymbolChange::Modified),
+                summary("parse", SymbolChange::Added),
+            ]),
+        };
+        let mut output = Vec::new();
+        Summary::default().render(&mut output, &data, None).unwrap();
+        let output = console::strip_ansi_codes(&String::from_utf8(output).unwrap()).to_string();
+        assert!(output.starts_with(
+            "Symbols:\n  modified: Config::load, Config::save\n  added: parse\n  unchanged: 1 symbol\n\na.rs -> b.rs\n"
+        ));
+    }
+}
@@ -1,4 +1,5 @@
-use crate::diff::{Hunk, Line, RichHunk};
+use crate::diff::{ChangedHunk, Hunk, Line, MovedHunk, RichHunk};
+use crate::rename::Rename;
 use crate::render::{
     ColorDef, DisplayData, EmphasizedStyle, RegularStyle, Renderer, default_option, opt_color_def,
 };
@@ -26,6 +27,38 @@ const HUNK_TITLE_SEPARATOR: &str = "-";
 pub struct Unified {
     pub addition: TextStyle,
     pub deletion: TextStyle,
+    /// The style for blocks of text that were moved without being changed
+    #[serde(default = "default_moved_style")]
+    pub moved: TextStyle,
+    /// The style for identifiers that were renamed throughout the document
+    #[serde(default = "default_renamed_style")]
+    pub renamed: TextStyle,
+}
+
+/// The default style for moved blocks
+///
+/// This is a separate function so configs written before moved blocks existed still parse.
+fn default_moved_style() -> TextStyle {
+    TextStyle {
+        regular_foreground: Color::Yellow,
+        emphasized_foreground: Color::Yellow,
+        highlight: None,
+        bold: true,
+        underline: false,
+        prefix: "> ".into(),
+    }
+}
+
+/// The default style for renames
+fn default_renamed_style() -> TextStyle {
+    TextStyle {
+        regular_foreground: Color::Cyan,
+        emphasized_foreground: Color::Cyan,
+        highlight: None,
+        bold: true,
+        underline: false,
+        prefix: "~ ".into(),
+    }
 }
 
 /// Text style options for additions or deleetions.
@@ -70,6 +103,8 @@ impl Default for Unified {
                 underline: false,
                 prefix: "- ".into(),
             },
+            moved: default_moved_style(),
+            renamed: default_renamed_style(),
         }
     }
 }
@@ -101,9 +136,13 @@ impl Renderer for Unified {
         data: &DisplayData,
         term_info: Option<&Term>,
     ) -> Result<()> {
-        let DisplayData { hunks, old, new } = &data;
+        let DisplayData {
+            hunks, old, new, ..
+        } = &data;
         let old_fmt = FormattingDirectives::from(&self.deletion);
         let new_fmt = FormattingDirectives::from(&self.addition);
+        let moved_fmt = FormattingDirectives::from(&self.moved);
+        let renamed_fmt = FormattingDirectives::from(&self.renamed);
 
         // We need access to specific line numbers in the text so we can print out text ranges
         // within a line. It's more efficient to break up the text by line up-front so we don't
@@ -120,6 +159,20 @@ impl Renderer for Unified {
             term_info,
         )?;
 
+        // The hunks can't show changes to how the text is stored, like its line endings, since
+        // the documents are decoded and whitespace is usually stripped
+        let format_changes = old.format.changes(&new.format);
+        for change in &format_changes {
+            writeln!(
+                writer,
+                "{}",
+                Style::new().bold().apply_to(format!("note: {change}"))
+            )?;
+        }
+        if !format_changes.is_empty() {
+            writeln!(writer)?;
+        }
+
         for hunk_wrapper in &hunks.0 {
             match hunk_wrapper {
                 RichHunk::Old(hunk) => {
@@ -128,6 +181,17 @@ impl Renderer for Unified {
                 RichHunk::New(hunk) => {
                     self.print_hunk(writer, &new_lines, hunk, &new_fmt)?;
                 }
+                RichHunk::Changed(hunk) => {
+                    self.print_changed_hunk(
+                        writer, &old_lines, &new_lines, hunk, &old_fmt, &new_fmt,
+                    )?;
+                }
+                RichHunk::Moved(hunk) => {
+                    self.print_moved_hunk(writer, &new_lines, hunk, &moved_fmt)?;
+                }
+                RichHunk::Renamed(rename) => {
+                    self.print_rename(writer, rename, &renamed_fmt)?;
+                }
             }
         }
         Ok(())
@@ -249,6 +313,140 @@ impl Unified {
         Ok(())
     }
 
+    /// Print a [changed hunk](ChangedHunk) to `stdout`
+    ///
+    /// The title shows the line ranges from both documents, and the body shows the deleted lines
+    /// followed by the lines that replaced them.
+    fn print_changed_hunk(
+        &self,
+        term: &mut dyn Write,
+        old_lines: &[&str],
+        new_lines: &[&str],
+        hunk: &ChangedHunk,
+        old_fmt: &FormattingDirectives,
+        new_fmt: &FormattingDirectives,
+    ) -> Result<()> {
+        let (Some(old_first), Some(old_last), Some(new_first), Some(new_last)) = (
+            hunk.old.first_line(),
+            hunk.old.last_line(),
+            hunk.new.first_line(),
+            hunk.new.last_line(),
+        ) else {
+            debug!("Skipping empty changed hunk");
+            return Ok(());
+        };
+        debug!(
+            "Printing changed hunk (lines {old_first} - {old_last} -> {new_first} - {new_last})"
+        );
+        let line_range = |first: usize, last: usize| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{first} - {last}")
+            }
+        };
+        let old_range = line_range(old_first, old_last);
+        let new_range = line_range(new_first, new_last);
+        let divider = " -> ";
+        let title_suffix = " (changed):";
+        let separator = HUNK_TITLE_SEPARATOR
+            .repeat(old_range.len() + divider.len() + new_range.len() + title_suffix.len());
+        writeln!(
+            term,
+            "\n{}{divider}{}{title_suffix}",
+            old_fmt.regular.0.apply_to(old_range),
+            new_fmt.regular.0.apply_to(new_range),
+        )?;
+        writeln!(term, "{separator}")?;
+
+        for (lines, hunk, fmt) in [
+            (old_lines, &hunk.old, old_fmt),
+            (new_lines, &hunk.new, new_fmt),
+        ] {
+            for line in &hunk.0 {
+                let line_index = line.line_index;
+                if line_index >= lines.len() {
+                    error!(
+                        "Received invalid line index {line_index}. Skipping printing this line."
+                    );
+                    continue;
+                }
+                self.print_line(term, lines[line_index], line, fmt)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Print a [moved hunk](MovedHunk) to `stdout`
+    ///
+    /// The title shows where the block was moved from and to, and the body shows the block at its
+    /// new location.
+    fn print_moved_hunk(
+        &self,
+        term: &mut dyn Write,
+        new_lines: &[&str],
+        hunk: &MovedHunk,
+        fmt: &FormattingDirectives,
+    ) -> Result<()> {
+        let (Some(old_first), Some(old_last), Some(new_first), Some(new_last)) = (
+            hunk.old.first_line(),
+            hunk.old.last_line(),
+            hunk.new.first_line(),
+            hunk.new.last_line(),
+        ) else {
+            debug!("Skipping empty moved hunk");
+            return Ok(());
+        };
+        debug!("Printing moved hunk (lines {old_first} - {old_last} -> {new_first} - {new_last})");
+        let title_str = format!("\n{old_first} - {old_last} -> {new_first} - {new_last} (moved):");
+        let separator = HUNK_TITLE_SEPARATOR.repeat(title_str.trim().len());
+        writeln!(term, "{}", fmt.regular.0.apply_to(title_str))?;
+        writeln!(term, "{separator}")?;
+
+        for line in hunk.new.0.iter().flat_map(|hunk| &hunk.0) {
+            let line_index = line.line_index;
+            if line_index >= new_lines.len() {
+                error!("Received invalid line index {line_index}. Skipping printing this line.");
+                continue;
+            }
+            self.print_line(term, new_lines[line_index], line, fmt)?;
+        }
+        Ok(())
+    }
+
+    /// Print a [rename](Rename) to `stdout`
+    ///
+    /// Renames are printed as a single summary line instead of each place the text was changed.
+    fn print_rename(
+        &self,
+        term: &mut dyn Write,
+        rename: &Rename,
+        fmt: &FormattingDirectives,
+    ) -> Result<()> {
+        let occurrences = rename.occurrences.len();
+        debug!(
+            "Printing rename of {} to {} ({occurrences} occurrences)",
+            rename.old_name, rename.new_name
+        );
+        writeln!(
+            term,
+            "\n{}{}{}{}{}",
+            fmt.regular.0.apply_to(fmt.prefix.as_ref()),
+            fmt.regular.0.apply_to("renamed "),
+            fmt.emphasis.0.apply_to(format!("`{}`", rename.old_name)),
+            fmt.regular.0.apply_to(" -> "),
+            fmt.emphasis.0.apply_to(format!("`{}`", rename.new_name)),
+        )?;
+        writeln!(
+            term,
+            "{}",
+            fmt.regular
+                .0
+                .apply_to(format!("({occurrences} occurrences)"))
+        )?;
+        Ok(())
+    }
+
     /// Print the title of a hunk to stdout
     ///
     /// This will print the line numbers that correspond to the hunk using the color directive for
@@ -306,8 +504,7 @@ impl Unified {
         // We keep printing ranges until we've covered the entire line
         for entry in &line.entries {
             // The range of text to emphasize
-            // TODO(afnan) deal with ranges spanning multiple rows
-            let emphasis_range = entry.start_position().column..entry.end_position().column;
+            let emphasis_range = line.columns(entry, text.len());
 
             // First we need to see if there's any regular text to cover. If the range has a len of
             // zero this is a no-op
@@ -0,0 +1,320 @@
+//! Summary statistics for a diff.
+//!
+//! These are useful for triaging large diffs without reading them, for example to find out that a
+//! change mostly renamed identifiers or only touched string literals.
+
+use crate::diff::{ChangedHunk, Hunk, MovedHunk, RichHunk, RichHunks};
+use crate::input_processing::Entry;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use strum::Display;
+
+/// The number of nodes of a particular kind that were changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct KindStats {
+    /// The number of nodes of this kind that were added
+    pub added: usize,
+
+    /// The number of nodes of this kind that were deleted
+    pub deleted: usize,
+}
+
+impl KindStats {
+    /// The total number of nodes of this kind that were changed.
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.added + self.deleted
+    }
+}
+
+/// Statistics about the changes in a diff.
+///
+/// Renames are counted as a deletion and an addition of each occurrence, so the statistics are the
+/// same whether or not renames were detected. Moved entries are counted separately and aren't
+/// considered to be added or deleted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DiffStats {
+    /// The number of hunks in the diff
+    pub hunks: usize,
+
+    /// The number of entries that were added
+    pub entries_added: usize,
+
+    /// The number of entries that were deleted
+    pub entries_deleted: usize,
+
+    /// The number of entries that were moved
+    pub entries_moved: usize,
+
+    /// The number of lines in the new document with added entries
+    pub lines_added: usize,
+
+    /// The number of lines in the old document with deleted entries
+    pub lines_deleted: usize,
+
+    /// The number of lines in the new document with moved entries
+    pub lines_moved: usize,
+
+    /// The number of identifiers that were renamed
+    pub renames: usize,
+
+    /// The number of changed nodes, by the kind of the node
+    pub kinds: BTreeMap<String, KindStats>,
+}
+
+/// Which document a changed entry belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Old,
+    New,
+}
+
+/// Accumulates the statistics for each hunk.
+#[derive(Debug, Default)]
+struct StatsBuilder {
+    stats: DiffStats,
+    old_lines: BTreeSet<usize>,
+    new_lines: BTreeSet<usize>,
+    moved_lines: BTreeSet<usize>,
+}
+
+impl StatsBuilder {
+    /// Count the entries that were deleted from the old document or added to the new document.
+    ///
+    /// Consecutive entries that came from the same node are counted as a single node.
+    fn add_changed<'a>(&mut self, entries: impl IntoIterator<Item = &'a Entry<'a>>, side: Side) {
+        let mut last_node = None;
+
+        for entry in entries {
+            let rows = entry.start_position.row..=entry.last_row();
+            let kind = self
+                .stats
+                .kinds
+                .entry(entry.kind().to_string())
+                .or_default();
+            let is_new_node = last_node != Some(entry.leaf_id());
+            last_node = Some(entry.leaf_id());
+
+            match side {
+                Side::Old => {
+                    self.stats.entries_deleted += 1;
+                    self.old_lines.extend(rows);
+                    kind.deleted += usize::from(is_new_node);
+                }
+                Side::New => {
+                    self.stats.entries_added += 1;
+                    self.new_lines.extend(rows);
+                    kind.added += usize::from(is_new_node);
+                }
+            }
+        }
+    }
+
+    /// Count the entries in a hunk.
+    fn add_hunk(&mut self, hunk: &Hunk, side: Side) {
+        self.add_changed(hunk.entries(), side);
+    }
+
+    /// Count the entries in a moved block.
+    fn add_moved(&mut self, hunk: &MovedHunk) {
+        for entry in hunk.new.0.iter().flat_map(Hunk::entries) {
+            self.stats.entries_moved += 1;
+            self.moved_lines
+                .extend(entry.start_position.row..=entry.last_row());
+        }
+    }
+
+    fn build(mut self) -> DiffStats {
+        self.stats.lines_deleted = self.old_lines.len();
+        self.stats.lines_added = self.new_lines.len();
+        self.stats.lines_moved = self.moved_lines.len();
+        self.stats
+    }
+}
+
+impl From<&RichHunks<'_>> for DiffStats {
+    fn from(hunks: &RichHunks<'_>) -> Self {
+        let mut builder = StatsBuilder::default();
+        builder.stats.hunks = hunks.0.len();
+
+        for hunk in &hunks.0 {
+            match hunk {
+                RichHunk::Old(hunk) => builder.add_hunk(hunk, Side::Old),
+                RichHunk::New(hunk) => builder.add_hunk(hunk, Side::New),
+                RichHunk::Changed(ChangedHunk { old, new }) => {
+                    builder.add_hunk(old, Side::Old);
+                    builder.add_hunk(new, Side::New);
+                }
+                RichHunk::Moved(hunk) => builder.add_moved(hunk),
+                RichHunk::Renamed(rename) => {
+                    builder.stats.renames += 1;
+
+                    for occurrence in &rename.occurrences {
+                        builder.add_changed(occurrence.old, Side::Old);
+                        builder.add_changed(occurrence.new, Side::New);
+                    }
+                }
+            }
+        }
+        builder.build()
+    }
+}
+
+/// What a diff changed, from the least to the most significant change.
+///
+/// This is useful in CI, for example to approve changes that only reformat code or only touch
+/// comments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Display)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeClass {
+    /// The documents are identical
+    NoChange,
+
+    /// The documents differ, but the diff is empty, so only whitespace or formatting changed
+    Formatting,
+
+    /// Every entry that the diff changed is part of a comment
+    Comments,
+
+    /// The diff changed code
+    Code,
+}
+
+impl ChangeClass {
+    /// Classify the change between two documents based on the hunks of their diff.
+    ///
+    /// This uses the same entries as the diff, so it respects the input processing config. For
+    /// example, changes to comments count as formatting if comments are excluded, and changes to
+    /// whitespace in string literals count as formatting if whitespace is stripped.
+    #[must_use]
+    pub fn classify(old_text: &str, new_text: &str, hunks: &RichHunks) -> Self {
+        if old_text == new_text {
+            return ChangeClass::NoChange;
+        }
+        let mut changed: Vec<&Entry> = Vec::new();
+
+        for hunk in &hunks.0 {
+            match hunk {
+                RichHunk::Old(hunk) | RichHunk::New(hunk) => changed.extend(hunk.entries()),
+                RichHunk::Changed(ChangedHunk { old, new }) => {
+                    changed.extend(old.entries());
+                    changed.extend(new.entries());
+                }
+                RichHunk::Moved(hunk) => {
+                    changed.extend(hunk.old.0.iter().flat_map(Hunk::entries));
+                    changed.extend(hunk.new.0.iter().flat_map(Hunk::entries));
+                }
+                RichHunk::Renamed(rename) => {
+                    for occurrence in &rename.occurrences {
+                        changed.extend(occurrence.old);
+                        changed.extend(occurrence.new);
+                    }
+                }
+            }
+        }
+
+        if changed.is_empty() {
+            ChangeClass::Formatting
+        } else if changed.iter().all(|entry| is_comment(entry)) {
+            ChangeClass::Comments
+        } else {
+            ChangeClass::Code
+        }
+    }
+
+    /// The exit code that diffsitter uses to report this class of change.
+    ///
+    /// More significant changes have higher exit codes, starting from 0 for no change.
+    #[must_use]
+    pub fn exit_code(self) -> i32 {
+        self as i32
+    }
+}
+
+/// Whether an entry is part of a comment.
+///
+/// Grammars name their comment nodes differently, such as `comment`, `line_comment` or
+/// `block_comment`, and some of them split comments into several leaves, so this checks whether
+/// any of the entry's ancestors is a comment.
+fn is_comment(entry: &Entry) -> bool {
+    let mut node = entry.reference;
+
+    while let Some(current) = node {
+        if current.kind().contains("comment") {
+            return true;
+        }
+        node = current.parent();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "static-grammar-libs")]
+    use crate::diff::{DiffConfig, compute_edit_script};
+    #[cfg(feature = "static-grammar-libs")]
+    use test_case::test_case;
+
+    #[test]
+    fn empty_diff_has_no_changes() {
+        assert_eq!(
+            DiffStats::from(&RichHunks(Vec::new())),
+            DiffStats::default()
+        );
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn stats_count_nodes_by_kind() {
+        use crate::input_processing::TreeSitterProcessor;
+        use crate::test_util::rust_entries;
+
+        let text_a = "fn main() {\n    let x = 1;\n    foo(x);\n}\n";
+        let text_b = "fn main() {\n    let x = 2;\n    foo(x, \"y\");\n    bar();\n}\n";
+        let (entries_a, entries_b) = rust_entries(text_a, text_b, &TreeSitterProcessor::default());
+        let hunks = compute_edit_script(&entries_a, &entries_b, &DiffConfig::default()).unwrap();
+        let stats = DiffStats::from(&hunks);
+
+        assert_eq!(stats.lines_deleted, 1);
+        assert_eq!(stats.lines_added, 3);
+        assert_eq!(stats.entries_deleted, 1);
+        assert_eq!(
+            stats.kinds["integer_literal"],
+            KindStats {
+                added: 1,
+                deleted: 1
+            }
+        );
+        assert_eq!(stats.kinds["identifier"].added, 1);
+    }
+
+    #[test]
+    fn classify_without_hunks() {
+        let hunks = RichHunks(Vec::new());
+        assert_eq!(
+            ChangeClass::classify("a(b);", "a(b);", &hunks),
+            ChangeClass::NoChange
+        );
+        assert_eq!(
+            ChangeClass::classify("a(b);", "a( b );", &hunks),
+            ChangeClass::Formatting
+        );
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test_case("fn main() {\n    // foo\n    foo(1);\n}\n", ChangeClass::Formatting ; "formatting")]
+    #[test_case("fn main() {\n    // bar\n    foo(1); /* a\n    b */\n}\n", ChangeClass::Comments ; "comments")]
+    #[test_case("fn main() {\n    // foo\n    foo(2);\n}\n", ChangeClass::Code ; "code")]
+    fn classify_changes(text_b: &str, expected: ChangeClass) {
+        use crate::input_processing::TreeSitterProcessor;
+        use crate::test_util::rust_entries;
+
+        let text_a = "fn main()   {\n    // foo\n    foo(1);\n}\n";
+        let (entries_a, entries_b) = rust_entries(text_a, text_b, &TreeSitterProcessor::default());
+        let hunks = compute_edit_script(&entries_a, &entries_b, &DiffConfig::default()).unwrap();
+
+        assert_eq!(ChangeClass::classify(text_a, text_b, &hunks), expected);
+    }
+}
@@ -0,0 +1,318 @@
+//! A summary of the symbols that changed between two documents.
+//!
+//! This gives an outline of a diff, such as "modified: `Config::try_from_file`", that reviewers
+//! can read before they dive into the hunks.
+
+use crate::ast_navigation::{Position, Span, SymbolInfo};
+use crate::diff::{ChangedHunk, Hunk, RichHunk, RichHunks};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use strum::Display;
+use tree_sitter::Point;
+
+/// How a symbol changed between the old and new documents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Display)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum SymbolChange {
+    /// The symbol only exists in the new document
+    Added,
+
+    /// The symbol only exists in the old document
+    Removed,
+
+    /// The symbol exists in both documents and the diff changed it
+    Modified,
+
+    /// The symbol exists in both documents and the diff didn't touch it
+    Unchanged,
+}
+
+/// A symbol along with how it changed between the two documents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SymbolSummary {
+    /// The name of the symbol, qualified with the names of the symbols that contain it
+    ///
+    /// For example, a method `new` in `impl Config` is called `Config::new`.
+    pub name: String,
+
+    /// The kind of the node that defines the symbol
+    pub kind: String,
+
+    /// How the symbol changed
+    pub change: SymbolChange,
+
+    /// Where the symbol is defined in the old document, if it exists there
+    pub old_span: Option<Span>,
+
+    /// Where the symbol is defined in the new document, if it exists there
+    pub new_span: Option<Span>,
+}
+
+/// Summarize how the symbols of the old document changed in the new document.
+///
+/// Symbols are matched by their qualified name and kind. If several symbols share a name and
+/// kind, such as two `impl` blocks for the same type, they're matched in the order they appear.
+/// A symbol that exists in both documents is modified if the diff has a change inside of it in
+/// either document.
+///
+/// Symbols from the old document come first in document order, followed by the symbols that
+/// were added to the new document.
+#[must_use]
+pub fn summarize_symbols(
+    old: &[SymbolInfo],
+    new: &[SymbolInfo],
+    hunks: &RichHunks,
+) -> Vec<SymbolSummary> {
+    let changes = ChangedPoints::from(hunks);
+    let old_names = qualified_names(old);
+    let new_names = qualified_names(new);
+
+    let mut unmatched: HashMap<(&str, &str), VecDeque<usize>> = HashMap::new();
+    for (i, (name, symbol)) in new_names.iter().zip(new).enumerate() {
+        unmatched
+            .entry((name, &symbol.kind))
+            .or_default()
+            .push_back(i);
+    }
+    let mut matched = vec![false; new.len()];
+    let mut summaries = Vec::new();
+
+    for (name, old_symbol) in old_names.iter().zip(old) {
+        let new_idx = unmatched
+            .get_mut(&(name.as_str(), old_symbol.kind.as_str()))
+            .and_then(VecDeque::pop_front);
+        let Some(new_idx) = new_idx else {
+            summaries.push(SymbolSummary {
+                name: name.clone(),
+                kind: old_symbol.kind.clone(),
+                change: SymbolChange::Removed,
+                old_span: Some(old_symbol.span.clone()),
+                new_span: None,
+            });
+            continue;
+        };
+        matched[new_idx] = true;
+        let new_symbol = &new[new_idx];
+        let is_modified = changes.old.iter().any(|&p| contains(&old_symbol.span, p))
+            || changes.new.iter().any(|&p| contains(&new_symbol.span, p));
+        summaries.push(SymbolSummary {
+            name: name.clone(),
+            kind: old_symbol.kind.clone(),
+            change: if is_modified {
+                SymbolChange::Modified
+            } else {
+                SymbolChange::Unchanged
+            },
+            old_span: Some(old_symbol.span.clone()),
+            new_span: Some(new_symbol.span.clone()),
+        });
+    }
+
+    for ((name, new_symbol), _) in new_names
+        .into_iter()
+        .zip(new)
+        .zip(matched)
+        .filter(|(_, matched)| !matched)
+    {
+        summaries.push(SymbolSummary {
+            name,
+            kind: new_symbol.kind.clone(),
+            change: SymbolChange::Added,
+            old_span: None,
+            new_span: Some(new_symbol.span.clone()),
+        });
+    }
+    summaries
+}
+
+/// The start positions of the entries that the diff changed in each document.
+#[derive(Debug, Default)]
+struct ChangedPoints {
+    old: Vec<Point>,
+    new: Vec<Point>,
+}
+
+impl ChangedPoints {
+    fn add_old(&mut self, hunk: &Hunk) {
+        self.old
+            .extend(hunk.entries().map(|entry| entry.start_position));
+    }
+
+    fn add_new(&mut self, hunk: &Hunk) {
+        self.new
+            .extend(hunk.entries().map(|entry| entry.start_position));
+    }
+}
+
+impl From<&RichHunks<'_>> for ChangedPoints {
+    fn from(hunks: &RichHunks<'_>) -> Self {
+        let mut points = ChangedPoints::default();
+
+        for hunk in &hunks.0 {
+            match hunk {
+                RichHunk::Old(hunk) => points.add_old(hunk),
+                RichHunk::New(hunk) => points.add_new(hunk),
+                RichHunk::Changed(ChangedHunk { old, new }) => {
+                    points.add_old(old);
+                    points.add_new(new);
+                }
+                // Moving text changes both the symbol it was moved out of and the one it was
+                // moved into
+                RichHunk::Moved(hunk) => {
+                    hunk.old.0.iter().for_each(|hunk| points.add_old(hunk));
+                    hunk.new.0.iter().for_each(|hunk| points.add_new(hunk));
+                }
+                RichHunk::Renamed(rename) => {
+                    for occurrence in &rename.occurrences {
+                        points
+                            .old
+                            .extend(occurrence.old.iter().map(|entry| entry.start_position));
+                        points
+                            .new
+                            .extend(occurrence.new.iter().map(|entry| entry.start_position));
+                    }
+                }
+            }
+        }
+        points
+    }
+}
+
+/// Qualify the name of each symbol with the names of the symbols that contain it.
+fn qualified_names(symbols: &[SymbolInfo]) -> Vec<String> {
+    let mut order: Vec<usize> = (0..symbols.len()).collect();
+    // Outer symbols sort before the symbols they contain
+    order.sort_by_key(|&i| {
+        let span = &symbols[i].span;
+        (key(&span.start), std::cmp::Reverse(key(&span.end)))
+    });
+    let mut names = vec![String::new(); symbols.len()];
+    let mut parents: Vec<usize> = Vec::new();
+
+    for i in order {
+        let span = &symbols[i].span;
+        while parents
+            .last()
+            .is_some_and(|&parent| key(&symbols[parent].span.end) < key(&span.end))
+        {
+            parents.pop();
+        }
+        names[i] = match parents.last() {
+            Some(&parent) => format!("{}::{}", names[parent], symbols[i].name),
+            None => symbols[i].name.clone(),
+        };
+        parents.push(i);
+    }
+    names
+}
+
+/// Whether a point is inside of a span.
+fn contains(span: &Span, point: Point) -> bool {
+    let point = (point.row, point.column);
+    key(&span.start) <= point && point < key(&span.end)
+}
+
+/// A key that orders positions by where they are in the document.
+fn key(position: &Position) -> (usize, usize) {
+    (position.line, position.column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(name: &str, kind: &str, start: (usize, usize), end: (usize, usize)) -> SymbolInfo {
+        SymbolInfo {
+            name: name.into(),
+            kind: kind.into(),
+            span: Span {
+                start: Position {
+                    line: start.0,
+                    column: start.1,
+                },
+                end: Position {
+                    line: end.0,
+                    column: end.1,
+                },
+            },
+            signature: String::new(),
+        }
+    }
+
+    #[test]
+    fn qualified_names_include_parents() {
+        let symbols = [
+            symbol("Config", "impl_item", (0, 0), (6, 1)),
+            symbol("new", "function_item", (1, 4), (3, 5)),
+            symbol("load", "function_item", (4, 4), (5, 5)),
+            symbol("main", "function_item", (8, 0), (8, 12)),
+        ];
+        assert_eq!(
+            qualified_names(&symbols),
+            ["Config", "Config::new", "Config::load", "main"]
+        );
+    }
+
+    #[test]
+    fn summarize_without_changes() {
+        let old = [
+            symbol("kept", "function_item", (0, 0), (0, 12)),
+            symbol("removed", "function_item", (1, 0), (1, 15)),
+        ];
+        let new = [
+            symbol("added", "struct_item", (0, 0), (0, 15)),
+            symbol("kept", "function_item", (2, 0), (2, 12)),
+        ];
+        let changes: Vec<_> = summarize_symbols(&old, &new, &RichHunks(Vec::new()))
+            .into_iter()
+            .map(|summary| (summary.name, summary.change))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                ("kept".to_string(), SymbolChange::Unchanged),
+                ("removed".to_string(), SymbolChange::Removed),
+                ("added".to_string(), SymbolChange::Added),
+            ]
+        );
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn summarize_modified_symbols() {
+        use crate::ast_navigation::list_symbols;
+        use crate::diff::{DiffConfig, compute_edit_script};
+        use crate::input_processing::TreeSitterProcessor;
+        use crate::parse::{GrammarConfig, generate_language};
+        use crate::test_util::parse_rust;
+
+        let text_a = "struct Config {}\n\nimpl Config {\n    fn load() {\n        read(1);\n    }\n\n    fn save() {}\n}\n";
+        let text_b = "struct Config {}\n\nimpl Config {\n    fn load() {\n        read(2);\n    }\n\n    fn save() {}\n}\n\nconst LIMIT: u32 = 3;\n";
+        let language = generate_language("rust", &GrammarConfig::default()).unwrap();
+        let tree_a = parse_rust(text_a);
+        let tree_b = parse_rust(text_b);
+        let processor = TreeSitterProcessor::default();
+        let entries_a = processor.process(&tree_a, text_a, "rust");
+        let entries_b = processor.process(&tree_b, text_b, "rust");
+        let hunks = compute_edit_script(&entries_a, &entries_b, &DiffConfig::default()).unwrap();
+        let symbols_a = list_symbols(&tree_a, text_a, &language, "rust");
+        let symbols_b = list_symbols(&tree_b, text_b, &language, "rust");
+        let mut changes: Vec<_> = summarize_symbols(&symbols_a, &symbols_b, &hunks)
+            .into_iter()
+            .map(|summary| (summary.change, summary.name))
+            .collect();
+        changes.sort();
+
+        assert_eq!(
+            changes,
+            [
+                (SymbolChange::Added, "LIMIT".to_string()),
+                (SymbolChange::Modified, "Config".to_string()),
+                (SymbolChange::Modified, "Config::load".to_string()),
+                (SymbolChange::Unchanged, "Config".to_string()),
+                (SymbolChange::Unchanged, "Config::save".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,30 @@
+//! Helpers that are shared by the unit tests of several modules.
+
+use crate::input_processing::{Entry, TreeSitterProcessor};
+use crate::parse::{GrammarConfig, generate_language};
+use tree_sitter::Tree;
+
+/// Parse a rust document.
+pub(crate) fn parse_rust(text: &str) -> Tree {
+    let language = generate_language("rust", &GrammarConfig::default()).unwrap();
+    let mut parser = tree_sitter::Parser::new();
+    parser.set_language(&language).unwrap();
+    parser.parse(text, None).unwrap()
+}
+
+/// Parse two rust documents and generate their entries with `processor`.
+///
+/// The trees are leaked so the entries can outlive this function, which is fine for the small
+/// documents used in tests.
+pub(crate) fn rust_entries<'a>(
+    text_a: &'a str,
+    text_b: &'a str,
+    processor: &TreeSitterProcessor,
+) -> (Vec<Entry<'a>>, Vec<Entry<'a>>) {
+    let tree_a: &'a Tree = Box::leak(Box::new(parse_rust(text_a)));
+    let tree_b: &'a Tree = Box::leak(Box::new(parse_rust(text_b)));
+    (
+        processor.process(tree_a, text_a, "rust"),
+        processor.process(tree_b, text_b, "rust"),
+    )
+}
@@ -0,0 +1,973 @@
+//! Structural diffs that operate on the syntax trees of the documents.
+//!
+//! The flat diff in [`crate::diff`] only sees the leaves of each tree, so it can't tell that a
+//! whole function was moved, it just sees a large deletion and a large addition. This module
+//! implements the matching algorithm from GumTree (Falleri et al., "Fine-grained and accurate
+//! source code differencing"), which maps the nodes of the old tree to the nodes of the new tree
+//! in two phases:
+//!
+//! 1. A top-down phase that greedily matches the largest isomorphic subtrees, using a structural
+//!    hash of each subtree.
+//! 2. A bottom-up phase that matches container nodes if enough of their descendants were matched,
+//!    and then tries to recover matches between their remaining children.
+//!
+//! The mappings are then used to derive insertions, deletions, updates and moves of nodes, which
+//! can be converted to [hunks](RichHunks) for the renderers.
+
+use crate::diff::{
+    DocumentType, Engine, Hunks, MovedHunk, Myers, RichHunks, RichHunksBuilder,
+    longest_increasing_subsequence,
+};
+use crate::input_processing::{EditType, Entry, LeafId};
+use anyhow::Result;
+use log::{debug, info};
+use logging_timer::time;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use tree_sitter::Node as TSNode;
+use tree_sitter::Tree as TSTree;
+
+/// The minimum height of a subtree for it to be matched in the top-down phase.
+///
+/// Leaves have a height of 1. Matching single tokens in the top-down phase creates a lot of
+/// spurious matches, so those are left to the bottom-up phase.
+const MIN_HEIGHT: usize = 2;
+
+/// The minimum ratio of common descendants for two container nodes to be matched in the bottom-up
+/// phase.
+const MIN_DICE: f64 = 0.5;
+
+/// The index of the root node in a [`SyntaxTree`].
+const ROOT: usize = 0;
+
+/// An edit operation on the nodes of a syntax tree.
+///
+/// Insertions and deletions are only reported for the root of each inserted or deleted subtree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeEdit<'a> {
+    /// A node that only exists in the new document
+    Insert(TSNode<'a>),
+
+    /// A node that only exists in the old document
+    Delete(TSNode<'a>),
+
+    /// A leaf whose text changed between the old and new document
+    Update { old: TSNode<'a>, new: TSNode<'a> },
+
+    /// A node that was moved to a different parent, or reordered within its parent
+    Move { old: TSNode<'a>, new: TSNode<'a> },
+}
+
+/// A node in a [`SyntaxTree`].
+#[derive(Debug)]
+struct TreeNode<'a> {
+    /// The tree-sitter node this corresponds to
+    reference: TSNode<'a>,
+
+    /// The processed text of the node if it's a leaf, otherwise this is empty
+    label: String,
+
+    /// The range of entries in the entry vector that came from this node
+    ///
+    /// This is empty for nodes that aren't leaves.
+    entries: Range<usize>,
+
+    /// The index of the parent node
+    parent: Option<usize>,
+
+    /// The indices of the child nodes, in ascending order
+    children: Vec<usize>,
+
+    /// The height of the subtree rooted at this node, leaves have a height of 1
+    height: usize,
+
+    /// The number of nodes in the subtree rooted at this node, including itself
+    size: usize,
+
+    /// A structural hash of the subtree rooted at this node
+    hash: u64,
+}
+
+/// A syntax tree that only contains the nodes that lead to an entry.
+///
+/// The nodes are stored in pre-order, which means that the descendants of a node at index `i`
+/// are exactly the nodes at `i + 1..i + size`. This makes descendant checks and mapping
+/// isomorphic subtrees cheap.
+#[derive(Debug)]
+struct SyntaxTree<'a> {
+    nodes: Vec<TreeNode<'a>>,
+}
+
+impl<'a> SyntaxTree<'a> {
+    /// Build a syntax tree from a tree-sitter tree and the entries that were generated from it.
+    ///
+    /// Leaves are the nodes that the entries refer to, which means that filtered nodes are left
+    /// out and pseudo-leaves are treated as leaves.
+    fn new(tree: &'a TSTree, entries: &[Entry<'a>]) -> Self {
+        // Entries from the same node are always contiguous in the entry vector
+        let mut leaf_entries: HashMap<LeafId, Range<usize>> = HashMap::new();
+
+        for (i, entry) in entries.iter().enumerate() {
+            leaf_entries
+                .entry(entry.leaf_id())
+                .and_modify(|range| range.end = i + 1)
+                .or_insert(i..i + 1);
+        }
+        let mut syntax_tree = SyntaxTree { nodes: Vec::new() };
+        let root = tree.root_node();
+
+        // We always need a root node, even if the document is empty, so that both trees can be
+        // matched.
+        if syntax_tree
+            .build(root, None, entries, &leaf_entries)
+            .is_none()
+        {
+            syntax_tree.push(root, None, String::new(), 0..0);
+            syntax_tree.finalize(ROOT);
+        }
+        syntax_tree
+    }
+
+    /// Recursively add a node and its descendants to the tree.
+    ///
+    /// This returns the index of the node, or `None` if neither the node nor any of its
+    /// descendants correspond to an entry.
+    fn build(
+        &mut self,
+        node: TSNode<'a>,
+        parent: Option<usize>,
+        entries: &[Entry<'a>],
+        leaf_entries: &HashMap<LeafId, Range<usize>>,
+    ) -> Option<usize> {
+        if let Some(range) = leaf_entries.get(&LeafId::Node(node.id())) {
+            let label = entries[range.clone()]
+                .iter()
+                .map(|entry| entry.text.as_ref())
+                .collect();
+            let idx = self.push(node, parent, label, range.clone());
+            self.finalize(idx);
+            return Some(idx);
+        }
+
+        if node.child_count() == 0 {
+            return None;
+        }
+        let idx = self.push(node, parent, String::new(), 0..0);
+        let mut cursor = node.walk();
+        let children: Vec<TSNode<'a>> = node.children(&mut cursor).collect();
+
+        for child in children {
+            if let Some(child_idx) = self.build(child, Some(idx), entries, leaf_entries) {
+                self.nodes[idx].children.push(child_idx);
+            }
+        }
+
+        // None of the descendants made it into the entry vector. Every descendant that was added
+        // has already removed itself, so this only removes the current node.
+        if self.nodes[idx].children.is_empty() {
+            self.nodes.truncate(idx);
+            return None;
+        }
+        self.finalize(idx);
+        Some(idx)
+    }
+
+    /// Add a node without computing any of its derived fields.
+    fn push(
+        &mut self,
+        reference: TSNode<'a>,
+        parent: Option<usize>,
+        label: String,
+        entries: Range<usize>,
+    ) -> usize {
+        self.nodes.push(TreeNode {
+            reference,
+            label,
+            entries,
+            parent,
+            children: Vec::new(),
+            height: 0,
+            size: 0,
+            hash: 0,
+        });
+        self.nodes.len() - 1
+    }
+
+    /// Compute the height, size and hash of a node once all of its children have been added.
+    fn finalize(&mut self, idx: usize) {
+        let node = &self.nodes[idx];
+        let mut hasher = DefaultHasher::new();
+        node.reference.kind_id().hash(&mut hasher);
+        node.label.hash(&mut hasher);
+        let mut height = 0;
+        let mut size = 1;
+
+        for &child in &node.children {
+            let child = &self.nodes[child];
+            child.hash.hash(&mut hasher);
+            height = height.max(child.height);
+            size += child.size;
+        }
+        let node = &mut self.nodes[idx];
+        node.height = height + 1;
+        node.size = size;
+        node.hash = hasher.finish();
+    }
+
+    fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn kind_id(&self, idx: usize) -> u16 {
+        self.nodes[idx].reference.kind_id()
+    }
+
+    fn is_leaf(&self, idx: usize) -> bool {
+        self.nodes[idx].children.is_empty()
+    }
+
+    /// The indices of every descendant of a node, not including the node itself.
+    fn descendants(&self, idx: usize) -> Range<usize> {
+        idx + 1..idx + self.nodes[idx].size
+    }
+
+    /// Count how many times each subtree hash occurs in the tree.
+    fn hash_counts(&self) -> HashMap<u64, usize> {
+        let mut counts = HashMap::new();
+
+        for node in &self.nodes {
+            *counts.entry(node.hash).or_default() += 1;
+        }
+        counts
+    }
+}
+
+/// Check whether two subtrees are identical.
+///
+/// Since the nodes are stored in pre-order, two subtrees with the same size are identical if every
+/// pair of corresponding nodes has the same kind, label and number of children.
+fn is_isomorphic(old: &SyntaxTree, a: usize, new: &SyntaxTree, b: usize) -> bool {
+    let (x, y) = (&old.nodes[a], &new.nodes[b]);
+    x.hash == y.hash
+        && x.size == y.size
+        && (0..x.size).all(|offset| {
+            let (x, y) = (&old.nodes[a + offset], &new.nodes[b + offset]);
+            x.reference.kind_id() == y.reference.kind_id()
+                && x.label == y.label
+                && x.children.len() == y.children.len()
+        })
+}
+
+/// The mappings between the nodes of two syntax trees.
+struct Matcher<'t, 'a> {
+    old: &'t SyntaxTree<'a>,
+    new: &'t SyntaxTree<'a>,
+
+    /// The node in the new tree that each node in the old tree is mapped to
+    old_to_new: Vec<Option<usize>>,
+
+    /// The node in the old tree that each node in the new tree is mapped to
+    new_to_old: Vec<Option<usize>>,
+}
+
+impl<'t, 'a> Matcher<'t, 'a> {
+    fn new(old: &'t SyntaxTree<'a>, new: &'t SyntaxTree<'a>) -> Self {
+        Self {
+            old,
+            new,
+            old_to_new: vec![None; old.len()],
+            new_to_old: vec![None; new.len()],
+        }
+    }
+
+    fn link(&mut self, a: usize, b: usize) {
+        self.old_to_new[a] = Some(b);
+        self.new_to_old[b] = Some(a);
+    }
+
+    /// Map every node in two isomorphic subtrees to each other.
+    fn link_subtrees(&mut self, a: usize, b: usize) {
+        for offset in 0..self.old.nodes[a].size {
+            self.link(a + offset, b + offset);
+        }
+    }
+
+    /// Whether neither subtree has any mapped nodes.
+    fn are_subtrees_unmatched(&self, a: usize, b: usize) -> bool {
+        (a..a + self.old.nodes[a].size).all(|x| self.old_to_new[x].is_none())
+            && (b..b + self.new.nodes[b].size).all(|y| self.new_to_old[y].is_none())
+    }
+
+    /// The nodes in the new tree that the descendants of `a` are mapped to, in ascending order.
+    fn mapped_descendants(&self, a: usize) -> Vec<usize> {
+        let mut targets: Vec<usize> = self
+            .old
+            .descendants(a)
+            .filter_map(|x| self.old_to_new[x])
+            .collect();
+        targets.sort_unstable();
+        targets
+    }
+
+    /// The ratio of descendants of `a` and `b` that are mapped to each other.
+    ///
+    /// `targets` are the [mapped descendants](Self::mapped_descendants) of `a`. The descendants of
+    /// `b` are a range of pre-order indices, so the common descendants are found with two binary
+    /// searches instead of walking the descendants of `a` for every candidate.
+    fn dice(&self, targets: &[usize], a: usize, b: usize) -> f64 {
+        let descendants = self.new.descendants(b);
+        let common = targets.partition_point(|&y| y < descendants.end)
+            - targets.partition_point(|&y| y < descendants.start);
+        let total = self.old.descendants(a).len() + self.new.descendants(b).len();
+
+        if total == 0 {
+            return 0.0;
+        }
+        2.0 * common as f64 / total as f64
+    }
+
+    /// The GumTree top-down phase.
+    ///
+    /// This visits the subtrees of both documents from tallest to shortest and maps the subtrees
+    /// that are identical. If a subtree is identical to multiple other subtrees we defer the
+    /// decision and prefer the pairs whose parents are the most similar.
+    fn match_top_down(&mut self) {
+        let old_hash_counts = self.old.hash_counts();
+        let new_hash_counts = self.new.hash_counts();
+        let mut old_queue = vec![ROOT];
+        let mut new_queue = vec![ROOT];
+        let mut candidates: Vec<(usize, usize)> = Vec::new();
+
+        loop {
+            let old_height = max_height(self.old, &old_queue);
+            let new_height = max_height(self.new, &new_queue);
+
+            if old_height.min(new_height) < MIN_HEIGHT {
+                break;
+            }
+
+            // We can only compare subtrees with the same height, so we open the taller subtrees
+            // until the heights line up.
+            if old_height > new_height {
+                for a in pop_height(self.old, &mut old_queue, old_height) {
+                    old_queue.extend(&self.old.nodes[a].children);
+                }
+                continue;
+            }
+
+            if new_height > old_height {
+                for b in pop_height(self.new, &mut new_queue, new_height) {
+                    new_queue.extend(&self.new.nodes[b].children);
+                }
+                continue;
+            }
+            let old_nodes = pop_height(self.old, &mut old_queue, old_height);
+            let new_nodes = pop_height(self.new, &mut new_queue, new_height);
+            let mut old_has_match = vec![false; old_nodes.len()];
+            let mut new_has_match = vec![false; new_nodes.len()];
+
+            for (i, &a) in old_nodes.iter().enumerate() {
+                for (j, &b) in new_nodes.iter().enumerate() {
+                    if !is_isomorphic(self.old, a, self.new, b) {
+                        continue;
+                    }
+                    old_has_match[i] = true;
+                    new_has_match[j] = true;
+                    let hash = self.old.nodes[a].hash;
+
+                    if old_hash_counts[&hash] == 1 && new_hash_counts[&hash] == 1 {
+                        self.link_subtrees(a, b);
+                    } else {
+                        candidates.push((a, b));
+                    }
+                }
+            }
+
+            for (i, &a) in old_nodes.iter().enumerate() {
+                if !old_has_match[i] {
+                    old_queue.extend(&self.old.nodes[a].children);
+                }
+            }
+
+            for (j, &b) in new_nodes.iter().enumerate() {
+                if !new_has_match[j] {
+                    new_queue.extend(&self.new.nodes[b].children);
+                }
+            }
+        }
+
+        // Candidates often share a parent, so its mapped descendants are only collected once
+        let mut parent_targets: HashMap<usize, Vec<usize>> = HashMap::new();
+        let mut scored_candidates: Vec<(f64, usize, usize)> = candidates
+            .into_iter()
+            .map(|(a, b)| {
+                let score = match (self.old.nodes[a].parent, self.new.nodes[b].parent) {
+                    (Some(x), Some(y)) => {
+                        let targets = parent_targets
+                            .entry(x)
+                            .or_insert_with(|| self.mapped_descendants(x));
+                        self.dice(targets, x, y)
+                    }
+                    _ => 0.0,
+                };
+                (score, a, b)
+            })
+            .collect();
+        scored_candidates.sort_by(|x, y| y.0.total_cmp(&x.0));
+
+        for (_, a, b) in scored_candidates {
+            if self.are_subtrees_unmatched(a, b) {
+                self.link_subtrees(a, b);
+            }
+        }
+    }
+
+    /// The GumTree bottom-up phase.
+    ///
+    /// This visits the unmatched container nodes of the old tree in post-order and maps each one to
+    /// the unmatched container of the same kind in the new tree that shares the most mapped
+    /// descendants with it.
+    fn match_bottom_up(&mut self) {
+        // Visiting pre-order indices in reverse guarantees that children are visited before their
+        // parents.
+        for a in (0..self.old.len()).rev() {
+            if a == ROOT || self.old_to_new[a].is_some() || self.old.is_leaf(a) {
+                continue;
+            }
+            let targets = self.mapped_descendants(a);
+            let best = self
+                .bottom_up_candidates(a, &targets)
+                .into_iter()
+                .map(|b| (self.dice(&targets, a, b), b))
+                .max_by(|x, y| x.0.total_cmp(&y.0));
+
+            if let Some((dice, b)) = best {
+                if dice > MIN_DICE {
+                    self.link(a, b);
+                    self.recover(a, b);
+                }
+            }
+        }
+
+        // The roots always correspond to each other
+        if self.old_to_new[ROOT].is_none() && self.new_to_old[ROOT].is_none() {
+            self.link(ROOT, ROOT);
+            self.recover(ROOT, ROOT);
+        }
+    }
+
+    /// Find the unmatched ancestors of `targets`, the nodes that the descendants of `a` are mapped
+    /// to.
+    ///
+    /// Any other container has no descendants in common with `a`. Only ancestors with the same kind
+    /// as `a` are considered, and the candidates are sorted so the results are deterministic.
+    fn bottom_up_candidates(&self, a: usize, targets: &[usize]) -> Vec<usize> {
+        let kind_id = self.old.kind_id(a);
+        let mut visited = HashSet::new();
+        let mut candidates = Vec::new();
+
+        for &target in targets {
+            let mut y = target;
+
+            while let Some(parent) = self.new.nodes[y].parent {
+                // Every ancestor above this one has already been checked
+                if !visited.insert(parent) {
+                    break;
+                }
+
+                if self.new_to_old[parent].is_none() && self.new.kind_id(parent) == kind_id {
+                    candidates.push(parent);
+                }
+                y = parent;
+            }
+        }
+        candidates.sort_unstable();
+        candidates
+    }
+
+    /// Try to map the unmatched children of two mapped nodes.
+    ///
+    /// This is a simplified version of the recovery step in GumTree. We first map children that
+    /// are identical, then map children whose kind only shows up once among the unmatched children
+    /// of both nodes.
+    fn recover(&mut self, a: usize, b: usize) {
+        let old_children = self.old.nodes[a].children.clone();
+        let new_children = self.new.nodes[b].children.clone();
+
+        for &x in &old_children {
+            let identical = new_children.iter().copied().find(|&y| {
+                is_isomorphic(self.old, x, self.new, y) && self.are_subtrees_unmatched(x, y)
+            });
+
+            if let Some(y) = identical {
+                self.link_subtrees(x, y);
+            }
+        }
+
+        let old_remaining: Vec<usize> = old_children
+            .into_iter()
+            .filter(|&x| self.old_to_new[x].is_none())
+            .collect();
+        let new_remaining: Vec<usize> = new_children
+            .into_iter()
+            .filter(|&y| self.new_to_old[y].is_none())
+            .collect();
+
+        for &x in &old_remaining {
+            let kind_id = self.old.kind_id(x);
+            let old_same_kind = old_remaining
+                .iter()
+                .filter(|&&other| self.old.kind_id(other) == kind_id)
+                .count();
+            let new_same_kind: Vec<usize> = new_remaining
+                .iter()
+                .copied()
+                .filter(|&y| self.new.kind_id(y) == kind_id)
+                .collect();
+
+            if old_same_kind != 1 || new_same_kind.len() != 1 {
+                continue;
+            }
+            let y = new_same_kind[0];
+
+            if self.old.is_leaf(x) != self.new.is_leaf(y) {
+                continue;
+            }
+            self.link(x, y);
+
+            if !self.old.is_leaf(x) {
+                self.recover(x, y);
+            }
+        }
+    }
+}
+
+/// The maximum height of the subtrees in a queue.
+fn max_height(tree: &SyntaxTree, queue: &[usize]) -> usize {
+    queue
+        .iter()
+        .map(|&idx| tree.nodes[idx].height)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Remove and return every subtree in the queue with the given height.
+fn pop_height(tree: &SyntaxTree, queue: &mut Vec<usize>, height: usize) -> Vec<usize> {
+    let (popped, remaining) = queue
+        .iter()
+        .partition(|&&idx| tree.nodes[idx].height == height);
+    *queue = remaining;
+    popped
+}
+
+/// How an entry is represented in the diff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EntryStatus {
+    /// The entry is the same in both documents
+    Unchanged,
+
+    /// The entry was added, deleted or updated
+    Changed,
+
+    /// The entry is part of the moved block with the given index
+    Moved(usize),
+}
+
+/// The result of a structural diff between two documents.
+pub struct TreeDiff<'a> {
+    old: SyntaxTree<'a>,
+    new: SyntaxTree<'a>,
+    old_entries: &'a [Entry<'a>],
+    new_entries: &'a [Entry<'a>],
+    old_to_new: Vec<Option<usize>>,
+    new_to_old: Vec<Option<usize>>,
+
+    /// The nodes in the old tree that are the root of a moved subtree
+    moved: Vec<bool>,
+
+    /// The edit script that transforms the old tree into the new one
+    edits: Vec<TreeEdit<'a>>,
+}
+
+impl<'a> TreeDiff<'a> {
+    /// The edit operations that transform the old tree into the new one.
+    #[must_use]
+    pub fn edits(&self) -> &[TreeEdit<'a>] {
+        &self.edits
+    }
+
+    /// Convert the structural diff into hunks that can be displayed by the renderers.
+    ///
+    /// Entries from inserted, deleted and updated leaves become regular old and new hunks, and the
+    /// unchanged entries of moved subtrees are grouped into moved hunks.
+    pub fn rich_hunks(&self) -> Result<RichHunks<'a>> {
+        let (old_status, new_status) = self.entry_statuses();
+        let move_count = self.moved.iter().filter(|&&x| x).count();
+        let mut moved_hunks: Vec<Option<MovedHunk<'a>>> = (0..move_count)
+            .map(|_| {
+                Some(MovedHunk {
+                    old: Hunks::new(),
+                    new: Hunks::new(),
+                })
+            })
+            .collect();
+
+        for (entry, status) in self.old_entries.iter().zip(&old_status) {
+            if let EntryStatus::Moved(group) = status {
+                moved_hunks[*group].as_mut().unwrap().old.push_back(entry)?;
+            }
+        }
+
+        for (entry, status) in self.new_entries.iter().zip(&new_status) {
+            if let EntryStatus::Moved(group) = status {
+                moved_hunks[*group].as_mut().unwrap().new.push_back(entry)?;
+            }
+        }
+
+        // We walk both documents in lockstep, using the unchanged entries as synchronization
+        // points, so the hunks come out roughly in document order.
+        let mut builder = RichHunksBuilder::new();
+        let (mut i, mut j) = (0, 0);
+
+        while i < self.old_entries.len() || j < self.new_entries.len() {
+            while i < self.old_entries.len() && old_status[i] != EntryStatus::Unchanged {
+                if old_status[i] == EntryStatus::Changed {
+                    builder.push_back(DocumentType::Old(&self.old_entries[i]))?;
+                }
+                i += 1;
+            }
+
+            while j < self.new_entries.len() && new_status[j] != EntryStatus::Unchanged {
+                match new_status[j] {
+                    EntryStatus::Changed => {
+                        builder.push_back(DocumentType::New(&self.new_entries[j]))?;
+                    }
+                    EntryStatus::Moved(group) => {
+                        if let Some(hunk) = moved_hunks[group].take() {
+                            builder.push_moved(hunk);
+                        }
+                    }
+                    EntryStatus::Unchanged => unreachable!(),
+                }
+                j += 1;
+            }
+            i += 1;
+            j += 1;
+        }
+        Ok(builder.build())
+    }
+
+    /// Determine how each entry in both documents should be represented in the diff.
+    fn entry_statuses(&self) -> (Vec<EntryStatus>, Vec<EntryStatus>) {
+        let mut old_status = vec![EntryStatus::Unchanged; self.old_entries.len()];
+        let mut new_status = vec![EntryStatus::Unchanged; self.new_entries.len()];
+
+        // Assign every node to the closest moved subtree that it's a part of. Parents are always
+        // visited before their children because the nodes are in pre-order.
+        let mut move_groups: Vec<Option<usize>> = vec![None; self.old.len()];
+        let mut move_count = 0;
+
+        for a in 0..self.old.len() {
+            move_groups[a] = if self.moved[a] {
+                move_count += 1;
+                Some(move_count - 1)
+            } else {
+                self.old.nodes[a]
+                    .parent
+                    .and_then(|parent| move_groups[parent])
+            };
+        }
+
+        for a in (0..self.old.len()).filter(|&a| self.old.is_leaf(a)) {
+            let old_range = self.old.nodes[a].entries.clone();
+            let unchanged_status =
+                move_groups[a].map_or(EntryStatus::Unchanged, EntryStatus::Moved);
+
+            let Some(b) = self.old_to_new[a] else {
+                old_status[old_range].fill(EntryStatus::Changed);
+                continue;
+            };
+            let new_range = self.new.nodes[b].entries.clone();
+
+            if self.old.nodes[a].label == self.new.nodes[b].label {
+                old_status[old_range].fill(unchanged_status);
+                new_status[new_range].fill(unchanged_status);
+                continue;
+            }
+
+            // Leaves can be split into multiple entries, so we diff the entries of updated leaves
+            // to only highlight the parts that changed.
+            old_status[old_range.clone()].fill(unchanged_status);
+            new_status[new_range.clone()].fill(unchanged_status);
+            let old_leaf_entries = &self.old_entries[old_range.clone()];
+            let new_leaf_entries = &self.new_entries[new_range.clone()];
+
+            for edit in Myers::default().diff(old_leaf_entries, new_leaf_entries) {
+                match edit {
+                    EditType::Deletion(entry) => {
+                        let offset = entry_offset(old_leaf_entries, entry);
+                        old_status[old_range.start + offset] = EntryStatus::Changed;
+                    }
+                    EditType::Addition(entry) => {
+                        let offset = entry_offset(new_leaf_entries, entry);
+                        new_status[new_range.start + offset] = EntryStatus::Changed;
+                    }
+                }
+            }
+        }
+
+        for b in 0..self.new.len() {
+            if self.new.is_leaf(b) && self.new_to_old[b].is_none() {
+                new_status[self.new.nodes[b].entries.clone()].fill(EntryStatus::Changed);
+            }
+        }
+        (old_status, new_status)
+    }
+}
+
+/// Find the offset of an entry within a slice of entries.
+fn entry_offset(entries: &[Entry], entry: &Entry) -> usize {
+    entries
+        .iter()
+        .position(|x| std::ptr::eq(x, entry))
+        .expect("the entry must come from the slice")
+}
+
+/// Compute the structural diff between two documents.
+///
+/// The entries must be the entries that were generated from each tree, the structural diff only
+/// considers the nodes that lead to an entry, so any input processing settings are respected.
+#[time("info", "tree_diff::{}")]
+pub fn diff_trees<'a>(
+    old_tree: &'a TSTree,
+    old_entries: &'a [Entry<'a>],
+    new_tree: &'a TSTree,
+    new_entries: &'a [Entry<'a>],
+) -> TreeDiff<'a> {
+    let old = SyntaxTree::new(old_tree, old_entries);
+    let new = SyntaxTree::new(new_tree, new_entries);
+    debug!("Matching trees with {} and {} nodes", old.len(), new.len());
+    let mut matcher = Matcher::new(&old, &new);
+    matcher.match_top_down();
+    matcher.match_bottom_up();
+    let Matcher {
+        old_to_new,
+        new_to_old,
+        ..
+    } = matcher;
+    let moved = find_moves(&old, &new, &old_to_new);
+    let edits = generate_edits(&old, &new, &old_to_new, &new_to_old, &moved);
+    info!("Generated {} tree edits", edits.len());
+
+    TreeDiff {
+        old,
+        new,
+        old_entries,
+        new_entries,
+        old_to_new,
+        new_to_old,
+        moved,
+        edits,
+    }
+}
+
+/// Find the nodes in the old tree that are the root of a moved subtree.
+///
+/// A mapped node was moved if its parent isn't mapped to the parent of its counterpart, or if it
+/// was reordered relative to its siblings. Reordered siblings are found by taking the longest
+/// sequence of siblings that kept their relative order, everything outside of that sequence is
+/// considered to have moved.
+fn find_moves(old: &SyntaxTree, new: &SyntaxTree, old_to_new: &[Option<usize>]) -> Vec<bool> {
+    let mut moved = vec![false; old.len()];
+
+    for a in 0..old.len() {
+        let Some(b) = old_to_new[a] else {
+            continue;
+        };
+
+        if let Some(parent) = old.nodes[a].parent {
+            if old_to_new[parent] != new.nodes[b].parent {
+                moved[a] = true;
+            }
+        }
+
+        // Check if the children that stayed under this node were reordered
+        let (siblings, positions): (Vec<usize>, Vec<usize>) = old.nodes[a]
+            .children
+            .iter()
+            .filter_map(|&x| {
+                let y = old_to_new[x]?;
+                new.nodes[b]
+                    .children
+                    .binary_search(&y)
+                    .ok()
+                    .map(|position| (x, position))
+            })
+            .unzip();
+        let mut in_order = vec![false; siblings.len()];
+
+        for i in longest_increasing_subsequence(&positions) {
+            in_order[i] = true;
+        }
+
+        for (x, in_order) in siblings.into_iter().zip(in_order) {
+            if !in_order {
+                moved[x] = true;
+            }
+        }
+    }
+    moved
+}
+
+/// Generate the edit operations from the mappings between two trees.
+fn generate_edits<'a>(
+    old: &SyntaxTree<'a>,
+    new: &SyntaxTree<'a>,
+    old_to_new: &[Option<usize>],
+    new_to_old: &[Option<usize>],
+    moved: &[bool],
+) -> Vec<TreeEdit<'a>> {
+    let mut edits = Vec::new();
+
+    for a in 0..old.len() {
+        let old_node = &old.nodes[a];
+
+        let Some(b) = old_to_new[a] else {
+            // Only report the root of each deleted subtree
+            if old_node
+                .parent
+                .is_none_or(|parent| old_to_new[parent].is_some())
+            {
+                edits.push(TreeEdit::Delete(old_node.reference));
+            }
+            continue;
+        };
+        let new_node = &new.nodes[b];
+
+        if old.is_leaf(a) && new.is_leaf(b) && old_node.label != new_node.label {
+            edits.push(TreeEdit::Update {
+                old: old_node.reference,
+                new: new_node.reference,
+            });
+        }
+
+        if moved[a] {
+            edits.push(TreeEdit::Move {
+                old: old_node.reference,
+                new: new_node.reference,
+            });
+        }
+    }
+
+    for b in 0..new.len() {
+        let new_node = &new.nodes[b];
+
+        if new_to_old[b].is_none()
+            && new_node
+                .parent
+                .is_none_or(|parent| new_to_old[parent].is_some())
+        {
+            edits.push(TreeEdit::Insert(new_node.reference));
+        }
+    }
+    edits
+}
+
+/// Compute the hunks for a structural diff between two documents.
+///
+/// This is a convenience wrapper around [`diff_trees`] that is analogous to
+/// [`compute_edit_script`](crate::diff::compute_edit_script).
+pub fn compute_tree_edit_script<'a>(
+    old_tree: &'a TSTree,
+    old_entries: &'a [Entry<'a>],
+    new_tree: &'a TSTree,
+    new_entries: &'a [Entry<'a>],
+) -> Result<RichHunks<'a>> {
+    diff_trees(old_tree, old_entries, new_tree, new_entries).rich_hunks()
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "static-grammar-libs")]
+    use super::*;
+
+    #[cfg(feature = "static-grammar-libs")]
+    use crate::{input_processing::TreeSitterProcessor, test_util::parse_rust};
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn test_identical_trees_have_no_edits() {
+        let text = "fn foo() { let x = 1; }\nfn bar() {}\n";
+        let tree_a = parse_rust(text);
+        let tree_b = parse_rust(text);
+        let processor = TreeSitterProcessor::default();
+        let entries_a = processor.process(&tree_a, text, "rust");
+        let entries_b = processor.process(&tree_b, text, "rust");
+        let tree_diff = diff_trees(&tree_a, &entries_a, &tree_b, &entries_b);
+        assert!(tree_diff.edits().is_empty());
+        assert!(tree_diff.rich_hunks().unwrap().0.is_empty());
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn test_moved_function() {
+        let text_a = "fn foo() { let x = 1; }\nfn bar() { let y = 2; }\n";
+        let text_b = "fn bar() { let y = 2; }\nfn foo() { let x = 1; }\n";
+        let tree_a = parse_rust(text_a);
+        let tree_b = parse_rust(text_b);
+        let processor = TreeSitterProcessor::default();
+        let entries_a = processor.process(&tree_a, text_a, "rust");
+        let entries_b = processor.process(&tree_b, text_b, "rust");
+        let tree_diff = diff_trees(&tree_a, &entries_a, &tree_b, &entries_b);
+        let edits = tree_diff.edits();
+
+        assert_eq!(edits.len(), 1, "{edits:#?}");
+        assert!(matches!(edits[0], TreeEdit::Move { .. }));
+        let hunks = tree_diff.rich_hunks().unwrap();
+        assert_eq!(hunks.0.len(), 1);
+        assert!(matches!(hunks.0[0], crate::diff::RichHunk::Moved(_)));
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn test_updated_identifier() {
+        let text_a = "fn foo() { let x = 1; }\n";
+        let text_b = "fn foo() { let y = 1; }\n";
+        let tree_a = parse_rust(text_a);
+        let tree_b = parse_rust(text_b);
+        let processor = TreeSitterProcessor::default();
+        let entries_a = processor.process(&tree_a, text_a, "rust");
+        let entries_b = processor.process(&tree_b, text_b, "rust");
+        let tree_diff = diff_trees(&tree_a, &entries_a, &tree_b, &entries_b);
+        let edits = tree_diff.edits();
+
+        assert_eq!(edits.len(), 1, "{edits:#?}");
+        let TreeEdit::Update { old, new } = edits[0] else {
+            panic!("Expected an update, got {:?}", edits[0]);
+        };
+        assert_eq!(old.utf8_text(text_a.as_bytes()).unwrap(), "x");
+        assert_eq!(new.utf8_text(text_b.as_bytes()).unwrap(), "y");
+    }
+
+    #[cfg(feature = "static-grammar-libs")]
+    #[test]
+    fn test_inserted_statement() {
+        let text_a = "fn foo() { let x = 1; }\n";
+        let text_b = "fn foo() { let x = 1; let y = 2; }\n";
+        let tree_a = parse_rust(text_a);
+        let tree_b = parse_rust(text_b);
+        let processor = TreeSitterProcessor::default();
+        let entries_a = processor.process(&tree_a, text_a, "rust");
+        let entries_b = processor.process(&tree_b, text_b, "rust");
+        let tree_diff = diff_trees(&tree_a, &entries_a, &tree_b, &entries_b);
+        let edits = tree_diff.edits();
+
+        assert_eq!(edits.len(), 1, "{edits:#?}");
+        let TreeEdit::Insert(node) = edits[0] else {
+            panic!("Expected an insertion, got {:?}", edits[0]);
+        };
+        assert_eq!(node.utf8_text(text_b.as_bytes()).unwrap(), "let y = 2;");
+    }
+}
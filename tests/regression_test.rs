@@ -2,9 +2,9 @@
 mod tests {
     use insta::assert_snapshot;
     use libdiffsitter::{
-        diff::{DocumentType, Hunk, RichHunks, compute_edit_script},
+        diff::{DiffConfig, Hunk, RichHunk, RichHunks, compute_edit_script},
         generate_ast_vector_data,
-        input_processing::{Entry, TreeSitterProcessor},
+        input_processing::{Entry, Granularity, TreeSitterProcessor},
         parse::GrammarConfig,
     };
     use std::path::PathBuf;
@@ -54,8 +54,32 @@ mod tests {
             .0
             .iter()
             .map(|document_type| match document_type {
-                DocumentType::Old(hunk) => format!("Old({})", generate_snapshot_hunk_string(hunk)),
-                DocumentType::New(hunk) => format!("New({})", generate_snapshot_hunk_string(hunk)),
+                RichHunk::Old(hunk) => format!("Old({})", generate_snapshot_hunk_string(hunk)),
+                RichHunk::New(hunk) => format!("New({})", generate_snapshot_hunk_string(hunk)),
+                RichHunk::Changed(hunk) => format!(
+                    "Changed(old={}, new={})",
+                    generate_snapshot_hunk_string(&hunk.old),
+                    generate_snapshot_hunk_string(&hunk.new)
+                ),
+                RichHunk::Moved(hunk) => format!(
+                    "Moved(old=[{}], new=[{}])",
+                    hunk.old
+                        .0
+                        .iter()
+                        .map(generate_snapshot_hunk_string)
+                        .collect::<String>(),
+                    hunk.new
+                        .0
+                        .iter()
+                        .map(generate_snapshot_hunk_string)
+                        .collect::<String>()
+                ),
+                RichHunk::Renamed(rename) => format!(
+                    "Renamed({} -> {}, occurrences={})",
+                    rename.old_name,
+                    rename.new_name,
+                    rename.occurrences.len()
+                ),
             })
             .collect::<Vec<String>>()
             .join("\n")
@@ -100,8 +124,13 @@ mod tests {
         let ast_data_a = generate_ast_vector_data(path_a, None, &config).unwrap();
         let ast_data_b = generate_ast_vector_data(path_b, None, &config).unwrap();
 
+        let granularity = if split_graphemes {
+            Granularity::Grapheme
+        } else {
+            Granularity::Node
+        };
         let processor = TreeSitterProcessor {
-            split_graphemes,
+            granularity,
             strip_whitespace,
             ..Default::default()
         };
@@ -116,7 +145,8 @@ mod tests {
             &ast_data_b.text,
             &ast_data_b.resolved_language,
         );
-        let diff_hunks = compute_edit_script(&diff_vec_a, &diff_vec_b).unwrap();
+        let diff_hunks =
+            compute_edit_script(&diff_vec_a, &diff_vec_b, &DiffConfig::default()).unwrap();
 
         // We have to set the snapshot name manually, otherwise there appear to be threading issues
         // and we end up with more snapshot files than there are tests, which cause